@@ -0,0 +1,59 @@
+//! Search throughput over generated in-memory data. Run with
+//! `cargo bench`, optionally followed by a part of the names of the
+//! benchmarks to run.
+
+use std::io::Cursor;
+
+use binu::{find_in_slice, Searcher};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+const DATA_LEN: usize = 16 << 20;
+const PATTERN: &[u8] = b"\x7fELF\x02\x01";
+
+/// `len` pseudo-random bytes with `pattern` written every `every`
+/// bytes. The same arguments always give the same data.
+fn generate(len: usize, pattern: &[u8], every: usize) -> Vec<u8> {
+    // xorshift64, good enough to not match the pattern by accident
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut data: Vec<u8> = (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect();
+    for offset in (0..len.saturating_sub(pattern.len())).step_by(every) {
+        data[offset..offset + pattern.len()].copy_from_slice(pattern);
+    }
+
+    data
+}
+
+fn search(c: &mut Criterion) {
+    let searcher = Searcher::new(PATTERN);
+    let ignore_case = Searcher::new(PATTERN).ignore_case(true);
+
+    for (density, every) in [("sparse", 1 << 20), ("dense", 64)] {
+        let data = generate(DATA_LEN, PATTERN, every);
+        let mut group = c.benchmark_group(density);
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.sample_size(10);
+        group.bench_function("find_in_slice", |b| {
+            b.iter(|| find_in_slice(black_box(&data), PATTERN).count())
+        });
+        group.bench_function("search_slice", |b| {
+            b.iter(|| searcher.search_slice(black_box(&data)).count())
+        });
+        group.bench_function("search_slice_ignore_case", |b| {
+            b.iter(|| ignore_case.search_slice(black_box(&data)).count())
+        });
+        group.bench_function("search_reader", |b| {
+            b.iter(|| searcher.search(Cursor::new(black_box(&data))).count())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
@@ -1,8 +1,11 @@
-use std::path::PathBuf;
+use std::io::{self, BufRead, IsTerminal};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use indicatif::{ProgressBar, ProgressStyle};
 
-use binu::{GrepConfig, InsertConfig, ReplaceConfig};
+use binu::{CaseFold, Encoding, Error, Offset, GrepConfig, GrepFormat, InsertConfig, ReplaceConfig, FillConfig, PatchConfig, EntropyConfig, SwapConfig, EndianSwapConfig, CutConfig, FileType, HashAlgo, HashConfig, PeekConfig, InsertAnchor, OffsetStyle, OffsetsFormat};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -19,7 +22,7 @@ pub struct Cli {
 pub enum Commands {
     /// Grep-like subcommand
     #[clap(visible_alias("g"))]
-    Grep(GrepArgs),
+    Grep(Box<GrepArgs>),
 
     /// Search and replace on the matches
     #[clap(visible_alias("r"))]
@@ -28,123 +31,1498 @@ pub enum Commands {
     /// Insert bytes at the given offset
     #[clap(visible_alias("i"))]
     Insert(InsertArgs),
+
+    /// Overwrite a range of bytes with a repeated byte
+    #[clap(visible_alias("f"))]
+    Fill(FillArgs),
+
+    /// Apply a list of offset: hexbytes overwrites from a spec file
+    #[clap(visible_alias("p"))]
+    Patch(PatchArgs),
+
+    /// Print the Shannon entropy of a range of bytes
+    #[clap(visible_alias("e"))]
+    Entropy(EntropyArgs),
+
+    /// Exchange two ranges of bytes
+    #[clap(visible_alias("s"))]
+    Swap(SwapArgs),
+
+    /// Reverse the bytes of every fixed-width word
+    EndianSwap(EndianSwapArgs),
+
+    /// Write a range of bytes to a new file
+    #[clap(visible_alias("c"))]
+    Cut(CutArgs),
+
+    /// Print the size of a file or of a range of bytes
+    Size(SizeArgs),
+
+    /// Print the digest of every file, like sha256sum
+    #[clap(visible_alias("h"))]
+    Hash(HashArgs),
+
+    /// Print the bytes at an offset, e.g. to check them before a patch
+    Peek(PeekArgs),
+}
+
+impl Commands {
+    /// Name of the subcommand, used to prefix its error messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commands::Grep(_) => "Grep",
+            Commands::Replace(_) => "Replace",
+            Commands::Insert(_) => "Insert",
+            Commands::Fill(_) => "Fill",
+            Commands::Patch(_) => "Patch",
+            Commands::Entropy(_) => "Entropy",
+            Commands::Swap(_) => "Swap",
+            Commands::EndianSwap(_) => "Endian swap",
+            Commands::Cut(_) => "Cut",
+            Commands::Size(_) => "Size",
+            Commands::Hash(_) => "Hash",
+            Commands::Peek(_) => "Peek",
+        }
+    }
 }
 
 #[derive(Debug, Args)]
+#[command(disable_help_flag = true)]
 pub struct GrepArgs {
+    /// Print help, -h is taken by --no-filename
+    #[arg(long, action = clap::ArgAction::Help)]
+    pub help: Option<bool>,
+
     /// When a directory is provided, recursively operate on all the files
     /// and subdirectories.
     #[arg(short, long)]
     pub recursive: bool,
+
+    /// When operating recursively, skip hidden files and don't descend
+    /// into hidden directories.
+    #[arg(long)]
+    pub no_hidden: bool,
+
+    /// When operating recursively, only search files with a name
+    /// matching the glob. Can be given multiple times
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// When operating recursively, skip files with a name matching the
+    /// glob, even if they're included. Can be given multiple times
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// When operating recursively, descend at most N levels into the
+    /// subdirectories. 0 only searches the files in the given
+    /// directories themselves
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Ignore ASCII case distinctions in the pattern and the data
+    #[arg(short = 'i', long)]
+    pub ignore_case: bool,
+
+    /// Which bytes count as the same letter with --ignore-case. latin1
+    /// also folds the accented Latin-1 letters, e.g. \xc9 and \xe9
+    #[arg(long, value_enum, default_value_t = FoldArg::Ascii)]
+    pub fold: FoldArg,
+
+    /// Interpret the pattern as a byte-oriented regex. Every file is
+    /// read whole into memory in this mode
+    #[arg(long)]
+    pub regex: bool,
+
+    /// Print the results as JSON instead
+    #[arg(long)]
+    pub json: bool,
+
+    /// Show a hexdump of N bytes around each match
+    #[arg(short = 'C', long, value_name = "N")]
+    pub context: Option<usize>,
+
+    /// Show a hexdump of N bytes before each match, overriding the
+    /// bytes before from --context
+    #[arg(short = 'B', long, value_name = "N")]
+    pub before_context: Option<usize>,
+
+    /// Show a hexdump of N bytes after each match, overriding the bytes
+    /// after from --context
+    #[arg(short = 'A', long, value_name = "N")]
+    pub after_context: Option<usize>,
+
+    /// Print a summary with the total number of matches and the number
+    /// of bytes they cover. With --count print the bytes of every file
+    #[arg(long)]
+    pub count_bytes: bool,
+
+    /// Only report matches at offsets that are a multiple of N
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    pub align: Option<u64>,
+
+    /// Only report matches which aren't surrounded by ASCII letters
+    /// or digits
+    #[arg(short = 'w', long)]
+    pub word: bool,
+
+    /// Print offsets as this base address plus the file offset, in
+    /// decimal or with a 0x prefix
+    #[arg(long, default_value = "0", value_parser = parse_address)]
+    pub base: u64,
+
+    /// For every match print the matched bytes in hex
+    #[arg(short = 'o', long)]
+    pub only_matching: bool,
+
+    /// Print how many files were searched and how many of them matched
+    #[arg(long)]
+    pub count_files: bool,
+
+    /// Print every match in this format and nothing else. {file},
+    /// {offset}, {offset_hex}, {len}, {match} and {match_hex} expand to
+    /// the filename, the offset in decimal and hex, the length and the
+    /// matched bytes escaped and in hex. Use {{ and }} for braces and
+    /// escapes like \t and \n, e.g. "{file}\t{offset_hex}\n"
+    #[arg(long, value_parser = parse_format)]
+    pub format: Option<GrepFormat>,
+
+    /// Write the offsets of the matches to this file as they are found,
+    /// instead of printing the results
+    #[arg(long, value_name = "PATH")]
+    pub offsets_to_file: Option<PathBuf>,
+
+    /// How --offsets-to-file writes the offsets: decimal lines, little
+    /// endian u64s, or CSV rows with the filename and the length
+    #[arg(long, value_enum, default_value_t = OffsetsFormatArg::Lines)]
+    pub offsets_format: OffsetsFormatArg,
+
+    /// Save how far the search got to this file every 16 MiB, so an
+    /// interrupted search can be continued with --resume, and the
+    /// matches found so far to PATH.matches. Only works with a single
+    /// literal pattern. Both are removed when the search is done
+    #[arg(long, value_name = "PATH")]
+    pub checkpoint: Option<PathBuf>,
+
+    /// Continue the search from the file and the offset saved in the
+    /// checkpoint, skipping everything before them
+    #[arg(long)]
+    pub resume: bool,
+
+    /// After the results print every pattern with its number of matches
+    /// in all the files, including patterns that weren't found
+    #[arg(long)]
+    pub count_patterns: bool,
+
+    /// Group the results of the files under their directory
+    #[arg(long)]
+    pub group_by_dir: bool,
+
+    /// Print nothing, exit with status 0 if anything matches and 1 if
+    /// nothing does. Stops at the first match
+    #[arg(short = 'q', long)]
+    pub quiet_match: bool,
+
+    /// Print the number of matches in every file instead of the offsets.
+    /// With --count-bytes also the number of bytes they cover. With
+    /// --quiet files without matches are left out
+    #[arg(short = 'c', long)]
+    pub count: bool,
+
+    /// Match every `\n` delimited line on its own, like text grep, and
+    /// print the matching lines with their numbers and the offsets of
+    /// their first matches. Works with literal patterns and --regex
+    #[arg(long)]
+    pub text: bool,
+
+    /// Search the files as one stream, one after another in the given
+    /// order, so matches straddling two files are found too. Print the
+    /// offset in the stream and the files of every match
+    #[arg(long)]
+    pub concat: bool,
+
+    /// List the files which don't contain the pattern instead
+    #[arg(short = 'L', long)]
+    pub invert_file: bool,
+
+    /// With --invert-file, only count the matches lying wholly within
+    /// this range, given as START:LEN
+    #[arg(long, value_parser = parse_range)]
+    pub within: Option<Range<usize>>,
+
+    /// Always read the pattern and the pattern list as hex digits, with
+    /// or without a 0x prefix. By default patterns like 0xdeadbeef are
+    /// read as hex and everything else as a string, where escapes like
+    /// \x7f stand for single bytes
+    #[arg(long, conflicts_with_all = ["string", "regex"])]
+    pub hex: bool,
+
+    /// Always read the patterns as strings, even if they look like 0x
+    /// followed by hex digits or contain \x escapes
+    #[arg(long)]
+    pub string: bool,
+
+    /// Paint the offsets and the matched bytes. With auto only when
+    /// printing to a terminal and not in quiet mode
+    #[arg(long, value_enum, default_value_t = ColorArg::Auto)]
+    pub color: ColorArg,
+
+    /// Search this many files at once. No more than this many files
+    /// are open at the same time
+    #[arg(short, long, value_name = "N", default_value = "1")]
+    pub jobs: usize,
+
+    /// Print the `\n` delimited line around each match, like text grep.
+    /// Bytes which aren't printable ASCII are escaped
+    #[arg(long)]
+    pub line: bool,
+
+    /// Print the first offset of every file and then how far every
+    /// match is from the previous one, e.g. to spot a fixed stride
+    #[arg(long)]
+    pub deltas: bool,
+
+    /// Search the gzip decompressed contents of the files, with the
+    /// offsets counted in the decompressed data. Only available when
+    /// built with the decompress feature
+    #[arg(short = 'z', long)]
+    pub decompress: bool,
+
+    /// Stop searching once N matches were found in all the files
+    /// together, unlike a limit per file
+    #[arg(long, value_name = "N")]
+    pub limit_total: Option<usize>,
+
+    /// Skip the matches directly preceded by this pattern. Read like
+    /// the pattern, but always as literal bytes
+    #[arg(long, value_name = "PATTERN")]
+    pub not_after: Option<String>,
+
+    /// Skip the matches directly followed by this pattern. Read like
+    /// the pattern, but always as literal bytes
+    #[arg(long, value_name = "PATTERN")]
+    pub not_before: Option<String>,
+
+    /// Print the filename before the results of every file. The default
+    /// when more than one file is searched
+    #[arg(short = 'H', long, overrides_with = "no_filename")]
+    pub with_filename: bool,
+
+    /// Never print the filenames. Files without matches are left out
+    #[arg(short = 'h', long, overrides_with = "with_filename")]
+    pub no_filename: bool,
+
+    /// Only search files of this type. A file is considered binary if
+    /// there's a NUL byte in its first few KiB
+    #[arg(long = "type", value_enum, default_value_t = FileTypeArg::All)]
+    pub file_type: FileTypeArg,
+
+    /// Transcode the pattern into this encoding before searching, e.g.
+    /// to find the UTF-16 strings of Windows binaries
+    #[arg(long, value_enum, default_value_t = EncodingArg::Ascii)]
+    pub encoding: EncodingArg,
     
+    /// Read the raw bytes of this file and use them as the pattern
+    #[arg(long, value_name = "PATH")]
+    pub pattern_file: Option<PathBuf>,
+
+    /// Also search for every pattern listed in this file, one per line.
+    /// Empty lines and lines starting with # are skipped
+    #[arg(long, value_name = "PATH")]
+    pub pattern_list: Option<PathBuf>,
+
+    /// Also search the files listed in this file, one per line. Empty
+    /// lines and lines starting with # are skipped
+    #[arg(long, value_name = "PATH")]
+    pub file_list: Option<PathBuf>,
+
     /// Pattern to search for
-    pub pattern: String,
+    #[clap(required_unless_present_any = ["pattern_file", "pattern_list"])]
+    pub pattern: Option<String>,
 
     /// Files to search for
-    #[clap(required = true, num_args = 1..)]
+    #[clap(
+        required_unless_present_any = ["pattern_file", "pattern_list", "file_list"],
+        num_args = 1..,
+    )]
     pub filenames: Vec<PathBuf>,
 }
 
+impl GrepArgs {
+    /// Build the config of the search. The builder decides which options
+    /// can be used together, its errors are usage errors.
+    fn config(&self, quiet: bool) -> Result<GrepConfig, Error> {
+        let mut builder = GrepConfig::builder()
+            .quiet(quiet)
+            .recursive(self.recursive)
+            .skip_hidden(self.no_hidden)
+            .case_insensitive(self.ignore_case)
+            .fold(self.fold.into())
+            .regex(self.regex)
+            .json(self.json)
+            .count_bytes(self.count_bytes)
+            .file_type(self.file_type.into())
+            .encoding(self.encoding.into())
+            .only_matching(self.only_matching)
+            .word(self.word)
+            .base(self.base)
+            .count_files(self.count_files)
+            .count_patterns(self.count_patterns)
+            .count(self.count)
+            .group_by_dir(self.group_by_dir)
+            .invert_file(self.invert_file)
+            .quiet_match(self.quiet_match)
+            .concat(self.concat)
+            .color(self.color.enabled(io::stdout().is_terminal() && !quiet))
+            .jobs(self.jobs)
+            .line(self.line)
+            .text(self.text)
+            .deltas(self.deltas)
+            .decompress(self.decompress)
+            .include(self.include.clone())
+            .exclude(self.exclude.clone())
+            .resume(self.resume)
+            .offsets_format(self.offsets_format.into());
+        if let Some(context) = self.context {
+            builder = builder.context(context);
+        }
+        if let Some(before) = self.before_context {
+            builder = builder.before_context(before);
+        }
+        if let Some(after) = self.after_context {
+            builder = builder.after_context(after);
+        }
+        if let Some(align) = self.align {
+            builder = builder.align(align);
+        }
+        if let Some(within) = &self.within {
+            builder = builder.within(within.clone());
+        }
+        if let Some(limit_total) = self.limit_total {
+            builder = builder.limit_total(limit_total);
+        }
+        if let Some(pattern) = &self.not_after {
+            builder = builder.not_after(decode_pattern(pattern, self.hex, self.string)?);
+        }
+        if let Some(pattern) = &self.not_before {
+            builder = builder.not_before(decode_pattern(pattern, self.hex, self.string)?);
+        }
+        if self.with_filename || self.no_filename {
+            builder = builder.with_filename(self.with_filename);
+        }
+        if let Some(max_depth) = self.max_depth {
+            builder = builder.max_depth(max_depth);
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            builder = builder.checkpoint(checkpoint.clone());
+        }
+        if let Some(format) = &self.format {
+            builder = builder.format(format.clone());
+        }
+        if let Some(offsets_file) = &self.offsets_to_file {
+            builder = builder.offsets_file(offsets_file.clone());
+        }
+        builder.build().map_err(|e| Error::Usage(e.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum FileTypeArg {
+    All,
+    Binary,
+    Text,
+}
+
+impl From<FileTypeArg> for FileType {
+    fn from(file_type: FileTypeArg) -> Self {
+        match file_type {
+            FileTypeArg::All => FileType::All,
+            FileTypeArg::Binary => FileType::Binary,
+            FileTypeArg::Text => FileType::Text,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FoldArg {
+    Ascii,
+    Latin1,
+}
+
+impl From<FoldArg> for CaseFold {
+    fn from(fold: FoldArg) -> Self {
+        match fold {
+            FoldArg::Ascii => CaseFold::Ascii,
+            FoldArg::Latin1 => CaseFold::Latin1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ColorArg {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorArg {
+    /// Whether to paint the output. `Auto` only paints for a terminal.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        match self {
+            ColorArg::Auto => is_terminal,
+            ColorArg::Always => true,
+            ColorArg::Never => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum EncodingArg {
+    Ascii,
+    Utf16le,
+    Utf16be,
+}
+
+impl From<EncodingArg> for Encoding {
+    fn from(encoding: EncodingArg) -> Self {
+        match encoding {
+            EncodingArg::Ascii => Encoding::Ascii,
+            EncodingArg::Utf16le => Encoding::Utf16Le,
+            EncodingArg::Utf16be => Encoding::Utf16Be,
+        }
+    }
+}
+
 #[derive(Debug, Args)]
+#[command(group(ArgGroup::new("occurrences").args(["nth", "replace_all"])))]
 pub struct ReplaceArgs {
     /// Pattern to replace
     pub pattern: String,
 
-    /// Replacing string
-    pub replace_with: String,
+    /// Replacing string. Leave it out when using --replace-file
+    #[clap(required_unless_present = "replace_file")]
+    pub replace_with: Option<String>,
 
-    /// When replacing only one, which occurrence to replace, counting from 0
-    #[arg(short, long, default_value_t = 0)]
-    pub nth: usize,
+    /// Read the replacing bytes from this file, or from stdin if it's `-`
+    #[arg(long, value_name = "PATH")]
+    pub replace_file: Option<PathBuf>,
+
+    /// Always read the pattern and the replacing string as hex digits,
+    /// with or without a 0x prefix. By default strings like 0xdeadbeef
+    /// are read as hex and everything else as a string, where escapes
+    /// like \x7f stand for single bytes
+    #[arg(long, conflicts_with_all = ["string", "regex"])]
+    pub hex: bool,
+
+    /// Always read the pattern and the replacing string as strings, even
+    /// if they look like 0x followed by hex digits or contain \x escapes
+    #[arg(long)]
+    pub string: bool,
+
+    /// When replacing only one, which occurrence to replace, counting
+    /// from 0. The first one by default
+    #[arg(short, long)]
+    pub nth: Option<usize>,
+
+    /// Count --nth backward from the last occurrence
+    #[arg(long, conflicts_with = "replace_all")]
+    pub from_end: bool,
 
     /// Replace all occurences
     #[arg(long)]
     pub replace_all: bool,
 
+    /// Replace only the first K occurences
+    #[arg(long, value_name = "K", conflicts_with_all = ["nth", "from_end", "replace_all"])]
+    pub limit: Option<usize>,
+
     /// Allow using longer replace strings than the matched patterns.
     /// Warning! Will result in a changed output binary size so it may
     /// cause changing of offsets, making some binary formats unreadable
     #[arg(long)]
     pub allow_length_change: bool,
 
+    /// Change the size of files which look like ELF or PE executables
+    /// anyway, even though it most likely breaks them
+    #[arg(long)]
+    pub force: bool,
+
+    /// Print the replaced offsets as JSON instead of the success message
+    #[arg(long)]
+    pub json: bool,
+
+    /// Print only the replaced offsets, one per line, instead of the
+    /// success message
+    #[arg(
+        long,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "decimal",
+        value_name = "STYLE",
+        conflicts_with_all = ["json", "recursive"],
+    )]
+    pub print_offsets: Option<OffsetStyleArg>,
+
+    /// End the offsets printed by --print-offsets with a NUL byte
+    /// instead of a newline
+    #[arg(long, requires = "print_offsets")]
+    pub print0: bool,
+
+    /// Re-read the output after replacing and check that it has the
+    /// replacement at every replaced offset
+    #[arg(long)]
+    pub verify: bool,
+
+    /// Expand %offset% and %size% in the replacing string to the offset
+    /// of the match and the size of the input file, in decimal. Use
+    /// e.g. %size:4% or %size:4be% for a 4 byte little or big endian
+    /// integer and %% for a literal %
+    #[arg(long)]
+    pub template: bool,
+
     /// When the replacing byte string is shorter than the replaced ones,
     /// fill the rest with this byte
     #[arg(long, default_value_t = 0)]
     pub fill_byte: u8,
 
-    /// File to replace
-    #[clap(required = true)]
-    pub input_filename: PathBuf,
+    /// Interpret the pattern as a byte-oriented regex. The replacing
+    /// string can then refer to capture groups with `$1` or `${name}`.
+    /// Implies --allow-length-change
+    #[arg(long)]
+    pub regex: bool,
 
-    /// Output file
-    #[clap(required = true)]
-    pub output_filename: PathBuf,
+    /// Ask for confirmation before every replacement, showing the
+    /// bytes around the match. Stdin has to be a terminal
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// Swap the pattern and the replacing string: every match of either
+    /// is replaced with the other, without the second replacement
+    /// undoing the first. They have to be equally long unless
+    /// --allow-length-change is set
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "nth", "from_end", "replace_all", "limit", "regex", "interactive", "template",
+        ],
+    )]
+    pub swap: bool,
+
+    /// Allow the output file to be the same as the input file. The
+    /// result is written to a temporary file first
+    #[arg(long)]
+    pub in_place: bool,
+
+    /// Don't overwrite an output file that already exists. When stdin
+    /// is a terminal ask whether to overwrite it instead of failing
+    #[arg(long, conflicts_with = "in_place")]
+    pub no_clobber: bool,
+
+    /// Replace in place in every file under the given directory. Files
+    /// without a match are left untouched. Requires --in-place or
+    /// --output-dir
+    #[arg(short, long, conflicts_with = "interactive")]
+    pub recursive: bool,
+
+    /// With --recursive, write every file under this directory at the
+    /// same relative path instead of replacing in place, creating the
+    /// directories on the way
+    #[arg(long, value_name = "DIR", requires = "recursive", conflicts_with = "in_place")]
+    pub output_dir: Option<PathBuf>,
+
+    /// File to replace, or `-` for stdin. A directory with --recursive
+    #[clap(required_unless_present = "replace_file")]
+    pub input_filename: Option<PathBuf>,
+
+    /// Output file, or `-` for stdout. Left out with --recursive
+    #[clap(required_unless_present_any = ["replace_file", "recursive"])]
+    pub output_filename: Option<PathBuf>,
 }
 
 #[derive(Debug, Args)]
+#[command(group(ArgGroup::new("pattern_anchor").args(["after_pattern", "before_pattern"])))]
 pub struct InsertArgs {
     /// What to insert
     pub to_insert: String,
     
-    /// At what offset. Starting from 0
-    pub offset: usize,
+    /// At what offset. Starting from 0, or counted back from the end
+    /// of the file when negative, so -0 appends. Can be a comma
+    /// separated list of offsets, all of them counted in the original
+    /// file. With --after-pattern or --before-pattern counted from the
+    /// match instead, so 0 is right next to it
+    #[arg(value_parser = parse_offsets, allow_hyphen_values = true)]
+    pub offsets: Offsets,
 
     /// To which file to insert
     pub input_filename: PathBuf,
 
+    /// Allow the output file to be the same as the input file. The
+    /// result is written to a temporary file first
+    #[arg(long)]
+    pub in_place: bool,
+
+    /// Don't overwrite an output file that already exists. When stdin
+    /// is a terminal ask whether to overwrite it instead of failing
+    #[arg(short, long, conflicts_with = "in_place")]
+    pub no_clobber: bool,
+
+    /// Print the offsets as JSON instead of the success message
+    #[arg(long)]
+    pub json: bool,
+
+    /// Expand %offset% and %size% in the inserted string to the offset
+    /// it's inserted at and the size of the input file, in decimal. Use
+    /// e.g. %size:4% or %size:4be% for a 4 byte little or big endian
+    /// integer and %% for a literal %
+    #[arg(long)]
+    pub template: bool,
+
+    /// Only check the offsets and print the size the output would have,
+    /// without writing anything
+    #[arg(long, conflicts_with = "json")]
+    pub dry_run: bool,
+
+    /// Fail when there's nothing to insert, instead of copying the
+    /// input as it is
+    #[arg(long)]
+    pub reject_empty: bool,
+
+    /// Count the offsets from right after a match of this pattern. Read
+    /// like a grep pattern, so 0x hex and \x escapes work
+    #[arg(long, value_name = "PATTERN")]
+    pub after_pattern: Option<String>,
+
+    /// Count the offsets from the start of a match of this pattern
+    #[arg(long, value_name = "PATTERN")]
+    pub before_pattern: Option<String>,
+
+    /// Which match of the pattern to count from, starting from 0
+    #[arg(long, default_value = "0", requires = "pattern_anchor")]
+    pub nth: usize,
+
+    /// Output file, or `-` for stdout
+    #[clap(required = true)]
+    pub output_filename: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct FillArgs {
+    /// Byte to fill the range with, in decimal or with a 0x prefix
+    #[arg(short, long, default_value = "0", value_parser = parse_byte)]
+    pub byte: u8,
+
+    /// Where the range starts. Starting from 0, or counted back from
+    /// the end of the file when negative
+    #[arg(short, long, allow_hyphen_values = true)]
+    pub start: Offset,
+
+    /// How many bytes to fill
+    #[arg(short, long)]
+    pub len: usize,
+
+    /// File to fill
+    pub input_filename: PathBuf,
+
+    /// Output file
+    #[clap(required = true)]
+    pub output_filename: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct PatchArgs {
+    /// File with one `offset: hexbytes` overwrite per line, e.g.
+    /// `0x10: 90 90`. Lines starting with # are skipped
+    #[arg(long, value_name = "PATH")]
+    pub spec: PathBuf,
+
+    /// File to patch
+    pub input_filename: PathBuf,
+
+    /// Output file
+    #[clap(required = true)]
+    pub output_filename: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct EntropyArgs {
+    /// Where the range starts. Starting from 0, or counted back from
+    /// the end of the file when negative
+    #[arg(short, long, default_value = "0", allow_hyphen_values = true)]
+    pub start: Offset,
+
+    /// Where the range ends, exclusive. Defaults to the end of the file
+    #[arg(short, long, allow_hyphen_values = true)]
+    pub end: Option<Offset>,
+
+    /// Also print how many times every byte occurs
+    #[arg(long)]
+    pub histogram: bool,
+
+    /// File to measure
+    pub input_filename: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct SwapArgs {
+    /// First range, as START:LEN in decimal or with a 0x prefix
+    #[arg(short, long, value_parser = parse_range)]
+    pub a: Range<usize>,
+
+    /// Second range, as START:LEN. Can have a different length than the
+    /// first one, the bytes between them are shifted then
+    #[arg(short, long, value_parser = parse_range)]
+    pub b: Range<usize>,
+
+    /// File to swap the ranges in
+    pub input_filename: PathBuf,
+
     /// Output file
     #[clap(required = true)]
     pub output_filename: PathBuf,
 }
 
+#[derive(Debug, Args)]
+pub struct EndianSwapArgs {
+    /// Width of the words in bytes
+    #[arg(short, long, value_parser = parse_width)]
+    pub width: usize,
+
+    /// Where the range of words starts. Starting from 0, or counted
+    /// back from the end of the file when negative
+    #[arg(short, long, default_value = "0", allow_hyphen_values = true)]
+    pub start: Offset,
+
+    /// Where the range of words ends, exclusive. Defaults to the end of
+    /// the file
+    #[arg(short, long, allow_hyphen_values = true)]
+    pub end: Option<Offset>,
+
+    /// Pad the last word with zeros when the range isn't made of whole
+    /// words, instead of failing
+    #[arg(long)]
+    pub pad: bool,
+
+    /// File to swap the words in
+    pub input_filename: PathBuf,
+
+    /// Output file
+    #[clap(required = true)]
+    pub output_filename: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct CutArgs {
+    /// Where the range starts. Starting from 0, or counted back from
+    /// the end of the file when negative
+    #[arg(short, long, allow_hyphen_values = true)]
+    pub start: Offset,
+
+    /// How many bytes to cut
+    #[arg(short, long)]
+    pub len: usize,
+
+    /// File to cut from
+    pub input_filename: PathBuf,
+
+    /// Output file
+    #[clap(required = true)]
+    pub output_filename: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct SizeArgs {
+    /// Where the range starts. Starting from 0, or counted back from
+    /// the end of the file when negative
+    #[arg(short, long, default_value = "0", allow_hyphen_values = true)]
+    pub start: Offset,
+
+    /// Where the range ends, exclusive. Defaults to the end of the file
+    #[arg(short, long, allow_hyphen_values = true)]
+    pub end: Option<Offset>,
+
+    /// File to measure
+    pub input_filename: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct PeekArgs {
+    /// Where the bytes start. Starting from 0, or counted back from the
+    /// end of the file when negative
+    #[arg(short, long, allow_hyphen_values = true)]
+    pub offset: Offset,
+
+    /// How many bytes to print
+    #[arg(short, long)]
+    pub len: usize,
+
+    /// Print a hexdump with the bytes as ASCII next to the hex digits
+    #[arg(short, long)]
+    pub ascii: bool,
+
+    /// File to peek into
+    pub input_filename: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct HashArgs {
+    /// Digest to compute
+    #[arg(long, value_enum, default_value_t = HashAlgoArg::Sha256)]
+    pub algo: HashAlgoArg,
+
+    /// When a directory is provided, hash all the files under it. The
+    /// output is sorted by path
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// When hashing recursively, skip hidden files and don't descend
+    /// into hidden directories
+    #[arg(long, requires = "recursive")]
+    pub no_hidden: bool,
+
+    /// When hashing recursively, hash this many files at once
+    #[arg(short, long, value_name = "N", default_value = "1", requires = "recursive")]
+    pub jobs: usize,
+
+    /// Files to hash
+    #[clap(required = true, num_args = 1..)]
+    pub filenames: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OffsetsFormatArg {
+    Lines,
+    Binary,
+    Csv,
+}
+
+impl From<OffsetsFormatArg> for OffsetsFormat {
+    fn from(format: OffsetsFormatArg) -> Self {
+        match format {
+            OffsetsFormatArg::Lines => OffsetsFormat::Lines,
+            OffsetsFormatArg::Binary => OffsetsFormat::Binary,
+            OffsetsFormatArg::Csv => OffsetsFormat::Csv,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OffsetStyleArg {
+    Decimal,
+    Hex,
+}
+
+impl From<OffsetStyleArg> for OffsetStyle {
+    fn from(style: OffsetStyleArg) -> Self {
+        match style {
+            OffsetStyleArg::Decimal => OffsetStyle::Decimal,
+            OffsetStyleArg::Hex => OffsetStyle::Hex,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HashAlgoArg {
+    Sha256,
+    Sha512,
+}
+
+impl From<HashAlgoArg> for HashAlgo {
+    fn from(algo: HashAlgoArg) -> Self {
+        match algo {
+            HashAlgoArg::Sha256 => HashAlgo::Sha256,
+            HashAlgoArg::Sha512 => HashAlgo::Sha512,
+        }
+    }
+}
+
+/// Ask whether to overwrite `output` anyway when --no-clobber is set,
+/// the output exists and stdin is a terminal. Return whether the
+/// output still has to be guarded.
+fn keep_no_clobber(no_clobber: bool, output: &Path) -> Result<bool, Error> {
+    let stdin = io::stdin();
+    if !no_clobber || !stdin.is_terminal() || binu::ensure_no_clobber(output).is_ok() {
+        return Ok(no_clobber);
+    }
+    eprint!("Output file {} already exists, overwrite it? [y/N] ", output.display());
+    let mut answer = String::new();
+    stdin.lock().read_line(&mut answer)?;
+
+    Ok(!matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Turn a pattern given on the command line into bytes. With `hex` it
+/// has to be hex digits, with `string` it's taken as is. Otherwise it's
+/// read as hex if it's 0x followed by an even number of hex digits, or
+/// as text with `\xHH` escapes like `\x7fELF`.
+fn decode_pattern(pattern: &str, hex: bool, string: bool) -> Result<Vec<u8>, Error> {
+    let digits = pattern.strip_prefix("0x").or_else(|| pattern.strip_prefix("0X"));
+    if hex {
+        return parse_hex(digits.unwrap_or(pattern))
+            .ok_or_else(|| Error::Usage(format!("{} isn't a valid hex pattern", pattern)));
+    }
+    if !string {
+        if let Some(bytes) = digits.and_then(parse_hex) {
+            return Ok(bytes);
+        }
+        return Ok(binu::unescape(pattern)?);
+    }
+
+    Ok(pattern.as_bytes().to_vec())
+}
+
+/// Parse a non-empty, even number of hex digits into bytes
+fn parse_hex(digits: &str) -> Option<Vec<u8>> {
+    if digits.is_empty() || !digits.len().is_multiple_of(2)
+        || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Parse a byte given either in decimal or in hex with a 0x prefix
+fn parse_byte(s: &str) -> Result<u8, String> {
+    let res = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    res.map_err(|e| e.to_string())
+}
+
+/// Parse an address given either in decimal or in hex with a 0x prefix
+fn parse_address(s: &str) -> Result<u64, String> {
+    let res = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    };
+    res.map_err(|e| e.to_string())
+}
+
+/// Parse a word width, which can only be 2, 4 or 8 bytes
+fn parse_width(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(width @ (2 | 4 | 8)) => Ok(width),
+        _ => Err("expected 2, 4 or 8".to_string()),
+    }
+}
+
+/// Parse a range given as START:LEN
+fn parse_range(s: &str) -> Result<Range<usize>, String> {
+    let (start, len) = s.split_once(':').ok_or("expected START:LEN")?;
+    let start = parse_address(start)? as usize;
+    let len = parse_address(len)? as usize;
+    let end = start.checked_add(len).ok_or("range is too big")?;
+
+    Ok(start..end)
+}
+
+/// Parse a grep output format, so unknown tokens are reported before
+/// anything is searched
+fn parse_format(s: &str) -> Result<GrepFormat, String> {
+    GrepFormat::parse(s).map_err(|e| e.to_string())
+}
+
+/// A comma separated list of offsets given as one argument. Wrapped,
+/// so clap parses the whole list at once instead of treating a `Vec`
+/// field as an argument taking many values.
+#[derive(Debug, Clone)]
+pub struct Offsets(pub Vec<Offset>);
+
+/// Parse a comma separated list of offsets
+fn parse_offsets(s: &str) -> Result<Offsets, String> {
+    s.split(',')
+        .map(|offset| offset.trim().parse().map_err(|e| format!("{}: {}", offset, e)))
+        .collect::<Result<_, _>>()
+        .map(Offsets)
+}
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_NO_MATCH: i32 = 1;
+pub const EXIT_ERROR: i32 = 2;
+
+/// Create a progress bar drawn on stderr. When `hidden` is set or
+/// stderr isn't a terminal, nothing is drawn.
+fn progress_bar(hidden: bool) -> ProgressBar {
+    if hidden || !io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template("{bar:40} {bytes}/{total_bytes} ({eta})")
+        .expect("Progress bar template is valid");
+    ProgressBar::new(0).with_style(style)
+}
+
 impl Cli {
-    pub fn exec(&self) {
+    /// Run the chosen subcommand. Return the exit status of the
+    /// process, like grep: `EXIT_SUCCESS` when something matched or
+    /// the command succeeded and `EXIT_NO_MATCH` when grep found
+    /// nothing. Errors are returned to the caller, which should exit
+    /// with `EXIT_ERROR`.
+    pub fn exec(&self) -> Result<i32, Error> {
+        let interactive = matches!(&self.command, Commands::Replace(args) if args.interactive);
+        let bar = progress_bar(self.quiet || interactive);
+        let mut progress = |done, total| {
+            bar.set_length(total);
+            bar.set_position(done);
+            // Get out of the way before the command prints its results
+            if done == total {
+                bar.finish_and_clear();
+            }
+        };
+
+        let res = self.run(&mut progress);
+        bar.finish_and_clear();
+
+        res
+    }
+
+    fn run(&self, progress: &mut dyn FnMut(u64, u64)) -> Result<i32, Error> {
         match &self.command {
             Commands::Grep(grep_args) => {
-                let grep_config = GrepConfig {
-                    quiet: self.quiet,
-                    recursive: grep_args.recursive,
-                };
-                binu::grep_command(
-                    grep_args.pattern.as_bytes(),
-                    &grep_args.filenames,
-                    &grep_config,
-                ).unwrap_or_else(|e| {
-                    eprintln!("Grep encountered error: {}", e);
-                });
+                let grep_config = grep_args.config(self.quiet)?;
+                // Regexes are never read as hex, their syntax has its own escapes
+                let string = grep_args.string || grep_args.regex;
+                // With a pattern file or list every positional argument is
+                // a filename
+                let mut patterns = Vec::new();
+                let mut filenames = Vec::new();
+                if let Some(pattern_file) = &grep_args.pattern_file {
+                    patterns.push(binu::read_pattern_file(pattern_file)?);
+                }
+                if let Some(pattern_list) = &grep_args.pattern_list {
+                    let list = binu::read_list_file(pattern_list)?;
+                    for pattern in list.iter() {
+                        patterns.push(decode_pattern(pattern, grep_args.hex, string)?);
+                    }
+                }
+                match &grep_args.pattern {
+                    Some(pattern) if grep_args.pattern_file.is_some()
+                        || grep_args.pattern_list.is_some() => {
+                        filenames.push(PathBuf::from(pattern));
+                    }
+                    Some(pattern) => {
+                        patterns.push(decode_pattern(pattern, grep_args.hex, string)?);
+                    }
+                    None => {}
+                }
+                filenames.extend(grep_args.filenames.iter().cloned());
+                if let Some(file_list) = &grep_args.file_list {
+                    let list = binu::read_list_file(file_list)?;
+                    filenames.extend(list.into_iter().map(PathBuf::from));
+                }
+                if patterns.is_empty() {
+                    return Err(Error::Usage("no patterns to search for".to_string()));
+                }
+                if filenames.is_empty() {
+                    return Err(Error::Usage("no files to search for".to_string()));
+                }
+                let found = if grep_args.invert_file {
+                    binu::absent_command(&patterns, &filenames, &grep_config)
+                        .map(|absent_files| absent_files > 0)
+                } else if grep_args.quiet_match {
+                    binu::contains_command(&patterns, &filenames, &grep_config)
+                } else if grep_args.text {
+                    binu::text_command(&patterns, &filenames, &grep_config)
+                        .map(|lines| lines > 0)
+                } else if grep_args.concat {
+                    binu::concat_command(&patterns, &filenames, &grep_config)
+                        .map(|count| count > 0)
+                } else if grep_args.count {
+                    binu::count_command(&patterns, &filenames, &grep_config)
+                        .map(|count| count > 0)
+                } else {
+                    binu::grep_command(
+                        &patterns,
+                        &filenames,
+                        &grep_config,
+                        progress,
+                    ).map(|summary| summary.matches > 0)
+                }?;
+                Ok(if found { EXIT_SUCCESS } else { EXIT_NO_MATCH })
             }
             Commands::Replace(replace_args) => {
+                let mut builder = ReplaceConfig::builder()
+                    .quiet(self.quiet)
+                    .from_end(replace_args.from_end)
+                    .replace_all(replace_args.replace_all)
+                    .fill_byte(replace_args.fill_byte)
+                    .allow_length_change(replace_args.allow_length_change)
+                    .regex(replace_args.regex)
+                    .interactive(replace_args.interactive)
+                    .in_place(replace_args.in_place)
+                    .force(replace_args.force)
+                    .json(replace_args.json)
+                    .recursive(replace_args.recursive)
+                    .template(replace_args.template)
+                    .verify(replace_args.verify)
+                    .swap(replace_args.swap)
+                    .print0(replace_args.print0)
+                    .no_clobber(replace_args.no_clobber);
+                if let Some(nth) = replace_args.nth {
+                    builder = builder.nth(nth);
+                }
+                if let Some(limit) = replace_args.limit {
+                    builder = builder.limit(limit);
+                }
+                if let Some(output_dir) = &replace_args.output_dir {
+                    builder = builder.output_dir(output_dir.clone());
+                }
+                if let Some(style) = replace_args.print_offsets {
+                    builder = builder.print_offsets(style.into());
+                }
+                let replace_config = builder.build().map_err(|e| Error::Usage(e.to_string()))?;
+                let string = replace_args.string || replace_args.regex;
+                if replace_args.recursive {
+                    if !replace_args.in_place && replace_args.output_dir.is_none() {
+                        return Err(Error::Usage(
+                            "--recursive needs --in-place or --output-dir".to_string(),
+                        ));
+                    }
+                    let (replace_with, directory) = match (
+                        &replace_args.replace_file,
+                        &replace_args.replace_with,
+                        &replace_args.input_filename,
+                        &replace_args.output_filename,
+                    ) {
+                        (Some(replace_file), Some(directory), None, None) => (
+                            binu::read_replacement(replace_file)?,
+                            PathBuf::from(directory),
+                        ),
+                        (None, Some(replace_with), Some(directory), None) => (
+                            decode_pattern(replace_with, replace_args.hex, string)?,
+                            directory.clone(),
+                        ),
+                        _ => {
+                            return Err(Error::Usage(
+                                "expected PATTERN, REPLACE_WITH (unless --replace-file is used) \
+                                 and DIRECTORY".to_string(),
+                            ));
+                        }
+                    };
+                    binu::replace_recursive_command(
+                        &decode_pattern(&replace_args.pattern, replace_args.hex, string)?,
+                        &replace_with,
+                        &[directory],
+                        &replace_config,
+                        progress,
+                    )?;
+                    return Ok(EXIT_SUCCESS);
+                }
+                // With a replace file the positional arguments shift by one,
+                // the replacing string is left out
+                let (replace_with, input_filename, output_filename) = match (
+                    &replace_args.replace_file,
+                    &replace_args.replace_with,
+                    &replace_args.input_filename,
+                    &replace_args.output_filename,
+                ) {
+                    (Some(replace_file), Some(input_filename), _, None)
+                        if replace_file.as_os_str() == "-" && input_filename == "-" => {
+                        return Err(Error::Usage(
+                            "stdin can't be both the replacement and the input".to_string(),
+                        ));
+                    }
+                    (Some(replace_file), Some(input_filename), Some(output_filename), None) => (
+                        binu::read_replacement(replace_file)?,
+                        PathBuf::from(input_filename),
+                        output_filename.clone(),
+                    ),
+                    (None, Some(replace_with), Some(input_filename), Some(output_filename)) => (
+                        decode_pattern(replace_with, replace_args.hex, string)?,
+                        input_filename.clone(),
+                        output_filename.clone(),
+                    ),
+                    _ => {
+                        return Err(Error::Usage(
+                            "expected PATTERN, REPLACE_WITH (unless --replace-file is used), \
+                             INPUT_FILENAME and OUTPUT_FILENAME".to_string(),
+                        ));
+                    }
+                };
                 let replace_config = ReplaceConfig {
-                    quiet: self.quiet,
-                    nth: replace_args.nth,
-                    replace_all: replace_args.replace_all,
-                    fill_byte: replace_args.fill_byte,
-                    allow_length_change: replace_args.allow_length_change,
+                    no_clobber: keep_no_clobber(replace_config.no_clobber, &output_filename)?,
+                    ..replace_config
                 };
                 binu::replace_command(
-                    replace_args.pattern.as_bytes(),
-                    replace_args.replace_with.as_bytes(),
-                    &replace_args.input_filename,
-                    &replace_args.output_filename,
+                    &decode_pattern(&replace_args.pattern, replace_args.hex, string)?,
+                    &replace_with,
+                    &input_filename,
+                    &output_filename,
                     &replace_config,
-                ).unwrap_or_else(|e| {
-                    eprintln!("Replace encountered error: {}", e);
-                });
+                    progress,
+                )?;
+                Ok(EXIT_SUCCESS)
             }
             Commands::Insert(insert_args) => {
                 let insert_config = InsertConfig {
                     quiet: self.quiet,
+                    in_place: insert_args.in_place,
+                    json: insert_args.json,
+                    dry_run: insert_args.dry_run,
+                    template: insert_args.template,
+                    reject_empty: insert_args.reject_empty,
+                    anchor: match (&insert_args.after_pattern, &insert_args.before_pattern) {
+                        (Some(pattern), _) => {
+                            Some(InsertAnchor::After(decode_pattern(pattern, false, false)?))
+                        }
+                        (_, Some(pattern)) => {
+                            Some(InsertAnchor::Before(decode_pattern(pattern, false, false)?))
+                        }
+                        _ => None,
+                    },
+                    nth: insert_args.nth,
+                    no_clobber: keep_no_clobber(insert_args.no_clobber, &insert_args.output_filename)?,
                 };
                 binu::insert_command(
                     insert_args.to_insert.as_bytes(),
-                    insert_args.offset,
+                    &insert_args.offsets.0,
                     &insert_args.input_filename,
                     &insert_args.output_filename,
                     &insert_config,
-                ).unwrap_or_else(|e| {
-                    eprintln!("Insert encountered error: {}", e);
-                });
+                    progress,
+                )?;
+                Ok(EXIT_SUCCESS)
+            }
+            Commands::Fill(fill_args) => {
+                let fill_config = FillConfig {
+                    quiet: self.quiet,
+                };
+                binu::fill_command(
+                    fill_args.byte,
+                    fill_args.start,
+                    fill_args.len,
+                    &fill_args.input_filename,
+                    &fill_args.output_filename,
+                    &fill_config,
+                    progress,
+                )?;
+                Ok(EXIT_SUCCESS)
+            }
+            Commands::Patch(patch_args) => {
+                let patch_config = PatchConfig {
+                    quiet: self.quiet,
+                };
+                binu::patch_command(
+                    &patch_args.spec,
+                    &patch_args.input_filename,
+                    &patch_args.output_filename,
+                    &patch_config,
+                    progress,
+                )?;
+                Ok(EXIT_SUCCESS)
+            }
+            Commands::Entropy(entropy_args) => {
+                let entropy_config = EntropyConfig {
+                    histogram: entropy_args.histogram,
+                };
+                binu::entropy_command(
+                    &entropy_args.input_filename,
+                    entropy_args.start,
+                    entropy_args.end,
+                    &entropy_config,
+                    progress,
+                )?;
+                Ok(EXIT_SUCCESS)
+            }
+            Commands::Swap(swap_args) => {
+                let swap_config = SwapConfig {
+                    quiet: self.quiet,
+                };
+                binu::swap_command(
+                    swap_args.a.clone(),
+                    swap_args.b.clone(),
+                    &swap_args.input_filename,
+                    &swap_args.output_filename,
+                    &swap_config,
+                    progress,
+                )?;
+                Ok(EXIT_SUCCESS)
+            }
+            Commands::EndianSwap(endian_swap_args) => {
+                let endian_swap_config = EndianSwapConfig {
+                    quiet: self.quiet,
+                    pad: endian_swap_args.pad,
+                };
+                binu::endian_swap_command(
+                    endian_swap_args.width,
+                    endian_swap_args.start,
+                    endian_swap_args.end,
+                    &endian_swap_args.input_filename,
+                    &endian_swap_args.output_filename,
+                    &endian_swap_config,
+                    progress,
+                )?;
+                Ok(EXIT_SUCCESS)
+            }
+            Commands::Cut(cut_args) => {
+                let cut_config = CutConfig {
+                    quiet: self.quiet,
+                };
+                binu::cut_command(
+                    cut_args.start,
+                    cut_args.len,
+                    &cut_args.input_filename,
+                    &cut_args.output_filename,
+                    &cut_config,
+                    progress,
+                )?;
+                Ok(EXIT_SUCCESS)
+            }
+            Commands::Size(size_args) => {
+                binu::size_command(&size_args.input_filename, size_args.start, size_args.end)?;
+                Ok(EXIT_SUCCESS)
             }
+            Commands::Hash(hash_args) => {
+                let hash_config = HashConfig {
+                    algo: hash_args.algo.into(),
+                    recursive: hash_args.recursive,
+                    skip_hidden: hash_args.no_hidden,
+                    jobs: hash_args.jobs,
+                };
+                binu::hash_command(&hash_args.filenames, &hash_config, progress)?;
+                Ok(EXIT_SUCCESS)
+            }
+            Commands::Peek(peek_args) => {
+                let peek_config = PeekConfig {
+                    ascii: peek_args.ascii,
+                };
+                binu::peek_command(
+                    &peek_args.input_filename,
+                    peek_args.offset,
+                    peek_args.len,
+                    &peek_config,
+                )?;
+                Ok(EXIT_SUCCESS)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replace_nth_conflicts_with_replace_all() {
+        let res = Cli::try_parse_from([
+            "binu", "replace", "--nth", "1", "--replace-all", "a", "b", "in", "out",
+        ]);
+        assert_eq!(res.unwrap_err().kind(), clap::error::ErrorKind::ArgumentConflict);
+
+        let res = Cli::try_parse_from(["binu", "replace", "--replace-all", "a", "b", "in", "out"]);
+        assert!(res.is_ok());
+        let res = Cli::try_parse_from(["binu", "replace", "--nth", "1", "a", "b", "in", "out"]);
+        assert!(res.is_ok());
+    }
+
+    #[test]
+    fn no_clobber_short_flag() {
+        let cli = Cli::try_parse_from(["binu", "insert", "-n", "nya", "0", "in", "out"]).unwrap();
+        let Commands::Insert(insert_args) = cli.command else {
+            panic!("Parsed as another command");
+        };
+        assert!(insert_args.no_clobber);
+
+        // For replace -n has been --nth all along
+        let cli = Cli::try_parse_from(["binu", "replace", "-n", "1", "a", "b", "in", "out"]).unwrap();
+        let Commands::Replace(replace_args) = cli.command else {
+            panic!("Parsed as another command");
+        };
+        assert_eq!(replace_args.nth, Some(1));
+        assert!(!replace_args.no_clobber);
+    }
+
+    /// Parse a grep command line and build its config.
+    fn grep_config(args: &[&str]) -> Result<GrepConfig, Error> {
+        let cli = Cli::try_parse_from([&["binu", "grep"][..], args].concat()).unwrap();
+        match cli.command {
+            Commands::Grep(grep_args) => grep_args.config(false),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn grep_text_conflicts() {
+        let conflicting = [&["--word"][..], &["--align", "4"], &["--type", "binary"], &["-i", "--fold", "latin1"]];
+        for args in conflicting {
+            let args = [&["--text"], args, &["nya", "test_files/file_lines"]].concat();
+            assert!(matches!(grep_config(&args), Err(Error::Usage(_))));
+        }
+        assert!(grep_config(&["--text", "nya", "test_files/file_lines"]).is_ok());
+    }
+
+    #[test]
+    fn grep_concat_conflicts() {
+        let args = ["--concat", "--type", "binary", "MAGIC", "test_files/file_part_a", "test_files/file_part_b"];
+        assert!(matches!(grep_config(&args), Err(Error::Usage(_))));
+    }
+
+    #[test]
+    fn grep_mode_conflicts() {
+        let conflicting = [
+            &["-L", "-q"][..], &["-c", "--text"], &["--concat", "-L"], &["-q", "--json"],
+            &["-L", "--count-files"], &["--offsets-format", "csv"],
+            &["--offsets-to-file", "out", "--line"],
+        ];
+        for args in conflicting {
+            let args = [args, &["nya", "test_files/file_one"]].concat();
+            assert!(matches!(grep_config(&args), Err(Error::Usage(_))), "{:?}", args);
         }
+        assert!(grep_config(&["-L", "--within", "0:4", "nya", "test_files/file_one"]).is_ok());
+    }
+
+    #[test]
+    fn pattern_auto_hex() {
+        assert_eq!(decode_pattern("0xdead", false, false).unwrap(), b"\xde\xad");
+        assert_eq!(decode_pattern("deadbeef", false, false).unwrap(), b"deadbeef");
+        assert_eq!(decode_pattern("0xnya", false, false).unwrap(), b"0xnya");
+        assert_eq!(decode_pattern("0xabc", false, false).unwrap(), b"0xabc");
+
+        assert_eq!(decode_pattern("deadbeef", true, false).unwrap(), b"\xde\xad\xbe\xef");
+        assert_eq!(decode_pattern("0XDEAD", true, false).unwrap(), b"\xde\xad");
+        assert!(decode_pattern("0xnya", true, false).is_err());
+        assert_eq!(decode_pattern("0xdead", false, true).unwrap(), b"0xdead");
+
+        assert_eq!(decode_pattern("\\x7fELF", false, false).unwrap(), b"\x7fELF");
+        assert_eq!(decode_pattern("\\x7fELF", false, true).unwrap(), b"\\x7fELF");
+        assert!(decode_pattern("\\x7", false, false).is_err());
+
+        let res = Cli::try_parse_from(["binu", "grep", "--hex", "--string", "00", "in"]);
+        assert_eq!(res.unwrap_err().kind(), clap::error::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn color_choice() {
+        assert!(!ColorArg::Never.enabled(true));
+        assert!(!ColorArg::Auto.enabled(false));
+        assert!(ColorArg::Auto.enabled(true));
+        assert!(ColorArg::Always.enabled(false));
+    }
+
+    #[test]
+    fn grep_exit_codes() {
+        let exec = |args: &[&str]| Cli::try_parse_from(args).unwrap().exec();
+
+        let code = exec(&["binu", "-q", "grep", "nya", "test_files/file_one"]);
+        assert_eq!(code.unwrap(), EXIT_SUCCESS);
+        let code = exec(&["binu", "-q", "grep", "meow", "test_files/file_one"]);
+        assert_eq!(code.unwrap(), EXIT_NO_MATCH);
+        let code = exec(&["binu", "grep", "-q", "nya", "test_files/file_one"]);
+        assert_eq!(code.unwrap(), EXIT_SUCCESS);
+    }
+
+    #[test]
+    fn exec_returns_errors() {
+        let cli = Cli::try_parse_from(["binu", "grep", "--regex", "(", "test_files/file_one"]).unwrap();
+        match cli.exec() {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidInput),
+            res => panic!("Expected an io error, got {:?}", res),
+        }
+
+        let cli = Cli::try_parse_from([
+            "binu", "-q", "fill", "--start", "0", "--len", "999",
+            "test_files/file_three", "test_files/file_three_fill_past_eof",
+        ]).unwrap();
+        assert!(matches!(cli.exec(), Err(Error::Io(_))));
     }
 }
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use crate::util;
+
+/// Reader chaining files one after another, as if they were a single
+/// file, e.g. the parts of a split archive. Offsets are counted from
+/// the start of the first file. Only the file being read is open.
+#[derive(Debug)]
+pub struct ConcatReader {
+    /// Path, starting offset and length of every file.
+    parts: Vec<(PathBuf, u64, u64)>,
+    len: u64,
+    position: u64,
+    current: Option<(usize, File)>,
+}
+
+impl ConcatReader {
+    /// Chain `filenames` in the given order. Their sizes are taken when
+    /// they're opened here, so they shouldn't change while reading.
+    pub fn open<T: AsRef<Path>>(filenames: &[T]) -> Result<Self, io::Error> {
+        let mut parts = Vec::new();
+        let mut len = 0;
+        for filename in filenames {
+            let file_len = util::file_len(&File::open(filename)?)?;
+            parts.push((filename.as_ref().to_path_buf(), len, file_len));
+            len += file_len;
+        }
+
+        Ok(ConcatReader { parts, len, position: 0, current: None })
+    }
+
+    /// Total length of all the files.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Files holding at least one of the `len` bytes at `offset`, in
+    /// order. More than one when the bytes straddle a boundary.
+    pub fn files_spanning(&self, offset: u64, len: usize) -> Vec<&Path> {
+        let end = offset + len as u64;
+        self.parts
+            .iter()
+            .filter(|(_, start, part_len)| {
+                *part_len > 0 && *start < end && start + part_len > offset
+            })
+            .map(|(path, _, _)| path.as_path())
+            .collect()
+    }
+
+    /// Index of the non-empty file holding the byte at `offset`.
+    fn part_at(&self, offset: u64) -> Option<usize> {
+        self.parts
+            .iter()
+            .position(|(_, start, part_len)| *start <= offset && offset < start + part_len)
+    }
+}
+
+impl Read for ConcatReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(n) = self.part_at(self.position) else {
+            return Ok(0);
+        };
+        let (path, start, part_len) = &self.parts[n];
+        if self.current.as_ref().is_none_or(|(current, _)| *current != n) {
+            let mut file = File::open(path)?;
+            file.seek(SeekFrom::Start(self.position - start))?;
+            self.current = Some((n, file));
+        }
+        // Never read past the length the file had when it was opened
+        let left = start + part_len - self.position;
+        let (_, file) = self.current.as_mut().unwrap();
+        let read = file.take(left).read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} got shorter while reading it", path.display()),
+            ));
+        }
+        self.position += read as u64;
+
+        Ok(read)
+    }
+}
+
+impl Seek for ConcatReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+        };
+        let Some(position) = position else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seeking before the start of the files",
+            ));
+        };
+        // Keep the open file if the new position is still in it
+        let part = self.part_at(position);
+        match (&mut self.current, part) {
+            (Some((current, file)), Some(n)) if *current == n => {
+                file.seek(SeekFrom::Start(position - self.parts[n].1))?;
+            }
+            _ => self.current = None,
+        }
+        self.position = position;
+
+        Ok(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concat_reader_test() {
+        let files = ["test_files/file_three", "test_files/file_empty", "test_files/file_three"];
+        let mut reader = ConcatReader::open(&files).unwrap();
+        assert_eq!(reader.len(), 10);
+        let mut buf = Vec::new();
+        reader.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf, b"\x00\x00\x01\x01\xfe\x00\x00\x01\x01\xfe");
+
+        reader.seek(SeekFrom::Start(3)).unwrap();
+        let mut buf = [0; 4];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"\x01\xfe\x00\x00");
+        reader.seek(SeekFrom::Current(-3)).unwrap();
+        reader.read_exact(&mut buf[..1]).unwrap();
+        assert_eq!(buf[0], 0xfe);
+
+        assert_eq!(reader.files_spanning(4, 2), vec![Path::new(files[0]), Path::new(files[2])]);
+        assert_eq!(reader.files_spanning(6, 2), vec![Path::new(files[2])]);
+    }
+}
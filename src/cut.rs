@@ -0,0 +1,105 @@
+use std::io;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::fs::File;
+use crate::util::{self, Offset};
+
+#[derive(Default, Debug)]
+pub struct CutConfig {
+    pub quiet: bool,
+}
+
+/// Function for executing the command line cut command. You probably
+/// want to use `cut()` instead. A `start` counted from the end is
+/// resolved against the size of the input file.
+pub fn cut_command(
+    start: Offset,
+    len: usize,
+    input_filename: &Path,
+    output_filename: &Path,
+    cut_config: &CutConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let start = start.resolve(util::file_len(&File::open(input_filename)?)?)?;
+    cut_with_progress(start, len, input_filename, output_filename, progress)?;
+    if !cut_config.quiet {
+        println!("Cut {} bytes successfully", len);
+    }
+
+    Ok(())
+}
+
+/// Write the range `[start, start+len)` of `input_filename` to
+/// `output_filename`, counting from 0. Return an error if the range
+/// doesn't fit in the file.
+pub fn cut(
+    start: usize,
+    len: usize,
+    input_filename: &Path,
+    output_filename: &Path,
+) -> Result<(), io::Error> {
+    cut_with_progress(start, len, input_filename, output_filename, &mut |_, _| {})
+}
+
+/// Same as `cut()`, except `progress` is periodically called with the
+/// number of bytes written so far and `len`.
+pub fn cut_with_progress(
+    start: usize,
+    len: usize,
+    input_filename: &Path,
+    output_filename: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let mut input_file = util::open_file(input_filename)?;
+    util::ensure_different_files(input_filename, output_filename)?;
+    let file_len = input_file.file.get_ref().metadata()?.len();
+    let end = start.checked_add(len).map(|end| end as u64);
+    if end.is_none_or(|end| end > file_len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Range {}..{} exceeds the end of the file", start, start.saturating_add(len)),
+        ));
+    }
+    let mut progress = util::Progress::new(len as u64, progress);
+
+    // Copying works in chunks, so we never hold the whole range in memory
+    input_file.file.seek(SeekFrom::Start(start as u64))?;
+    util::write_atomically(output_filename, |output_file| {
+        util::copy_exact_with_progress(&mut input_file.file, output_file, len as u64, &mut progress)
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::grep;
+
+    #[test]
+    fn simple_cut_test() {
+        cut(
+            4,
+            4,
+            Path::new("test_files/file_one"),
+            Path::new("test_files/file_one_cut")
+        ).expect("Probably a file related error");
+
+        let file = vec!["test_files/file_one_cut"];
+        let res = grep::grep(b"yame", &file).unwrap();
+        assert_eq!(res[0].1, vec![0]);
+        assert_eq!(std::fs::metadata("test_files/file_one_cut").unwrap().len(), 4);
+    }
+
+    #[test]
+    fn cut_test_past_eof() {
+        let res = cut(
+            2,
+            4,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_cut_past_eof")
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
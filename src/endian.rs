@@ -0,0 +1,169 @@
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::fs::File;
+use crate::util::{self, Offset};
+
+#[derive(Default, Debug)]
+pub struct EndianSwapConfig {
+    pub quiet: bool,
+    pub pad: bool,
+}
+
+/// Function for executing the command line endian-swap command. You
+/// probably want to use `endian_swap()` instead. Offsets counted from
+/// the end are resolved against the size of the input file, a missing
+/// `end` means the end of the file.
+pub fn endian_swap_command(
+    width: usize,
+    start: Offset,
+    end: Option<Offset>,
+    input_filename: &Path,
+    output_filename: &Path,
+    endian_swap_config: &EndianSwapConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let file_len = util::file_len(&File::open(input_filename)?)?;
+    let start = start.resolve(file_len)?;
+    let end = end.unwrap_or(Offset::FromEnd(0)).resolve(file_len)?;
+    let words = endian_swap_with_progress(
+        width,
+        start,
+        end,
+        endian_swap_config.pad,
+        input_filename,
+        output_filename,
+        progress,
+    )?;
+    if !endian_swap_config.quiet {
+        let plural = if words == 1 { "" } else { "s" };
+        println!("Swapped {} word{} successfully", words, plural);
+    }
+
+    Ok(())
+}
+
+/// Reverse the bytes of every `width` bytes long word in the range
+/// `[start, end)` of `input_filename`, counting from 0. Results are
+/// saved in `output_filename`. Return an error if the length of the
+/// range isn't a multiple of `width`, unless `pad` is set, in which
+/// case the last word is padded with zeros, growing the output.
+/// Return the number of swapped words.
+pub fn endian_swap(
+    width: usize,
+    start: usize,
+    end: usize,
+    pad: bool,
+    input_filename: &Path,
+    output_filename: &Path,
+) -> Result<usize, io::Error> {
+    endian_swap_with_progress(width, start, end, pad, input_filename, output_filename, &mut |_, _| {})
+}
+
+/// Same as `endian_swap()`, except `progress` is periodically called
+/// with the number of input bytes processed so far and the input size.
+pub fn endian_swap_with_progress(
+    width: usize,
+    start: usize,
+    end: usize,
+    pad: bool,
+    input_filename: &Path,
+    output_filename: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<usize, io::Error> {
+    let mut input_file = util::open_file(input_filename)?;
+    util::ensure_different_files(input_filename, output_filename)?;
+    let file_len = input_file.file.get_ref().metadata()?.len();
+    if width == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Word width can't be 0"));
+    }
+    if start > end || end as u64 > file_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Range {}..{} doesn't fit in the file", start, end),
+        ));
+    }
+    let len = end - start;
+    if !len.is_multiple_of(width) && !pad {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Range length {} isn't a multiple of the word width {}", len, width),
+        ));
+    }
+    let mut progress = util::Progress::new(file_len, progress);
+    util::write_atomically(output_filename, |output_file| {
+        util::copy_exact_with_progress(&mut input_file.file, output_file, start as u64, &mut progress)?;
+
+        // Chunks hold whole words, so no word is split between two reads
+        let chunk_len = (util::PROGRESS_INTERVAL as usize / width).max(1) * width;
+        let mut buf = vec![0u8; chunk_len];
+        let mut remaining = len;
+        while remaining > 0 {
+            let read = remaining.min(chunk_len);
+            input_file.file.read_exact(&mut buf[..read])?;
+            progress.advance(read as u64);
+            remaining -= read;
+            // Only the last word can be partial, when padding
+            let n = read.next_multiple_of(width);
+            buf[read..n].fill(0);
+            for word in buf[..n].chunks_mut(width) {
+                word.reverse();
+            }
+            output_file.write_all(&buf[..n])?;
+        }
+        util::copy_with_progress(&mut input_file.file, output_file, &mut progress)?;
+        output_file.flush()?;
+        util::copy_metadata(input_file.file.get_ref(), output_file.get_ref())
+    })?;
+
+    Ok(len.div_ceil(width))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn simple_endian_swap_test() {
+        let words = endian_swap(
+            4,
+            0,
+            8,
+            false,
+            Path::new("test_files/file_words"),
+            Path::new("test_files/file_words_swap"),
+        ).expect("Probably a file related error");
+        assert_eq!(words, 2);
+        assert_eq!(
+            std::fs::read("test_files/file_words_swap").unwrap(),
+            b"\x04\x03\x02\x01\x08\x07\x06\x05\x0a\x0b",
+        );
+    }
+
+    #[test]
+    fn endian_swap_test_pad() {
+        let res = endian_swap(
+            4,
+            0,
+            10,
+            false,
+            Path::new("test_files/file_words"),
+            Path::new("test_files/file_words_swap_pad"),
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        endian_swap(
+            4,
+            0,
+            10,
+            true,
+            Path::new("test_files/file_words"),
+            Path::new("test_files/file_words_swap_pad"),
+        ).expect("Probably a file related error");
+        assert_eq!(
+            std::fs::read("test_files/file_words_swap_pad").unwrap(),
+            b"\x04\x03\x02\x01\x08\x07\x06\x05\x00\x00\x0b\x0a",
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use std::io;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::fs::File;
+use crate::util::{self, Offset};
+
+#[derive(Default, Debug)]
+pub struct EntropyConfig {
+    pub histogram: bool,
+}
+
+/// Function for executing the command line entropy command. You
+/// probably want to use `byte_histogram()` and `entropy()` instead.
+/// Offsets counted from the end are resolved against the size of the
+/// input file, a missing `end` means the end of the file. Return the
+/// entropy of the range.
+pub fn entropy_command(
+    input_filename: &Path,
+    start: Offset,
+    end: Option<Offset>,
+    entropy_config: &EntropyConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<f64, io::Error> {
+    let file_len = util::file_len(&File::open(input_filename)?)?;
+    let start = start.resolve(file_len)?;
+    let end = end.unwrap_or(Offset::FromEnd(0)).resolve(file_len)?;
+    let histogram = byte_histogram_with_progress(input_filename, start, end, progress)?;
+    let res = entropy(&histogram);
+    println!("Entropy: {:.4} bits per byte", res);
+    if entropy_config.histogram {
+        for (byte, &count) in histogram.iter().enumerate().filter(|(_, &count)| count != 0) {
+            println!("{:#04x}: {}", byte, count);
+        }
+    }
+
+    Ok(res)
+}
+
+/// Count how many times every byte value occurs in the range
+/// `[start, end)` of `input_filename`, counting from 0. The file is
+/// read in chunks, so it's never held whole in memory. Return an
+/// error if the range doesn't fit in the file.
+pub fn byte_histogram(
+    input_filename: &Path,
+    start: usize,
+    end: usize,
+) -> Result<[u64; 256], io::Error> {
+    byte_histogram_with_progress(input_filename, start, end, &mut |_, _| {})
+}
+
+/// Same as `byte_histogram()`, except `progress` is periodically
+/// called with the number of bytes counted so far and the range size.
+pub fn byte_histogram_with_progress(
+    input_filename: &Path,
+    start: usize,
+    end: usize,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<[u64; 256], io::Error> {
+    let mut input_file = util::open_file(input_filename)?;
+    let file_len = util::file_len(input_file.file.get_ref())?;
+    if start > end || end as u64 > file_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Range {}..{} doesn't fit in the file", start, end),
+        ));
+    }
+    input_file.file.seek(SeekFrom::Start(start as u64))?;
+    let len = (end - start) as u64;
+    let mut reader = input_file.file.take(len);
+    let mut progress = util::Progress::new(len, progress);
+
+    let mut histogram = [0u64; 256];
+    let mut buf = vec![0u8; util::PROGRESS_INTERVAL as usize];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for &byte in buf[..n].iter() {
+            histogram[byte as usize] += 1;
+        }
+        progress.advance(n as u64);
+    }
+
+    Ok(histogram)
+}
+
+/// Shannon entropy of the bytes counted in `histogram`, in bits per
+/// byte. It's 0 for a single repeated byte and 8 for uniformly
+/// distributed bytes, which usually means compressed or encrypted
+/// data.
+pub fn entropy(histogram: &[u64; 256]) -> f64 {
+    let total: u64 = histogram.iter().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    histogram
+        .iter()
+        .filter(|&&count| count != 0)
+        .map(|&count| {
+            let p = count as f64 / total as f64;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn simple_entropy_test() {
+        let file = Path::new("test_files/file_zeros");
+        let histogram = byte_histogram(file, 0, 64).expect("Probably a file related error");
+        assert_eq!(histogram[0], 64);
+        assert_eq!(entropy(&histogram), 0.0);
+
+        let file = Path::new("test_files/file_two");
+        let histogram = byte_histogram(file, 0, 127).expect("Probably a file related error");
+        assert_eq!(histogram.iter().sum::<u64>(), 127);
+        assert!(entropy(&histogram) > 2.0);
+
+        assert_eq!(entropy(&[1; 256]), 8.0);
+    }
+
+    #[test]
+    fn entropy_test_range() {
+        let file = Path::new("test_files/file_three");
+        let histogram = byte_histogram(file, 1, 3).expect("Probably a file related error");
+        assert_eq!(histogram[0], 1);
+        assert_eq!(histogram[1], 1);
+        assert_eq!(entropy(&histogram), 1.0);
+
+        let res = byte_histogram(file, 2, 6);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,37 @@
+use std::fmt;
+use std::io;
+
+/// Error returned by the command line layer. The library functions
+/// themselves return `io::Error`, which gets wrapped in `Error::Io`.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing a file failed, or a library function
+    /// rejected its input.
+    Io(io::Error),
+    /// The given arguments don't make sense together.
+    Usage(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Usage(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Usage(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,120 @@
+use std::io;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::fs::File;
+use crate::util::{self, Offset};
+
+#[derive(Default, Debug)]
+pub struct FillConfig {
+    pub quiet: bool,
+}
+
+/// Function for executing the command line fill command. You probably
+/// want to use `fill()` instead. A `start` counted from the end is
+/// resolved against the size of the input file.
+pub fn fill_command(
+    fill_byte: u8,
+    start: Offset,
+    len: usize,
+    input_filename: &Path,
+    output_filename: &Path,
+    fill_config: &FillConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let start = start.resolve(util::file_len(&File::open(input_filename)?)?)?;
+    fill_with_progress(fill_byte, start, len, input_filename, output_filename, progress)?;
+    if !fill_config.quiet {
+        println!("Filled {} bytes successfully", len);
+    }
+
+    Ok(())
+}
+
+/// Overwrite the range `[start, start+len)` of `input_filename` with
+/// `len` copies of `fill_byte`, counting from 0. Results are saved in
+/// `output_filename`. Return an error if the range doesn't fit in the
+/// file.
+pub fn fill(
+    fill_byte: u8,
+    start: usize,
+    len: usize,
+    input_filename: &Path,
+    output_filename: &Path,
+) -> Result<(), io::Error> {
+    fill_with_progress(fill_byte, start, len, input_filename, output_filename, &mut |_, _| {})
+}
+
+/// Same as `fill()`, except `progress` is periodically called with the
+/// number of input bytes processed so far and the input size.
+pub fn fill_with_progress(
+    fill_byte: u8,
+    start: usize,
+    len: usize,
+    input_filename: &Path,
+    output_filename: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let mut input_file = util::open_file(input_filename)?;
+    util::ensure_different_files(input_filename, output_filename)?;
+    let file_len = input_file.file.get_ref().metadata()?.len();
+    let end = start.checked_add(len).map(|end| end as u64);
+    if end.is_none_or(|end| end > file_len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Range {}..{} exceeds the end of the file", start, start.saturating_add(len)),
+        ));
+    }
+    let mut progress = util::Progress::new(file_len, progress);
+    util::write_atomically(output_filename, |output_file| {
+        // Copying works in chunks, so we never hold the whole file in memory
+        util::copy_exact_with_progress(&mut input_file.file, output_file, start as u64, &mut progress)?;
+        io::copy(&mut io::repeat(fill_byte).take(len as u64), output_file)?;
+        input_file.file.seek_relative(len as i64)?;
+        progress.advance(len as u64);
+        util::copy_with_progress(&mut input_file.file, output_file, &mut progress)?;
+        output_file.flush()?;
+        util::copy_metadata(input_file.file.get_ref(), output_file.get_ref())
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::grep;
+
+    #[test]
+    fn simple_fill_test() {
+        fill(
+            0xff,
+            16,
+            8,
+            Path::new("test_files/file_one"),
+            Path::new("test_files/file_one_fill")
+        ).expect("Probably a file related error");
+
+        let file = vec!["test_files/file_one_fill"];
+        let res = grep::grep(b"\xff", &file).unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].1, (16..24).collect::<Vec<u64>>());
+
+        let res = grep::grep(b"nya", &file).unwrap();
+        assert_eq!(res[0].1, vec![
+            3, 9, 12, 32, 43, 48, 55, 58, 64, 67, 74, 77, 84, 94, 104, 109
+        ]);
+    }
+
+    #[test]
+    fn fill_test_past_eof() {
+        let res = fill(
+            0xff,
+            2,
+            4,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_fill_past_eof")
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
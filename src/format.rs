@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use crate::util::{self, FoundMatch};
+
+/// Format of every line printed by grep for a match, parsed once. The
+/// tokens `{file}`, `{offset}`, `{offset_hex}`, `{len}`, `{match}` and
+/// `{match_hex}` expand to the filename, the offset in decimal and
+/// with a 0x prefix, the length of the match, the matched bytes with
+/// the unprintable ones escaped and the matched bytes as hex digits.
+/// `{{` and `}}` are literal braces. Escapes like `\t` and `\n` in
+/// the rest are understood like in patterns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrepFormat {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Token {
+    File,
+    Offset,
+    OffsetHex,
+    Len,
+    Match,
+    MatchHex,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(Vec<u8>),
+    Token(Token),
+}
+
+impl GrepFormat {
+    /// Parse the tokens in `format`. Return an error for an unknown or
+    /// an unclosed token.
+    pub fn parse(format: &str) -> Result<Self, io::Error> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = format;
+        while let Some(start) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..start]);
+            let brace = &rest[start..start + 1];
+            rest = &rest[start + 1..];
+            if let Some(after) = rest.strip_prefix(brace) {
+                literal.push_str(brace);
+                rest = after;
+                continue;
+            }
+            if brace == "}" {
+                literal.push('}');
+                continue;
+            }
+            let Some(end) = rest.find('}') else {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Unclosed format token {{{}", rest),
+                ));
+            };
+            let token = match &rest[..end] {
+                "file" => Token::File,
+                "offset" => Token::Offset,
+                "offset_hex" => Token::OffsetHex,
+                "len" => Token::Len,
+                "match" => Token::Match,
+                "match_hex" => Token::MatchHex,
+                name => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Unknown format token {{{}}}", name),
+                    ));
+                }
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(util::unescape(&std::mem::take(&mut literal))?));
+            }
+            parts.push(Part::Token(token));
+            rest = &rest[end + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(util::unescape(&literal)?));
+        }
+
+        Ok(GrepFormat { parts })
+    }
+
+    /// Whether the format has a token for the matched bytes, so they
+    /// have to be read from the file.
+    pub fn reads_match(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Token(Token::Match | Token::MatchHex)))
+    }
+
+    /// Expand the tokens for `found` in `filename`, opened as `file`.
+    /// Offsets are printed as `base` plus the file offset. The matched
+    /// bytes are only read from the file when they're used, so `file`
+    /// can be `None` if `reads_match()` is false.
+    pub fn expand(
+        &self,
+        file: Option<&mut File>,
+        filename: &Path,
+        found: FoundMatch,
+        base: u64,
+    ) -> Result<Vec<u8>, io::Error> {
+        let matched = match file {
+            Some(file) if self.reads_match() => util::read_context(file, found.offset, found.len, 0)?.1,
+            None if self.reads_match() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "The matched bytes can't be read without the file",
+                ));
+            }
+            _ => Vec::new(),
+        };
+        let offset = found.offset.saturating_add(base);
+        let mut out = Vec::new();
+        for part in self.parts.iter() {
+            let expanded = match part {
+                Part::Literal(bytes) => {
+                    out.extend_from_slice(bytes);
+                    continue;
+                }
+                Part::Token(Token::File) => filename.display().to_string(),
+                Part::Token(Token::Offset) => offset.to_string(),
+                Part::Token(Token::OffsetHex) => format!("{:#x}", offset),
+                Part::Token(Token::Len) => found.len.to_string(),
+                Part::Token(Token::Match) => util::escape_bytes(&matched),
+                Part::Token(Token::MatchHex) => {
+                    matched.iter().map(|b| format!("{:02x}", b)).collect()
+                }
+            };
+            out.extend_from_slice(expanded.as_bytes());
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grep_format_test() {
+        let format = GrepFormat::parse("{file}\\t{offset_hex} {{{len}}} {match}={match_hex}\\n").unwrap();
+        let found = FoundMatch { offset: 21, len: 3 };
+        let filename = Path::new("test_files/file_two");
+        let file = &mut File::open(filename).unwrap();
+        let line = format.expand(Some(file), filename, found, 0).unwrap();
+        assert_eq!(line, b"test_files/file_two\t0x15 {3} 20%=323025\n");
+        let line = GrepFormat::parse("{offset}").unwrap()
+            .expand(None, filename, found, 0x1000)
+            .unwrap();
+        assert_eq!(line, b"4117");
+
+        let res = GrepFormat::parse("{file} {size}");
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = GrepFormat::parse("{offset");
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
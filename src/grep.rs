@@ -1,72 +1,2274 @@
-use std::io;
+use std::fs;
+use std::io::{self, BufRead, Read, Seek, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::path::{PathBuf, Path};
 
-use crate::util;
+use regex::bytes::Regex;
+
+use crate::concat::ConcatReader;
+use crate::format::GrepFormat;
+use crate::searcher::{self, Searcher};
+use crate::util::{self, CaseFold, FoundMatch};
+
+/// How many bytes at most are printed on each side of a match in the
+/// line mode, so files without newlines aren't printed whole.
+const LINE_WINDOW: usize = 256;
+
+/// How often a checkpointed search saves how far it got, in bytes.
+pub const CHECKPOINT_INTERVAL: u64 = 1 << 24;
+
+/// Files at least this big are counted with `count_matches_fast()` by
+/// the count command, when it's possible.
+pub const FAST_COUNT_MIN_LEN: u64 = 1 << 20;
+
+/// Which files get searched, based on a guess if they are binary or
+/// text. A file is considered binary if there's a NUL byte in its
+/// first few KiB.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    #[default]
+    All,
+    Binary,
+    Text,
+}
+
+/// Encoding the pattern is transcoded into before searching. Useful
+/// for e.g. Windows binaries, which store their strings as UTF-16.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Search for the pattern bytes as they are.
+    #[default]
+    Ascii,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    /// Transcode `pattern` into the bytes of this encoding. For UTF-16
+    /// the pattern has to be valid UTF-8.
+    pub fn encode(self, pattern: &[u8]) -> Result<Vec<u8>, io::Error> {
+        if self == Encoding::Ascii {
+            return Ok(pattern.to_vec());
+        }
+        let pattern = std::str::from_utf8(pattern).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Pattern isn't valid UTF-8: {}", e))
+        })?;
+        let encoded = pattern
+            .encode_utf16()
+            .flat_map(|unit| match self {
+                Encoding::Utf16Be => unit.to_be_bytes(),
+                _ => unit.to_le_bytes(),
+            })
+            .collect();
+
+        Ok(encoded)
+    }
+}
+
+/// How `grep_command()` writes the offsets to `offsets_file`.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetsFormat {
+    /// Every offset in decimal on its own line.
+    #[default]
+    Lines,
+    /// Every offset as a little endian u64.
+    Binary,
+    /// A `file,offset,len` header and a row for every match.
+    Csv,
+}
 
 #[derive(Default, Debug)]
 pub struct GrepConfig {
     pub quiet: bool,
     pub recursive: bool,
+    pub skip_hidden: bool,
+    pub case_insensitive: bool,
+    pub fold: CaseFold,
+    pub regex: bool,
+    pub json: bool,
+    pub context: Option<GrepContext>,
+    pub count_bytes: bool,
+    pub file_type: FileType,
+    pub encoding: Encoding,
+    pub only_matching: bool,
+    pub align: Option<u64>,
+    pub word: bool,
+    pub base: u64,
+    pub count_files: bool,
+    pub count_patterns: bool,
+    pub count: bool,
+    pub group_by_dir: bool,
+    pub invert_file: bool,
+    pub quiet_match: bool,
+    pub concat: bool,
+    pub within: Option<Range<usize>>,
+    pub color: bool,
+    pub jobs: usize,
+    pub line: bool,
+    pub text: bool,
+    pub deltas: bool,
+    pub decompress: bool,
+    pub limit_total: Option<usize>,
+    pub not_after: Option<Vec<u8>>,
+    pub not_before: Option<Vec<u8>>,
+    pub with_filename: Option<bool>,
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    pub max_depth: Option<usize>,
+    pub checkpoint: Option<PathBuf>,
+    pub resume: bool,
+    pub format: Option<GrepFormat>,
+    pub offsets_file: Option<PathBuf>,
+    pub offsets_format: OffsetsFormat,
+}
+
+/// Number of bytes shown in the hexdump before and after every match.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrepContext {
+    pub before: usize,
+    pub after: usize,
+}
+
+/// How far a checkpointed grep got, saved so an interrupted search can
+/// be resumed: the file being searched, the offset searched up to and
+/// the matches found in the file before it. The matches are kept in a
+/// file of their own next to the checkpoint, see `matches_path()`.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    pub path: PathBuf,
+    pub offset: u64,
+    pub matches: Vec<FoundMatch>,
+}
+
+impl Checkpoint {
+    /// Read a checkpoint written by `write()`, together with its
+    /// matches.
+    pub fn read(filename: &Path) -> Result<Self, io::Error> {
+        let invalid = || io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid checkpoint file {}", filename.display()),
+        );
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(filename)?)
+            .map_err(|_| invalid())?;
+        let path = json["file"].as_str().ok_or_else(invalid)?;
+        let offset = json["offset"].as_u64().ok_or_else(invalid)?;
+        let matches_len = json["matches_len"].as_u64().ok_or_else(invalid)?;
+        // Matches appended after the checkpoint was saved are left out
+        let mut records = Vec::new();
+        fs::File::open(Self::matches_path(filename))?.take(matches_len).read_to_end(&mut records)?;
+        if records.len() as u64 != matches_len || matches_len % MATCH_RECORD_LEN != 0 {
+            return Err(invalid());
+        }
+        let matches = records
+            .chunks_exact(MATCH_RECORD_LEN as usize)
+            .map(|record| {
+                let (offset, len) = record.split_at(8);
+                FoundMatch {
+                    offset: u64::from_le_bytes(offset.try_into().expect("8 bytes")),
+                    len: u64::from_le_bytes(len.try_into().expect("8 bytes")) as usize,
+                }
+            })
+            .collect();
+
+        Ok(Checkpoint { path: PathBuf::from(path), offset, matches })
+    }
+
+    /// Save the checkpoint as JSON in `filename` and its matches next
+    /// to it. The JSON is written to a temporary file first, so an
+    /// interruption never leaves half of it.
+    pub fn write(&self, filename: &Path) -> Result<(), io::Error> {
+        CheckpointWriter::open(filename, &self.path, 0)?.save(self.offset, &self.matches)
+    }
+
+    /// The file with the matches of the checkpoint in `filename`, every
+    /// one of them as a little endian u64 offset and u64 length.
+    pub fn matches_path(filename: &Path) -> PathBuf {
+        let mut matches_path = filename.as_os_str().to_owned();
+        matches_path.push(".matches");
+        PathBuf::from(matches_path)
+    }
+}
+
+/// Size of a single match in the match file of a checkpoint.
+const MATCH_RECORD_LEN: u64 = 16;
+
+/// Saves the checkpoint of a file again and again while it's searched.
+/// Only the new matches are appended to the match file every time, and
+/// the checkpoint itself only keeps how long the match file is, so the
+/// matches found before are never written again.
+struct CheckpointWriter<'a> {
+    filename: &'a Path,
+    path: &'a Path,
+    matches: fs::File,
+    matches_len: u64,
+}
+
+impl<'a> CheckpointWriter<'a> {
+    /// Start saving the checkpoint in `filename` for the search of
+    /// `path`, keeping the first `kept` matches of the match file.
+    fn open(filename: &'a Path, path: &'a Path, kept: usize) -> Result<Self, io::Error> {
+        let matches = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(Checkpoint::matches_path(filename))?;
+        let matches_len = kept as u64 * MATCH_RECORD_LEN;
+        matches.set_len(matches_len)?;
+
+        Ok(CheckpointWriter { filename, path, matches, matches_len })
+    }
+
+    /// Append `found` to the match file and save the checkpoint at
+    /// `offset`. The matches are synced first, so the checkpoint never
+    /// counts matches which weren't written yet.
+    fn save(&mut self, offset: u64, found: &[FoundMatch]) -> Result<(), io::Error> {
+        let records: Vec<u8> = found
+            .iter()
+            .flat_map(|found| [found.offset.to_le_bytes(), (found.len as u64).to_le_bytes()])
+            .flatten()
+            .collect();
+        self.matches.seek(io::SeekFrom::Start(self.matches_len))?;
+        self.matches.write_all(&records)?;
+        self.matches.sync_data()?;
+        self.matches_len += records.len() as u64;
+        let json = serde_json::json!({
+            "file": self.path.to_string_lossy(),
+            "offset": offset,
+            "matches_len": self.matches_len,
+        });
+        util::write_atomically(self.filename, |out| out.write_all(json.to_string().as_bytes()))
+    }
+}
+
+/// Summary of the results written by `write_results()`.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GrepSummary {
+    /// Number of searched files.
+    pub files_scanned: usize,
+    /// Number of files with at least one match.
+    pub files_matched: usize,
+    /// Number of matches in all the files.
+    pub matches: usize,
+}
+
+impl GrepConfig {
+    /// Start building a config, validated when `build()` is called.
+    pub fn builder() -> GrepConfigBuilder {
+        GrepConfigBuilder::default()
+    }
+}
+
+/// Builder returned by `GrepConfig::builder()`.
+#[derive(Default, Debug)]
+pub struct GrepConfigBuilder {
+    config: GrepConfig,
+}
+
+impl GrepConfigBuilder {
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.config.quiet = quiet;
+        self
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.config.recursive = recursive;
+        self
+    }
+
+    pub fn skip_hidden(mut self, skip_hidden: bool) -> Self {
+        self.config.skip_hidden = skip_hidden;
+        self
+    }
+
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.config.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Which bytes count as the same letter with `case_insensitive`.
+    pub fn fold(mut self, fold: CaseFold) -> Self {
+        self.config.fold = fold;
+        self
+    }
+
+    pub fn regex(mut self, regex: bool) -> Self {
+        self.config.regex = regex;
+        self
+    }
+
+    pub fn json(mut self, json: bool) -> Self {
+        self.config.json = json;
+        self
+    }
+
+    /// Show `context` bytes both before and after every match.
+    pub fn context(mut self, context: usize) -> Self {
+        self.config.context = Some(GrepContext { before: context, after: context });
+        self
+    }
+
+    /// Show `before` bytes before every match, keeping the bytes shown
+    /// after it.
+    pub fn before_context(mut self, before: usize) -> Self {
+        let after = self.config.context.map_or(0, |context| context.after);
+        self.config.context = Some(GrepContext { before, after });
+        self
+    }
+
+    /// Show `after` bytes after every match, keeping the bytes shown
+    /// before it.
+    pub fn after_context(mut self, after: usize) -> Self {
+        let before = self.config.context.map_or(0, |context| context.before);
+        self.config.context = Some(GrepContext { before, after });
+        self
+    }
+
+    pub fn count_bytes(mut self, count_bytes: bool) -> Self {
+        self.config.count_bytes = count_bytes;
+        self
+    }
+
+    pub fn file_type(mut self, file_type: FileType) -> Self {
+        self.config.file_type = file_type;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: Encoding) -> Self {
+        self.config.encoding = encoding;
+        self
+    }
+
+    pub fn only_matching(mut self, only_matching: bool) -> Self {
+        self.config.only_matching = only_matching;
+        self
+    }
+
+    pub fn align(mut self, align: u64) -> Self {
+        self.config.align = Some(align);
+        self
+    }
+
+    pub fn word(mut self, word: bool) -> Self {
+        self.config.word = word;
+        self
+    }
+
+    pub fn base(mut self, base: u64) -> Self {
+        self.config.base = base;
+        self
+    }
+
+    pub fn count_files(mut self, count_files: bool) -> Self {
+        self.config.count_files = count_files;
+        self
+    }
+
+    /// Print every pattern with its number of matches in all the files
+    /// after the results, including patterns that weren't found.
+    pub fn count_patterns(mut self, count_patterns: bool) -> Self {
+        self.config.count_patterns = count_patterns;
+        self
+    }
+
+    /// Print the number of matches in every file instead of the
+    /// matches, see `count_command()`.
+    pub fn count(mut self, count: bool) -> Self {
+        self.config.count = count;
+        self
+    }
+
+    pub fn group_by_dir(mut self, group_by_dir: bool) -> Self {
+        self.config.group_by_dir = group_by_dir;
+        self
+    }
+
+    pub fn invert_file(mut self, invert_file: bool) -> Self {
+        self.config.invert_file = invert_file;
+        self
+    }
+
+    /// Only find out if anything matches, see `contains_command()`.
+    pub fn quiet_match(mut self, quiet_match: bool) -> Self {
+        self.config.quiet_match = quiet_match;
+        self
+    }
+
+    /// Search the files as one stream, see `concat_command()`.
+    pub fn concat(mut self, concat: bool) -> Self {
+        self.config.concat = concat;
+        self
+    }
+
+    pub fn within(mut self, within: Range<usize>) -> Self {
+        self.config.within = Some(within);
+        self
+    }
+
+    /// Paint the offsets cyan and the matched bytes red with ANSI
+    /// escape codes. JSON output is never painted.
+    pub fn color(mut self, color: bool) -> Self {
+        self.config.color = color;
+        self
+    }
+
+    /// Search this many files at once, see `grep_parallel()`.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.config.jobs = jobs;
+        self
+    }
+
+    pub fn line(mut self, line: bool) -> Self {
+        self.config.line = line;
+        self
+    }
+
+    /// Match every line on its own, see `grep_text()`.
+    pub fn text(mut self, text: bool) -> Self {
+        self.config.text = text;
+        self
+    }
+
+    /// Print the first offset of every file and then the distance of
+    /// every match from the previous one, e.g. to spot a fixed stride.
+    pub fn deltas(mut self, deltas: bool) -> Self {
+        self.config.deltas = deltas;
+        self
+    }
+
+    /// Search the gzip decompressed contents of the files instead, with
+    /// the offsets counted in the decompressed data. Needs the
+    /// `decompress` feature.
+    pub fn decompress(mut self, decompress: bool) -> Self {
+        self.config.decompress = decompress;
+        self
+    }
+
+    /// Stop the whole search once this many matches were found in all
+    /// the files together. The file with the last of them is only
+    /// searched up to it and the files after it aren't opened at all.
+    pub fn limit_total(mut self, limit_total: usize) -> Self {
+        self.config.limit_total = Some(limit_total);
+        self
+    }
+
+    /// Drop the matches directly preceded by these bytes, compared
+    /// exactly as they are, without case folding or an encoding.
+    pub fn not_after(mut self, not_after: Vec<u8>) -> Self {
+        self.config.not_after = Some(not_after);
+        self
+    }
+
+    /// Drop the matches directly followed by these bytes, compared
+    /// exactly as they are, without case folding or an encoding.
+    pub fn not_before(mut self, not_before: Vec<u8>) -> Self {
+        self.config.not_before = Some(not_before);
+        self
+    }
+
+    /// Whether to print the filename before the results of every file.
+    /// By default it's printed only when more than one file is searched.
+    pub fn with_filename(mut self, with_filename: bool) -> Self {
+        self.config.with_filename = Some(with_filename);
+        self
+    }
+
+    /// When searching recursively, only search files with a name
+    /// matching one of these globs.
+    pub fn include(mut self, include: Vec<String>) -> Self {
+        self.config.include = include;
+        self
+    }
+
+    /// When searching recursively, skip files with a name matching one
+    /// of these globs, even if they're included.
+    pub fn exclude(mut self, exclude: Vec<String>) -> Self {
+        self.config.exclude = exclude;
+        self
+    }
+
+    /// When searching recursively, descend at most this many levels
+    /// into the subdirectories, 0 meaning only the files in the given
+    /// directories.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.config.max_depth = Some(max_depth);
+        self
+    }
+
+    /// Save how far the search got in this file every
+    /// `CHECKPOINT_INTERVAL` bytes, see `Checkpoint`. Only a single
+    /// literal pattern can be checkpointed.
+    pub fn checkpoint(mut self, checkpoint: PathBuf) -> Self {
+        self.config.checkpoint = Some(checkpoint);
+        self
+    }
+
+    /// Continue the search from the saved checkpoint. Files before the
+    /// one in the checkpoint are skipped.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.config.resume = resume;
+        self
+    }
+
+    /// Print every match expanded with `format` and nothing else.
+    pub fn format(mut self, format: GrepFormat) -> Self {
+        self.config.format = Some(format);
+        self
+    }
+
+    /// Write the offsets of the matches to this file instead of
+    /// printing them, as they are found. Only the CSV format keeps the
+    /// filenames.
+    pub fn offsets_file(mut self, offsets_file: PathBuf) -> Self {
+        self.config.offsets_file = Some(offsets_file);
+        self
+    }
+
+    pub fn offsets_format(mut self, offsets_format: OffsetsFormat) -> Self {
+        self.config.offsets_format = offsets_format;
+        self
+    }
+
+    /// Return the config, or an error if mutually exclusive options
+    /// were combined.
+    pub fn build(self) -> Result<GrepConfig, io::Error> {
+        if self.config.json && (self.config.context.is_some() || self.config.count_bytes
+            || self.config.count_files || self.config.count_patterns
+            || self.config.group_by_dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "JSON output can't be used together with context, count_bytes, \
+                 count_files, count_patterns or group_by_dir",
+            ));
+        }
+        if self.config.count_patterns && self.config.jobs > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "count_patterns can't be used together with more than one job",
+            ));
+        }
+        if self.config.skip_hidden && !self.config.recursive {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "skip_hidden only makes sense together with recursive",
+            ));
+        }
+        if (!self.config.include.is_empty() || !self.config.exclude.is_empty()
+            || self.config.max_depth.is_some()) && !self.config.recursive {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "include, exclude and max_depth only make sense together with recursive",
+            ));
+        }
+        util::NameFilter::new(&self.config.include, &self.config.exclude)?;
+        if self.config.only_matching && (self.config.json || self.config.context.is_some()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only_matching can't be used together with JSON output or context",
+            ));
+        }
+        if self.config.align == Some(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "align has to be at least 1",
+            ));
+        }
+        if self.config.fold != CaseFold::Ascii && (self.config.regex
+            || !self.config.case_insensitive) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A case fold needs case_insensitive and can't be used together with regex",
+            ));
+        }
+        if self.config.regex && self.config.encoding != Encoding::Ascii {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "An encoding can't be used together with regex",
+            ));
+        }
+        if self.config.line && (self.config.json || self.config.context.is_some()
+            || self.config.only_matching) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "line can't be used together with JSON output, context or only_matching",
+            ));
+        }
+        // Every mode has a command of its own printing something else
+        // than the matches
+        let modes = [
+            self.config.invert_file,
+            self.config.quiet_match,
+            self.config.text,
+            self.config.concat,
+            self.config.count,
+        ];
+        if modes.iter().filter(|&&mode| mode).count() > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Only one of invert_file, quiet_match, text, concat and count can be used",
+            ));
+        }
+        // Only whether the files match is found out, stopping early
+        if (self.config.invert_file || self.config.quiet_match) && (self.config.json
+            || self.config.context.is_some() || self.config.count_bytes
+            || self.config.format.is_some() || self.config.offsets_file.is_some()
+            || self.config.checkpoint.is_some() || self.config.count_patterns || self.config.line
+            || self.config.deltas || self.config.decompress || self.config.limit_total.is_some()
+            || self.config.not_after.is_some() || self.config.not_before.is_some()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invert_file and quiet_match can't be used together with JSON output, context, \
+                 count_bytes, format, offsets_file, checkpoint, count_patterns, line, deltas, \
+                 decompress, limit_total, not_after or not_before",
+            ));
+        }
+        if self.config.invert_file && (self.config.only_matching || self.config.count_files
+            || self.config.group_by_dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invert_file can't be used together with only_matching, count_files or group_by_dir",
+            ));
+        }
+        // The lines and the stream of all the files are printed their
+        // own way, without going through the results of every file
+        if (self.config.text || self.config.concat) && (self.config.json
+            || self.config.context.is_some() || self.config.count_bytes || self.config.only_matching
+            || self.config.count_files || self.config.count_patterns || self.config.group_by_dir
+            || self.config.line || self.config.format.is_some() || self.config.checkpoint.is_some()
+            || self.config.jobs > 1 || self.config.deltas || self.config.decompress
+            || self.config.limit_total.is_some() || self.config.not_after.is_some()
+            || self.config.not_before.is_some() || self.config.offsets_file.is_some()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "text and concat can't be used together with JSON output, context, count_bytes, \
+                 only_matching, count_files, count_patterns, group_by_dir, line, format, \
+                 checkpoint, more than one job, deltas, decompress, limit_total, not_after, \
+                 not_before or offsets_file",
+            ));
+        }
+        // Lines are matched with the regex engine, which knows nothing
+        // about words, alignment, the type of the file or folding
+        // anything but ASCII
+        if self.config.text && (self.config.word || self.config.align.is_some()
+            || self.config.file_type != FileType::All || self.config.fold != CaseFold::Ascii) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "text can't be used together with word, align, file_type or a case fold",
+            ));
+        }
+        // The files are searched as one, whole files are never looked at
+        if self.config.concat && (self.config.regex || self.config.file_type != FileType::All) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "concat can't be used together with regex or file_type",
+            ));
+        }
+        if self.config.deltas && (self.config.json || self.config.context.is_some()
+            || self.config.line || self.config.only_matching || self.config.format.is_some()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "deltas can't be used together with JSON output, context, line, \
+                 only_matching or format",
+            ));
+        }
+        // Everything reading the bytes of a match again would read the
+        // compressed file
+        if self.config.decompress && (self.config.regex || self.config.context.is_some()
+            || self.config.line || self.config.only_matching || self.config.format.is_some()
+            || self.config.checkpoint.is_some() || self.config.invert_file
+            || self.config.jobs > 1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "decompress can't be used together with regex, context, line, only_matching, \
+                 format, checkpoint, invert_file or more than one job",
+            ));
+        }
+        if self.config.format.is_some() && (self.config.json || self.config.context.is_some()
+            || self.config.line || self.config.only_matching || self.config.count_bytes
+            || self.config.count_files || self.config.group_by_dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "format can't be used together with JSON output, context, line, \
+                 only_matching, count_bytes, count_files or group_by_dir",
+            ));
+        }
+        // Stopping early needs the files searched one after another
+        if self.config.limit_total.is_some() && (self.config.checkpoint.is_some()
+            || self.config.invert_file || self.config.jobs > 1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "limit_total can't be used together with checkpoint, invert_file \
+                 or more than one job",
+            ));
+        }
+        if (self.config.not_after.is_some() || self.config.not_before.is_some())
+            && (self.config.decompress || self.config.checkpoint.is_some()
+            || self.config.invert_file || self.config.jobs > 1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "not_after and not_before can't be used together with decompress, \
+                 checkpoint, invert_file or more than one job",
+            ));
+        }
+        // The offsets are written as they are found, nothing is buffered
+        if self.config.offsets_file.is_some() && (self.config.json
+            || self.config.context.is_some() || self.config.only_matching || self.config.line
+            || self.config.deltas || self.config.format.is_some() || self.config.count_files
+            || self.config.group_by_dir || self.config.invert_file || self.config.jobs > 1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offsets_file can't be used together with JSON output, context, only_matching, \
+                 line, deltas, format, count_files, group_by_dir, invert_file or more than \
+                 one job",
+            ));
+        }
+        if self.config.offsets_format != OffsetsFormat::Lines && self.config.offsets_file.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "offsets_format only makes sense together with offsets_file",
+            ));
+        }
+        if self.config.resume && self.config.checkpoint.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "resume needs a checkpoint",
+            ));
+        }
+        if self.config.checkpoint.is_some() && (self.config.regex || self.config.jobs > 1) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A checkpoint can't be used together with regex or more than one job",
+            ));
+        }
+        // Only the number of matches of every file is printed
+        if self.config.count && (self.config.context.is_some() || self.config.only_matching
+            || self.config.line || self.config.deltas || self.config.format.is_some()
+            || self.config.offsets_file.is_some() || self.config.base != 0
+            || self.config.count_files || self.config.count_patterns || self.config.group_by_dir
+            || self.config.invert_file || self.config.text) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "count can't be used together with context, only_matching, line, deltas, \
+                 format, offsets_file, base, count_files, count_patterns, group_by_dir, \
+                 invert_file or text",
+            ));
+        }
+        if self.config.within.is_some() && !self.config.invert_file {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "within only makes sense together with invert_file",
+            ));
+        }
+
+        Ok(self.config)
+    }
 }
 
 /// Function for executing the command line grep command. You probably
-/// want to use `grep()` instead.
-pub fn grep_command<T: AsRef<Path>>(
-    pattern: &[u8],
+/// want to use `grep()` instead. The summary is returned in quiet mode
+/// too, where only the messages are left out.
+pub fn grep_command<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
     filenames: &[T],
     grep_config: &GrepConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<GrepSummary, io::Error> {
+    let paths = search_paths(filenames, grep_config)?;
+    if grep_config.jobs > 1 {
+        let results = grep_parallel(patterns, &paths, grep_config)?;
+        return write_results(&mut io::stdout().lock(), &results, grep_config);
+    }
+
+    // Print the results of every file as soon as it's searched
+    let mut results = grep_iter(patterns, &paths, grep_config, progress)?;
+    let summary = match &grep_config.offsets_file {
+        Some(offsets_file) => util::write_atomically(offsets_file, |out| {
+            write_offsets_iter(out, &mut results, grep_config.offsets_format, grep_config.base)
+        })?,
+        None => write_results_iter(&mut io::stdout().lock(), results.searched_files(), grep_config)?,
+    };
+    if grep_config.count_patterns && !grep_config.quiet {
+        let mut out = io::stdout().lock();
+        writeln!(out)?;
+        write_pattern_counts(&mut out, patterns, results.pattern_counts())?;
+    }
+
+    Ok(summary)
+}
+
+/// Write every pattern with its number of matches, one per line, with
+/// bytes that aren't printable ASCII escaped. `counts` are in the
+/// order of `patterns`, like the ones from `GrepIter::pattern_counts()`.
+pub fn write_pattern_counts<W: Write, P: AsRef<[u8]>>(
+    out: &mut W,
+    patterns: &[P],
+    counts: &[usize],
 ) -> Result<(), io::Error> {
-    // Handle directories as paths
-    let files;
-    let paths: Vec::<&Path>;
+    for (pattern, count) in patterns.iter().zip(counts) {
+        writeln!(out, "{}: {}", util::escape_bytes(pattern.as_ref()), count)?;
+    }
+
+    Ok(())
+}
+
+/// Write the offsets of the matches in `results` to `out` in `format`,
+/// shifted by `base`, as the results come. Nothing else is written, so
+/// e.g. millions of matches don't go through the pretty printer.
+pub fn write_offsets_iter<W, I>(
+    out: &mut W,
+    results: I,
+    format: OffsetsFormat,
+    base: u64,
+) -> Result<GrepSummary, io::Error>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<(PathBuf, Vec<FoundMatch>), io::Error>>,
+{
+    if format == OffsetsFormat::Csv {
+        writeln!(out, "file,offset,len")?;
+    }
+    let mut summary = GrepSummary::default();
+    for result in results {
+        let (filename, found) = result?;
+        summary.files_scanned += 1;
+        summary.files_matched += usize::from(!found.is_empty());
+        summary.matches += found.len();
+        let filename = filename.to_string_lossy();
+        for found in found.iter() {
+            let offset = found.offset.saturating_add(base);
+            match format {
+                OffsetsFormat::Lines => writeln!(out, "{}", offset)?,
+                OffsetsFormat::Binary => out.write_all(&offset.to_le_bytes())?,
+                OffsetsFormat::Csv => {
+                    writeln!(out, "{},{},{}", csv_field(&filename), offset, found.len)?
+                }
+            }
+        }
+    }
+
+    Ok(summary)
+}
+
+/// Quote `field` if it has a comma, a quote or a newline in it.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Function for executing the command line grep command in the
+/// `--quiet-match` mode. Return whether any of the files matches any
+/// of `patterns`, stopping at the first match. Nothing is printed.
+pub fn contains_command<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<bool, io::Error> {
+    let paths = search_paths(filenames, grep_config)?;
+    let matchers = build_matchers(patterns, grep_config)?;
+
+    for file in util::open_files(&paths) {
+        let mut file = file?;
+        if grep_config.file_type != FileType::All
+            && util::looks_binary(&mut file)? != (grep_config.file_type == FileType::Binary)
+        {
+            continue;
+        }
+        if matches_within(&mut file, &matchers, grep_config, None)? {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Function for executing the command line grep command in the
+/// `--invert-file` mode. Print the files in which none of `patterns`
+/// occurs, only counting the matches lying wholly within
+/// `grep_config.within` if it's set. Return the number of printed
+/// files.
+pub fn absent_command<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<usize, io::Error> {
+    let paths = search_paths(filenames, grep_config)?;
+    let matchers = build_matchers(patterns, grep_config)?;
+    let mut out = io::stdout().lock();
+
+    let mut absent_files = 0;
+    for file in util::open_files(&paths) {
+        let mut file = file?;
+        if grep_config.file_type != FileType::All
+            && util::looks_binary(&mut file)? != (grep_config.file_type == FileType::Binary)
+        {
+            continue;
+        }
+        if !matches_within(&mut file, &matchers, grep_config, grep_config.within.clone())? {
+            writeln!(out, "{}", file.path.display())?;
+            absent_files += 1;
+        }
+    }
+
+    Ok(absent_files)
+}
+
+/// Function for executing the command line grep command in the
+/// `--count` mode. Print the number of matches in every file, after
+/// its name when more than one file is searched, and with
+/// `grep_config.count_bytes` the number of bytes they cover. With
+/// `grep_config.quiet` the files without matches are left out and with
+/// `grep_config.json` the counts are printed as a JSON array of
+/// `{ "file": ..., "count": ... }` objects. Return the total number of
+/// matches.
+pub fn count_command<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<usize, io::Error> {
+    let paths = search_paths(filenames, grep_config)?;
+    let show_filename = grep_config.with_filename.unwrap_or(paths.len() > 1);
+    let mut out = io::stdout().lock();
+
+    let mut total = 0;
+    let mut json = Vec::new();
+    let mut write_count = |path: &Path, count: usize, bytes: u64| -> Result<(), io::Error> {
+        total += count;
+        if grep_config.quiet && count == 0 {
+            return Ok(());
+        }
+        if grep_config.json {
+            json.push(serde_json::json!({ "file": path.to_string_lossy(), "count": count }));
+            return Ok(());
+        }
+        if show_filename {
+            write!(out, "{}: ", path.display())?;
+        }
+        if grep_config.count_bytes {
+            writeln!(out, "{} ({} bytes)", count, bytes)
+        } else {
+            writeln!(out, "{}", count)
+        }
+    };
+    if counts_directly(grep_config) {
+        let matchers = build_matchers(patterns, grep_config)?;
+        let align = grep_config.align.unwrap_or(1).max(1);
+        for file in util::open_files(&paths) {
+            let mut file = file?;
+            if grep_config.file_type != FileType::All
+                && util::looks_binary(&mut file)? != (grep_config.file_type == FileType::Binary)
+            {
+                continue;
+            }
+            let path = file.path;
+            let (count, bytes) = count_opened(file, &matchers, align, grep_config.word)?;
+            write_count(path, count, bytes)?;
+        }
+    } else {
+        let mut no_progress = |_, _| {};
+        for result in grep_iter(patterns, &paths, grep_config, &mut no_progress)? {
+            let (path, found) = result?;
+            write_count(&path, found.len(), found.iter().map(|found| found.len as u64).sum())?;
+        }
+    }
+    if grep_config.json {
+        writeln!(out, "{}", serde_json::Value::Array(json))?;
+    }
+
+    Ok(total)
+}
+
+/// Whether the matches of a file can be counted on their own with the
+/// options of `grep_config`, see `count_opened()`. The options which
+/// filter, limit or decompress the matches need them searched like
+/// `grep_iter()` does.
+fn counts_directly(grep_config: &GrepConfig) -> bool {
+    !grep_config.decompress
+        && grep_config.limit_total.is_none()
+        && grep_config.not_after.is_none()
+        && grep_config.not_before.is_none()
+        && grep_config.checkpoint.is_none()
+}
+
+/// Function for executing the command line grep command in the
+/// `--concat` mode. Print every match in the files searched as one
+/// stream, with its offset in the stream and the files it lies in,
+/// joined with ` + ` when it straddles their boundary. Return the
+/// number of matches.
+pub fn concat_command<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<usize, io::Error> {
+    let found = grep_concat(patterns, filenames, grep_config)?;
+    let mut out = io::stdout().lock();
+    if found.is_empty() {
+        if !grep_config.quiet {
+            writeln!(out, "Nothing found")?;
+        }
+        return Ok(0);
+    }
+    for concat_match in found.iter() {
+        let files: Vec<_> = concat_match.files
+            .iter()
+            .map(|file| file.display().to_string())
+            .collect();
+        let offset = concat_match.found.offset.saturating_add(grep_config.base);
+        writeln!(out, "{}: {}", util::paint(offset, util::CYAN, grep_config.color), files.join(" + "))?;
+    }
+
+    Ok(found.len())
+}
+
+/// Function for executing the command line grep command in the
+/// `--text` mode. Print every matching line with its number and the
+/// offset of its first match, after the filename when more than one
+/// file is searched, like text grep. Bytes which aren't printable
+/// ASCII are escaped. Return the number of matching lines.
+pub fn text_command<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<usize, io::Error> {
+    let paths = search_paths(filenames, grep_config)?;
+    let show_filename = grep_config.with_filename.unwrap_or(paths.len() > 1);
+    let mut out = io::stdout().lock();
+
+    let mut total = 0;
+    for path in paths.iter() {
+        for line_match in grep_text(patterns, path, grep_config)? {
+            if show_filename {
+                write!(out, "{}:", path.display())?;
+            }
+            let offset = line_match.offset.saturating_add(grep_config.base);
+            writeln!(
+                out,
+                "{}:{}: {}",
+                line_match.line,
+                util::paint(offset, util::CYAN, grep_config.color),
+                util::escape_bytes(&line_match.bytes),
+            )?;
+            total += 1;
+        }
+    }
+
+    Ok(total)
+}
+
+/// A line found by `grep_text()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LineMatch {
+    /// Number of the line, counted from 1.
+    pub line: usize,
+    /// Offset of the first match in the line, counted from the start
+    /// of the file.
+    pub offset: u64,
+    /// The line itself, without the newline.
+    pub bytes: Vec<u8>,
+}
+
+/// Split `filename` on `\n` and match every line on its own, like text
+/// grep, so `^` and `$` match at the ends of the lines. The patterns
+/// are regexes with `grep_config.regex` and literal bytes otherwise,
+/// either way matched with the regex engine. Return the matching
+/// lines.
+pub fn grep_text<P: AsRef<[u8]>>(
+    patterns: &[P],
+    filename: &Path,
+    grep_config: &GrepConfig,
+) -> Result<Vec<LineMatch>, io::Error> {
+    let regexes = patterns
+        .iter()
+        .map(|pattern| {
+            if grep_config.regex {
+                return util::compile_regex(pattern.as_ref(), grep_config.case_insensitive);
+            }
+            let escaped: String = grep_config.encoding
+                .encode(pattern.as_ref())?
+                .iter()
+                .map(|b| format!("\\x{:02x}", b))
+                .collect();
+            util::compile_regex(escaped.as_bytes(), grep_config.case_insensitive)
+        })
+        .collect::<Result<Vec<_>, io::Error>>()?;
+    let mut reader = util::open_file(filename)?.file;
+
+    let mut ret = Vec::new();
+    let mut line = Vec::new();
+    let mut line_start = 0;
+    for number in 1.. {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            break;
+        }
+        if line.last() == Some(&b'\n') {
+            line.pop();
+        }
+        let first = regexes.iter().filter_map(|regex| regex.find(&line)).map(|m| m.start()).min();
+        if let Some(start) = first {
+            ret.push(LineMatch { line: number, offset: line_start + start as u64, bytes: line.clone() });
+        }
+        line_start += read as u64;
+    }
+
+    Ok(ret)
+}
+
+/// A match found by `grep_concat()`. Its offset is counted from the
+/// start of the first file, `files` are the ones it lies in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConcatMatch {
+    pub found: FoundMatch,
+    pub files: Vec<PathBuf>,
+}
+
+/// Search `filenames` as if they were a single file, one after another
+/// in the given order, so matches straddling the end of a file and the
+/// start of the next one are found too, e.g. in the parts of a split
+/// archive. Only literal patterns are supported and the files aren't
+/// filtered by their type.
+pub fn grep_concat<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<Vec<ConcatMatch>, io::Error> {
+    if grep_config.regex {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Files can't be searched as one with regex",
+        ));
+    }
+    if grep_config.file_type != FileType::All {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Files searched as one can't be filtered by file_type",
+        ));
+    }
+    let paths = search_paths(filenames, grep_config)?;
+    let mut reader = ConcatReader::open(&paths)?;
+    let matchers = build_matchers(patterns, grep_config)?;
+
+    let mut found_matches = Vec::new();
+    for matcher in matchers.iter() {
+        let Matcher::Literal(searcher) = matcher else {
+            unreachable!("regex is rejected above");
+        };
+        reader.rewind()?;
+        let len = searcher.pattern().len();
+        found_matches.extend(searcher.search(&mut reader).map(|offset| FoundMatch { offset, len }));
+    }
+    found_matches.sort_by(|a, b| a.offset.cmp(&b.offset).then(b.len.cmp(&a.len)));
+    found_matches.dedup_by_key(|found| found.offset);
+
+    Ok(found_matches
+        .into_iter()
+        .map(|found| {
+            let files = reader.files_spanning(found.offset, found.len)
+                .into_iter()
+                .map(Path::to_path_buf)
+                .collect();
+            ConcatMatch { found, files }
+        })
+        .collect())
+}
+
+/// Count the matches of any of `patterns` in `filename` like
+/// `count_command()` does, with the options of `grep_config`. Return
+/// the number of matches and the number of bytes they cover, which is
+/// the number of matches times the length of the pattern for a single
+/// literal pattern.
+pub fn count_file<P: AsRef<[u8]>>(
+    patterns: &[P],
+    filename: &Path,
+    grep_config: &GrepConfig,
+) -> Result<(usize, u64), io::Error> {
+    if !counts_directly(grep_config) {
+        let mut no_progress = |_, _| {};
+        let filenames = [filename];
+        let mut found = Vec::new();
+        for result in grep_iter(patterns, &filenames, grep_config, &mut no_progress)? {
+            found.extend(result?.1);
+        }
+        return Ok((found.len(), found.iter().map(|found| found.len as u64).sum()));
+    }
+    let matchers = build_matchers(patterns, grep_config)?;
+    let align = grep_config.align.unwrap_or(1).max(1);
+    count_opened(util::open_file(filename)?, &matchers, align, grep_config.word)
+}
+
+/// Count the matches of `matchers` in `file`, a single plain literal
+/// pattern through `count_opened_fast()`. Only for the options accepted
+/// by `counts_directly()`, which the fast path knows nothing about.
+fn count_opened(
+    mut file: util::OpenedFile,
+    matchers: &[Matcher],
+    align: u64,
+    word: bool,
+) -> Result<(usize, u64), io::Error> {
+    match matchers {
+        [Matcher::Literal(searcher)] => {
+            let file_len = util::file_len(file.file.get_ref())?;
+            let count = if util::longer_than_file(searcher.pattern().len(), file.file.get_ref())? {
+                0
+            } else if searcher.is_plain() && file_len >= FAST_COUNT_MIN_LEN {
+                count_opened_fast(searcher.pattern(), &mut file)?
+            } else {
+                searcher.find_matches(&mut file).count()
+            };
+            Ok((count, count as u64 * searcher.pattern().len() as u64))
+        }
+        // Matches of different patterns at the same offset count once,
+        // so they have to be collected to be merged
+        _ => {
+            let mut no_progress = |_, _| {};
+            let mut progress = util::Progress::new(0, &mut no_progress);
+            let mut counts = vec![0; matchers.len()];
+            let found = search_file(file, matchers, align, word, usize::MAX, &mut counts, &mut progress)?.1;
+            Ok((found.len(), found.iter().map(|found| found.len as u64).sum()))
+        }
+    }
+}
+
+/// Count the occurrences of `pattern` in `reader` from its current
+/// position, overlapping ones too, like `grep()` finds them. Unlike
+/// `grep()` the offsets aren't kept, so any number of matches is
+/// counted in constant memory.
+pub fn count_matches<R: Read + Seek>(pattern: &[u8], reader: R) -> Result<usize, io::Error> {
+    Ok(Searcher::new(pattern).search(reader).count())
+}
+
+/// Count the occurrences of `pattern` in `filename`, overlapping ones
+/// too, as fast as possible: the file is mapped into memory and
+/// searched with Boyer-Moore-Horspool, without keeping any offsets.
+/// Inputs which can't be mapped, like pipes or files in `/proc`, are
+/// read and counted like `count_matches()` does instead.
+pub fn count_matches_fast(pattern: &[u8], filename: &Path) -> Result<usize, io::Error> {
+    count_opened_fast(pattern, &mut util::open_file(filename)?)
+}
+
+fn count_opened_fast(pattern: &[u8], file: &mut util::OpenedFile) -> Result<usize, io::Error> {
+    let metadata = file.file.get_ref().metadata()?;
+    // Files in /proc claim to be empty, mapping them would find nothing
+    if metadata.is_file() && metadata.len() > 0 {
+        // SAFETY: the map is only read. A file truncated by someone else
+        // while it's counted can still crash the process, which is the
+        // price of mapping it
+        if let Ok(map) = unsafe { memmap2::Mmap::map(file.file.get_ref()) } {
+            return Ok(util::count_horspool(&map, pattern));
+        }
+    }
+
+    Ok(Searcher::new(pattern).find_matches(file).count())
+}
+
+/// Check if any of `matchers` matches `file`, stopping at the first
+/// match. With a `range` only the matches lying wholly within it
+/// count.
+fn matches_within(
+    file: &mut util::OpenedFile,
+    matchers: &[Matcher],
+    grep_config: &GrepConfig,
+    range: Option<Range<usize>>,
+) -> Result<bool, io::Error> {
+    let align = grep_config.align.unwrap_or(1).max(1);
+    let range = range.map(|range| range.start as u64..range.end as u64);
+    let inside = |found: &FoundMatch| match &range {
+        Some(range) => found.offset >= range.start && found.offset + found.len as u64 <= range.end,
+        None => true,
+    };
+    // Matches of a matcher come sorted, so there's no point in looking
+    // past the end once every matcher got there
+    let mut past_end = vec![false; matchers.len()];
+    let mut found = false;
+    let mut on_match = |i: usize, found_match: FoundMatch| {
+        if range.as_ref().is_some_and(|range| found_match.offset >= range.end) {
+            past_end[i] = true;
+            return past_end.contains(&false);
+        }
+        found = inside(&found_match);
+        !found
+    };
+    scan_matchers(&mut file.file, matchers, align, grep_config.word, &mut on_match, &mut |_| Ok(()))?;
+
+    Ok(found)
+}
+
+/// Expand the globs in `filenames` ourselves, so it doesn't depend on
+/// the shell, and replace directories with the files in them when
+/// searching recursively, keeping only the included files.
+fn search_paths<T: AsRef<Path>>(
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<Vec<PathBuf>, io::Error> {
+    let filenames = util::expand_globs(filenames)?;
     if grep_config.recursive {
-        files = util::open_all_directories(filenames)?;
-        paths = files.iter().map(|path| path.as_path()).collect::<Vec<&Path>>();
+        let filter = util::NameFilter::new(&grep_config.include, &grep_config.exclude)?;
+        util::open_all_directories(&filenames, grep_config.skip_hidden, &filter, grep_config.max_depth)
+    } else {
+        Ok(filenames)
+    }
+}
+
+/// Write results returned by `grep_matches()` to `out` in the format
+/// selected by `grep_config`, the same way the grep command does.
+pub fn write_results<W: Write>(
+    out: &mut W,
+    results: &[(PathBuf, Vec<FoundMatch>)],
+    grep_config: &GrepConfig,
+) -> Result<GrepSummary, io::Error> {
+    write_results_iter(out, results.iter().cloned().map(Ok), grep_config)
+}
+
+/// Same as `write_results()`, except the results of every file are
+/// written as soon as the iterator yields them, e.g. from `grep_iter()`.
+/// The output is exactly the same. JSON output and grouping by
+/// directory still need all the results before anything is written.
+/// The bytes of the matches are read through the files kept open in
+/// the results, e.g. by `GrepIter::searched_files()`, and only the
+/// files that aren't are opened again.
+pub fn write_results_iter<W, I, R>(
+    out: &mut W,
+    results: I,
+    grep_config: &GrepConfig,
+) -> Result<GrepSummary, io::Error>
+where
+    W: Write,
+    I: IntoIterator<Item = Result<R, io::Error>>,
+    R: Into<SearchedFile>,
+{
+    let results = results.into_iter().map(|result| result.map(Into::into));
+    if grep_config.json {
+        let results = results.collect::<Result<Vec<_>, _>>()?;
+        let shifted: Vec<_> = results
+            .iter()
+            .map(|searched| {
+                let found = searched.matches
+                    .iter()
+                    .map(|m| FoundMatch { offset: m.offset.saturating_add(grep_config.base), ..*m })
+                    .collect();
+                (searched.path.clone(), found)
+            })
+            .collect();
+        writeln!(out, "{}", grep_results_to_json(&shifted))?;
+        return Ok(GrepSummary {
+            files_scanned: results.len(),
+            files_matched: results.iter().filter(|searched| !searched.matches.is_empty()).count(),
+            matches: results.iter().map(|searched| searched.matches.len()).sum(),
+        });
+    }
+
+    // Only the formatted matches are written, so scripts get exactly
+    // what they asked for
+    if let Some(format) = &grep_config.format {
+        let mut summary = GrepSummary::default();
+        for result in results {
+            let SearchedFile { path: filename, matches: found, file } = result?;
+            summary.files_scanned += 1;
+            summary.files_matched += usize::from(!found.is_empty());
+            summary.matches += found.len();
+            if found.is_empty() {
+                continue;
+            }
+            let mut file = file;
+            for &found in found.iter() {
+                let file = match format.reads_match() {
+                    true => Some(kept_or_opened(&mut file, &filename)?),
+                    false => None,
+                };
+                out.write_all(&format.expand(file, &filename, found, grep_config.base)?)?;
+            }
+        }
+        return Ok(summary);
+    }
+
+    // Files are searched in the order they were found in, so they have
+    // to be sorted to get every directory in one place
+    let mut grouped;
+    let mut in_order;
+    let results: &mut dyn Iterator<Item = _> = if grep_config.group_by_dir {
+        let mut collected = results.collect::<Result<Vec<_>, _>>()?;
+        collected.sort_by(|a, b| a.path.parent().cmp(&b.path.parent()));
+        grouped = collected.into_iter().map(Ok);
+        &mut grouped
     } else {
-        paths = filenames.iter().map(|path| path.as_ref()).collect();
+        in_order = results;
+        &mut in_order
+    };
+
+    // Look one file ahead to know if there's more than one of them
+    let mut results = results.peekable();
+    let first = results.next();
+    let show_filename = grep_config.with_filename.unwrap_or_else(|| results.peek().is_some());
+    let results = first.into_iter().chain(results);
+
+    // Files without matches are held back until we know that something
+    // was found at all, otherwise we only print "Nothing found"
+    let mut pending_empty: Vec<SearchedFile> = Vec::new();
+    let mut written = 0;
+    let mut last_dir = None;
+    let mut summary = GrepSummary::default();
+    let mut bytes = 0;
+    for result in results {
+        let mut searched = result?;
+        summary.files_scanned += 1;
+        if searched.matches.is_empty() {
+            // Nothing is read from it, so it doesn't stay open
+            searched.file = None;
+            pending_empty.push(searched);
+            continue;
+        }
+        for mut empty in pending_empty.drain(..) {
+            write_grouped(out, written, &mut last_dir, &mut empty, show_filename, grep_config)?;
+            written += 1;
+        }
+        write_grouped(out, written, &mut last_dir, &mut searched, show_filename, grep_config)?;
+        written += 1;
+        summary.files_matched += 1;
+        summary.matches += searched.matches.len();
+        bytes += searched.matches.iter().map(|found| found.len as u64).sum::<u64>();
     }
 
-    // Get results
-    let results = grep(pattern, &paths)?;
-    let is_empty: bool = results.iter().all(|e| e.1.is_empty());
-    if is_empty {
+    if written == 0 {
         if !grep_config.quiet {
-            println!("Nothing found");
+            writeln!(out, "Nothing found")?;
+        }
+    } else {
+        for mut empty in pending_empty.drain(..) {
+            write_grouped(out, written, &mut last_dir, &mut empty, show_filename, grep_config)?;
+            written += 1;
+        }
+    }
+
+    if grep_config.count_bytes && !grep_config.quiet && written != 0 {
+        writeln!(out)?;
+        writeln!(out, "Total: {} matches covering {} bytes", summary.matches, bytes)?;
+    }
+    if grep_config.count_files && !grep_config.quiet {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "Scanned {} files, {} with matches",
+            summary.files_scanned,
+            summary.files_matched,
+        )?;
+    }
+    
+    Ok(summary)
+}
+
+/// Same as `write_file_results()`, except when grouping by directory a
+/// header is written before the first file of every directory.
+fn write_grouped<W: Write>(
+    out: &mut W,
+    written: usize,
+    last_dir: &mut Option<PathBuf>,
+    searched: &mut SearchedFile,
+    show_filename: bool,
+    grep_config: &GrepConfig,
+) -> Result<(), io::Error> {
+    if grep_config.group_by_dir {
+        let dir = searched.path.parent().unwrap_or(Path::new("")).to_path_buf();
+        if last_dir.as_ref() != Some(&dir) {
+            if written != 0 {
+                writeln!(out)?;
+            }
+            writeln!(out, "== {} ==", dir.display())?;
+            *last_dir = Some(dir);
+            return write_file_results(out, true, searched, show_filename, grep_config);
+        }
+    }
+
+    write_file_results(out, written == 0, searched, show_filename, grep_config)
+}
+
+/// Pretty print the results of a single file. Every file except the
+/// first one is separated from the previous one with an empty line.
+/// Without the filename header, files without matches are left out
+/// and nothing separates the files. The bytes around the matches are
+/// read through the file kept open in `searched`, which is only opened
+/// again if it wasn't kept.
+fn write_file_results<W: Write>(
+    out: &mut W,
+    first: bool,
+    searched: &mut SearchedFile,
+    show_filename: bool,
+    grep_config: &GrepConfig,
+) -> Result<(), io::Error> {
+    let SearchedFile { path: filename, matches: offsets, file } = searched;
+    if !show_filename && offsets.is_empty() {
+        return Ok(());
+    }
+    if show_filename {
+        if !first {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}:", filename.display())?;
+    }
+    // Offsets are printed relative to the base address
+    let base = grep_config.base;
+    let color = grep_config.color;
+    if let Some(context) = grep_config.context {
+        for (n, found) in offsets.iter().enumerate() {
+            let file = kept_or_opened(file, filename)?;
+            let (start, bytes) = util::read_window(
+                file,
+                found.offset,
+                found.len,
+                context.before,
+                context.after,
+            )?;
+            let offset = found.offset.saturating_add(base);
+            let marked = offset..offset.saturating_add(found.len as u64);
+            writeln!(out, "{}:", util::paint(offset, util::CYAN, color))?;
+            let dump = util::hexdump_colored(start.saturating_add(base), &bytes, marked, color);
+            write!(out, "{}", dump)?;
+            if n != offsets.len() - 1 {
+                writeln!(out)?;
+            }
         }
+        writeln!(out)?;
         return Ok(());
     }
+    if grep_config.line {
+        for found in offsets.iter() {
+            let file = kept_or_opened(file, filename)?;
+            let (start, bytes) = util::read_line(file, found.offset, found.len, LINE_WINDOW)?;
+            let match_start = (found.offset - start) as usize;
+            let match_end = (match_start + found.len).min(bytes.len());
+            writeln!(
+                out,
+                "{}: {}{}{}",
+                util::paint(found.offset.saturating_add(base), util::CYAN, color),
+                util::escape_bytes(&bytes[..match_start]),
+                util::paint(util::escape_bytes(&bytes[match_start..match_end]), util::RED, color),
+                util::escape_bytes(&bytes[match_end..]),
+            )?;
+        }
+        return Ok(());
+    }
+    if grep_config.only_matching {
+        for found in offsets.iter() {
+            let file = kept_or_opened(file, filename)?;
+            let (_, bytes) = util::read_context(file, found.offset, found.len, 0)?;
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let offset = util::paint(found.offset.saturating_add(base), util::CYAN, color);
+            writeln!(out, "{}: {}", offset, util::paint(hex.join(" "), util::RED, color))?;
+        }
+        return Ok(());
+    }
+    for (n, found) in offsets.iter().enumerate() {
+        match n.checked_sub(1) {
+            Some(previous) if grep_config.deltas => {
+                let delta = found.offset - offsets[previous].offset;
+                write!(out, "{}", util::paint(format!("+{}", delta), util::CYAN, color))?;
+            }
+            _ => write!(out, "{}", util::paint(found.offset.saturating_add(base), util::CYAN, color))?,
+        }
+        if n != offsets.len() - 1 {
+            write!(out, ", ")?;
+        }
+    }
+    writeln!(out)?;
+
+    Ok(())
+}
+
+/// The file kept open in a `SearchedFile`, or `filename` opened again
+/// into `file` if it wasn't kept.
+fn kept_or_opened<'a>(file: &'a mut Option<fs::File>, filename: &Path) -> Result<&'a mut fs::File, io::Error> {
+    match file {
+        Some(file) => Ok(file),
+        None => Ok(file.insert(util::open_file(filename)?.file.into_inner())),
+    }
+}
+
+/// Serialize results returned by `grep_matches()` as a JSON array of
+/// `{ "file": ..., "offsets": [...] }` objects.
+pub fn grep_results_to_json(results: &[(PathBuf, Vec<FoundMatch>)]) -> String {
+    let files: Vec<_> = results
+        .iter()
+        .map(|(filename, found)| {
+            serde_json::json!({
+                "file": filename.to_string_lossy(),
+                "offsets": found.iter().map(|m| m.offset).collect::<Vec<_>>(),
+            })
+        })
+        .collect();
+
+    serde_json::Value::Array(files).to_string()
+}
+
+/// Read the raw bytes of `filename` to be used as a search pattern,
+/// e.g. a whole header template. Return an error if it's empty.
+pub fn read_pattern_file(filename: &Path) -> Result<Vec<u8>, io::Error> {
+    let pattern = fs::read(filename)?;
+    if pattern.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Pattern file {} is empty", filename.display()),
+        ));
+    }
+
+    Ok(pattern)
+}
+
+/// Read a list of file paths or patterns from `filename`, one per
+/// line. Empty lines and lines starting with `#` are skipped.
+pub fn read_list_file(filename: &Path) -> Result<Vec<String>, io::Error> {
+    let list = fs::read_to_string(filename)?
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    Ok(list)
+}
+
+/// Check if `pattern` occurs in `filename`. Unlike `grep()` this stops
+/// at the first match instead of finding all of them.
+pub fn contains(pattern: &[u8], filename: &Path) -> Result<bool, io::Error> {
+    let mut file = util::open_file(filename)?;
+    let found = util::find_matches(&mut file, pattern).next().is_some();
+
+    Ok(found)
+}
+
+/// Opposite of `contains()`, except with a `range` only the matches
+/// lying wholly within it count, e.g. to make sure a region is free of
+/// a pattern before replacing it elsewhere.
+pub fn absent(
+    pattern: &[u8],
+    filename: &Path,
+    range: Option<Range<usize>>,
+) -> Result<bool, io::Error> {
+    let mut file = util::open_file(filename)?;
+    let matchers = [Matcher::Literal(Searcher::new(pattern))];
+
+    Ok(!matches_within(&mut file, &matchers, &GrepConfig::default(), range)?)
+}
+
+/// Find all occurrences of `pattern` in `filenames`. Return a Vec of
+/// matched offsets. Files are read until EOF instead of up to their
+/// reported size, so block devices like `/dev/sda` can be searched too.
+/// Files shorter than the pattern aren't read at all, there's nothing
+/// to find in them.
+pub fn grep<T: AsRef<Path>>(
+    pattern: &[u8],
+    filenames: &[T],
+) -> Result<Vec<(PathBuf, Vec<u64>)>, io::Error> {
+    let ret = grep_matches(pattern, filenames, &GrepConfig::default())?
+        .into_iter()
+        .map(|(path, found)| (path, found.iter().map(|m| m.offset).collect()))
+        .collect();
+
+    Ok(ret)
+}
+
+/// Same as `grep()`, except every match also carries its length, so
+/// the caller knows where it ends. Matching options are taken from
+/// `grep_config`. In regex mode every file is read whole into memory.
+pub fn grep_matches<T: AsRef<Path>>(
+    pattern: &[u8],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<Vec<(PathBuf, Vec<FoundMatch>)>, io::Error> {
+    grep_patterns(&[pattern], filenames, grep_config)
+}
+
+/// Same as `grep_matches()`, except it searches for all of `patterns`
+/// at once. Offsets in every file are guaranteed to be sorted and
+/// unique. When matches of different patterns start at the same
+/// offset, only the longest one is kept.
+pub fn grep_patterns<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<Vec<(PathBuf, Vec<FoundMatch>)>, io::Error> {
+    grep_patterns_with_progress(patterns, filenames, grep_config, &mut |_, _| {})
+}
+
+/// Same as `grep_patterns()`, except `progress` is periodically called
+/// with the number of bytes searched so far and the total number of
+/// bytes to search in the files reached so far. Every file is opened
+/// once and then searched once per pattern.
+pub fn grep_patterns_with_progress<T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<Vec<(PathBuf, Vec<FoundMatch>)>, io::Error> {
+    grep_iter(patterns, filenames, grep_config, progress)?.collect()
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Most files `grep_parallel()` had open at once in its last call
+    /// made by the current thread.
+    static MAX_OPEN_FILES: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Same as `grep_patterns()`, except the files are searched by
+/// `grep_config.jobs` threads at once. Every thread has at most one
+/// file open at a time, so no more than `jobs` files are ever open,
+/// however many files there are. The results are still in the order
+/// of `filenames`. A `jobs` of 0 is treated as 1.
+pub fn grep_parallel<T: AsRef<Path> + Sync, P: AsRef<[u8]>>(
+    patterns: &[P],
+    filenames: &[T],
+    grep_config: &GrepConfig,
+) -> Result<Vec<(PathBuf, Vec<FoundMatch>)>, io::Error> {
+    let matchers = build_matchers(patterns, grep_config)?;
+    let align = grep_config.align.unwrap_or(1).max(1);
+    let next = AtomicUsize::new(0);
+    let open_files = AtomicUsize::new(0);
+    let max_open_files = AtomicUsize::new(0);
+
+    let mut results: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..grep_config.jobs.max(1))
+            .map(|_| scope.spawn(|| {
+                let mut ret = Vec::new();
+                // Files are handed out one by one, a worker only takes
+                // the next one after it's done with the previous one
+                let mut no_progress = |_, _| {};
+                loop {
+                    let n = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(filename) = filenames.get(n) else {
+                        break;
+                    };
+                    let open = open_files.fetch_add(1, Ordering::SeqCst) + 1;
+                    max_open_files.fetch_max(open, Ordering::SeqCst);
+                    let res = util::open_file(filename.as_ref()).and_then(|mut file| {
+                        if grep_config.file_type != FileType::All
+                            && util::looks_binary(&mut file)?
+                                != (grep_config.file_type == FileType::Binary)
+                        {
+                            return Ok(None);
+                        }
+                        let mut progress = util::Progress::new(0, &mut no_progress);
+                        let mut counts = vec![0; matchers.len()];
+                        let word = grep_config.word;
+                        search_file(file, &matchers, align, word, usize::MAX, &mut counts, &mut progress)
+                            .map(Some)
+                    });
+                    open_files.fetch_sub(1, Ordering::SeqCst);
+                    ret.push((n, res));
+                }
+                ret
+            }))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Grep worker panicked"))
+            .collect()
+    });
+    #[cfg(test)]
+    MAX_OPEN_FILES.with(|max| max.set(max_open_files.load(Ordering::SeqCst)));
+
+    // Workers finish in any order
+    results.sort_by_key(|&(n, _)| n);
+    results
+        .into_iter()
+        .filter_map(|(_, res)| res.transpose())
+        .collect()
+}
+
+/// The matches found in a single file, together with the file itself
+/// while it's still open, so the bytes of the matches can be printed
+/// without opening it again.
+#[derive(Debug)]
+pub struct SearchedFile {
+    pub path: PathBuf,
+    pub matches: Vec<FoundMatch>,
+    pub file: Option<fs::File>,
+}
+
+impl From<(PathBuf, Vec<FoundMatch>)> for SearchedFile {
+    fn from((path, matches): (PathBuf, Vec<FoundMatch>)) -> Self {
+        SearchedFile { path, matches, file: None }
+    }
+}
+
+/// Iterator returned by the `grep_iter()` function. Every file is only
+/// searched when the iterator gets to it, in the order of the given
+/// filenames. Files not matching the configured `FileType` are skipped.
+pub struct GrepIter<'a, T: AsRef<Path>> {
+    matchers: Vec<Matcher>,
+    files: util::OpenFiles<'a, T>,
+    file_type: FileType,
+    align: u64,
+    word: bool,
+    pattern_counts: Vec<usize>,
+    checkpoint: Option<PathBuf>,
+    resume: Option<Checkpoint>,
+    decompress: bool,
+    limit_total: Option<usize>,
+    found_total: usize,
+    adjacent: AdjacentFilter,
+    progress: util::Progress<'a>,
+}
+
+impl<'a, T: AsRef<Path>> GrepIter<'a, T> {
+    /// Number of matches of every pattern in the files searched so far,
+    /// in the order of the patterns. Matches of different patterns at
+    /// the same offset all count, even though only one is returned.
+    pub fn pattern_counts(&self) -> &[usize] {
+        &self.pattern_counts
+    }
+
+    /// Same as iterating, except the searched files are kept open in
+    /// the results, so the bytes of the matches can be read without
+    /// opening the files again, see `write_results_iter()`.
+    pub fn searched_files(
+        &mut self,
+    ) -> impl Iterator<Item = Result<SearchedFile, io::Error>> + use<'_, 'a, T> {
+        std::iter::from_fn(move || self.next_searched())
+    }
+
+    fn next_searched(&mut self) -> Option<Result<SearchedFile, io::Error>> {
+        let remaining = match self.limit_total {
+            Some(limit) if self.found_total >= limit => return None,
+            Some(limit) => limit - self.found_total,
+            None => usize::MAX,
+        };
+        loop {
+            let mut file = match self.files.next() {
+                Some(Ok(file)) => file,
+                Some(Err(e)) => return Some(Err(e)),
+                None => return self.finish_checkpoint().err().map(Err),
+            };
+            if self.file_type != FileType::All {
+                let is_binary = match util::looks_binary(&mut file) {
+                    Ok(is_binary) => is_binary,
+                    Err(e) => return Some(Err(e)),
+                };
+                if is_binary != (self.file_type == FileType::Binary) {
+                    continue;
+                }
+            }
+            // The total grows file by file, so no file is opened twice
+            match util::file_len(file.file.get_ref()) {
+                Ok(len) => self.progress.add_total(len),
+                Err(e) => return Some(Err(e)),
+            }
+            // The filter and the printer read next to the matches
+            // through the same opened file
+            let mut opened = match file.file.get_ref().try_clone() {
+                Ok(opened) => opened,
+                Err(e) => return Some(Err(e)),
+            };
+            if let Some(checkpoint_filename) = self.checkpoint.clone() {
+                // Files before the one in the checkpoint were already done
+                let resumed = match self.resume.take() {
+                    Some(resumed) if resumed.path != file.path => {
+                        self.resume = Some(resumed);
+                        continue;
+                    }
+                    resumed => resumed.unwrap_or_default(),
+                };
+                let res = self.search_checkpointed(file, &checkpoint_filename, resumed);
+                return Some(res.map(|(path, matches)| SearchedFile { path, matches, file: Some(opened) }));
+            }
+            // Filtered out matches mustn't count towards the limit
+            let limit = if self.adjacent.is_empty() { remaining } else { usize::MAX };
+            let res = if self.decompress {
+                search_decompressed(
+                    file,
+                    &self.matchers,
+                    limit,
+                    &mut self.pattern_counts,
+                    &mut self.progress,
+                )
+            } else {
+                search_file(
+                    file,
+                    &self.matchers,
+                    self.align,
+                    self.word,
+                    limit,
+                    &mut self.pattern_counts,
+                    &mut self.progress,
+                )
+            };
+            let res = res.and_then(|(path, found_matches)| {
+                let mut matches = self.adjacent.filter(&mut opened, found_matches)?;
+                matches.truncate(remaining);
+                // Offsets in decompressed data don't point into the file
+                let file = (!self.decompress).then_some(opened);
+                Ok(SearchedFile { path, matches, file })
+            });
+            if let Ok(searched) = &res {
+                self.found_total += searched.matches.len();
+            }
+            return Some(res);
+        }
+    }
+
+    /// Remove the checkpoint after the whole search is done, it's not
+    /// needed anymore. Return an error if the file to resume from
+    /// wasn't among the searched ones.
+    fn finish_checkpoint(&mut self) -> Result<(), io::Error> {
+        let Some(checkpoint_filename) = self.checkpoint.take() else {
+            return Ok(());
+        };
+        if let Some(resumed) = self.resume.take() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} from the checkpoint wasn't searched", resumed.path.display()),
+            ));
+        }
+        for filename in [Checkpoint::matches_path(&checkpoint_filename), checkpoint_filename] {
+            match fs::remove_file(filename) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Search `file` for the only pattern, saving a checkpoint at its
+    /// start and then every `CHECKPOINT_INTERVAL` bytes. With `resumed`
+    /// the search continues from there, keeping its matches.
+    fn search_checkpointed(
+        &mut self,
+        mut file: util::OpenedFile,
+        checkpoint_filename: &Path,
+        resumed: Checkpoint,
+    ) -> Result<(PathBuf, Vec<FoundMatch>), io::Error> {
+        let Matcher::Literal(searcher) = &self.matchers[0] else {
+            unreachable!("Regexes are never checkpointed");
+        };
+        let path = PathBuf::from(file.path);
+        let Checkpoint { offset: start, matches, .. } = resumed;
+        let mut writer = CheckpointWriter::open(checkpoint_filename, &path, matches.len())?;
+        writer.save(start, &[])?;
+        // Only the matches from the saved offset on weren't found yet,
+        // the byte before it is read for word boundaries
+        if start > 0 {
+            file.file.seek(io::SeekFrom::Start(start - 1))?;
+        }
+        let mut saved = matches.len();
+        let matches = std::cell::RefCell::new(matches);
+        let len = searcher.pattern().len();
+        let mut on_match = |_, offset| {
+            matches.borrow_mut().push(FoundMatch { offset, len });
+            true
+        };
+
+        let base = self.progress.done();
+        let progress = &mut self.progress;
+        let mut saved_at = start / CHECKPOINT_INTERVAL;
+        let mut on_progress = |searched: u64| {
+            progress.set(base + searched.saturating_sub(start));
+            if searched / CHECKPOINT_INTERVAL == saved_at {
+                return Ok(());
+            }
+            saved_at = searched / CHECKPOINT_INTERVAL;
+            let matches = matches.borrow();
+            writer.save(searched, &matches[saved..])?;
+            saved = matches.len();
+            Ok(())
+        };
+        searcher::search_stream_all(&[searcher], &mut file.file, start, &mut on_match, &mut on_progress)?;
+        let matches = matches.into_inner();
+        self.pattern_counts[0] += matches.len();
+
+        Ok((path, matches))
+    }
+}
+
+/// Bytes which mustn't directly precede or follow a match, see
+/// `GrepConfigBuilder::not_after()` and `not_before()`.
+#[derive(Default, Debug, Clone)]
+struct AdjacentFilter {
+    not_after: Option<Vec<u8>>,
+    not_before: Option<Vec<u8>>,
+}
+
+impl AdjacentFilter {
+    fn is_empty(&self) -> bool {
+        self.not_after.is_none() && self.not_before.is_none()
+    }
+
+    /// Drop the `matches` in `file` which are preceded by `not_after` or
+    /// followed by `not_before`. Only the few bytes next to every match
+    /// are read.
+    fn filter(&self, file: &mut fs::File, matches: Vec<FoundMatch>) -> Result<Vec<FoundMatch>, io::Error> {
+        if self.is_empty() || matches.is_empty() {
+            return Ok(matches);
+        }
+        let mut window = Vec::new();
+        let mut kept = Vec::new();
+        for found in matches {
+            if let Some(not_after) = &self.not_after {
+                let len = not_after.len() as u64;
+                if found.offset >= len
+                    && read_window(file, found.offset - len, len, &mut window)? == not_after
+                {
+                    continue;
+                }
+            }
+            if let Some(not_before) = &self.not_before {
+                let end = found.offset + found.len as u64;
+                if read_window(file, end, not_before.len() as u64, &mut window)? == not_before {
+                    continue;
+                }
+            }
+            kept.push(found);
+        }
+
+        Ok(kept)
+    }
+}
+
+/// Read up to `len` bytes of `file` at `offset` into `buf`, fewer when
+/// the file ends before.
+fn read_window<'a>(
+    file: &mut fs::File,
+    offset: u64,
+    len: u64,
+    buf: &'a mut Vec<u8>,
+) -> Result<&'a [u8], io::Error> {
+    file.seek(io::SeekFrom::Start(offset))?;
+    buf.clear();
+    file.take(len).read_to_end(buf)?;
+
+    Ok(buf)
+}
+
+/// A single pattern of a search, prepared once before any file is
+/// searched.
+enum Matcher {
+    Literal(Searcher),
+    Regex(Regex),
+}
+
+impl<T: AsRef<Path>> Iterator for GrepIter<'_, T> {
+    type Item = Result<(PathBuf, Vec<FoundMatch>), io::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_searched().map(|res| res.map(|searched| (searched.path, searched.matches)))
+    }
+}
+
+/// Search `file` for all of `matchers` in a single pass. Offsets in
+/// the result are sorted and unique, the longest match wins, and only
+/// the first `limit` of them are kept. The matches of every matcher are
+/// added to its entry in `counts`.
+fn search_file(
+    mut file: util::OpenedFile,
+    matchers: &[Matcher],
+    align: u64,
+    word: bool,
+    limit: usize,
+    counts: &mut [usize],
+    progress: &mut util::Progress,
+) -> Result<(PathBuf, Vec<FoundMatch>), io::Error> {
+    let path = PathBuf::from(file.path);
+    let file_len = util::file_len(file.file.get_ref())?;
+    let base = progress.done();
+    let mut on_progress = |searched| {
+        progress.set(base + searched);
+        Ok(())
+    };
+    let found_matches =
+        collect_matches(&mut file.file, matchers, align, word, limit, counts, &mut on_progress)?;
+    progress.set(base + file_len);
+
+    Ok((path, found_matches))
+}
+
+/// Same as `search_file()`, except the gzip decompressed contents of
+/// `file` are searched. Only literal patterns can be searched in a
+/// stream.
+fn search_decompressed(
+    mut file: util::OpenedFile,
+    matchers: &[Matcher],
+    limit: usize,
+    counts: &mut [usize],
+    progress: &mut util::Progress,
+) -> Result<(PathBuf, Vec<FoundMatch>), io::Error> {
+    if matchers.iter().any(|matcher| matches!(matcher, Matcher::Regex(_))) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Decompressed data can't be searched with regex",
+        ));
+    }
+    let path = PathBuf::from(file.path);
+    let file_len = util::file_len(file.file.get_ref())?;
+    let base = progress.done();
+    let reader = util::decompressed(&mut file.file)?;
+    let found_matches = collect_matches(reader, matchers, 1, false, limit, counts, &mut |_| Ok(()))?;
+    progress.set(base + file_len);
+
+    Ok((path, found_matches))
+}
+
+/// Collect the matches of all of `matchers` in `reader` for
+/// `search_file()`, merged and limited like it describes.
+fn collect_matches<R: Read>(
+    reader: R,
+    matchers: &[Matcher],
+    align: u64,
+    word: bool,
+    limit: usize,
+    counts: &mut [usize],
+    on_progress: &mut dyn FnMut(u64) -> Result<(), io::Error>,
+) -> Result<Vec<FoundMatch>, io::Error> {
+    // The first `limit` matches of all the matchers are among the
+    // first `limit` of every single one, so a matcher is done once it
+    // has that many
+    let mut per_matcher = vec![Vec::new(); matchers.len()];
+    let mut done = 0;
+    let mut on_match = |i: usize, found| {
+        let found_matches: &mut Vec<FoundMatch> = &mut per_matcher[i];
+        if found_matches.len() < limit {
+            found_matches.push(found);
+            if found_matches.len() == limit {
+                done += 1;
+            }
+        }
+        done < matchers.len()
+    };
+    scan_matchers(reader, matchers, align, word, &mut on_match, on_progress)?;
+    for (found_matches, count) in per_matcher.iter().zip(counts.iter_mut()) {
+        *count += found_matches.len();
+    }
+    let mut found_matches = per_matcher.concat();
+    found_matches.sort_by(|a, b| a.offset.cmp(&b.offset).then(b.len.cmp(&a.len)));
+    found_matches.dedup_by_key(|found| found.offset);
+    found_matches.truncate(limit);
+
+    Ok(found_matches)
+}
+
+/// Search the rest of `reader` for all of `matchers` in a single pass,
+/// so it doesn't have to be seekable. `on_match` gets the index of the
+/// matcher and every match of it, in order, and the search stops as
+/// soon as it returns false. Literal patterns are searched while the
+/// data streams by, but a regex match can be arbitrarily long, so with
+/// any regex among `matchers` the data is read into memory first.
+fn scan_matchers<R: Read>(
+    mut reader: R,
+    matchers: &[Matcher],
+    align: u64,
+    word: bool,
+    on_match: &mut dyn FnMut(usize, FoundMatch) -> bool,
+    on_progress: &mut dyn FnMut(u64) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    let searchers: Vec<&Searcher> = matchers
+        .iter()
+        .filter_map(|matcher| match matcher {
+            Matcher::Literal(searcher) => Some(searcher),
+            Matcher::Regex(_) => None,
+        })
+        .collect();
+    if searchers.len() == matchers.len() {
+        let mut on_offset = |i: usize, offset| {
+            on_match(i, FoundMatch { offset, len: searchers[i].pattern().len() })
+        };
+        return searcher::search_stream_all(&searchers, reader, 0, &mut on_offset, on_progress);
+    }
 
-    // Pretty print
-    for (n, (filename, offsets)) in results.iter().enumerate() {
-        println!("{}:", filename.display());
-        for (n, offset) in offsets.iter().enumerate() {
-            print!("{}", offset);
-            if n != offsets.len() - 1 {
-                print!(", ");
+    let mut data = Vec::new();
+    reader.read_to_end(&mut data)?;
+    on_progress(data.len() as u64)?;
+    let is_word_byte = |offset: usize| data.get(offset).is_some_and(u8::is_ascii_alphanumeric);
+    for (i, matcher) in matchers.iter().enumerate() {
+        let found_matches: Box<dyn Iterator<Item = FoundMatch>> = match matcher {
+            Matcher::Literal(searcher) => {
+                let len = searcher.pattern().len();
+                Box::new(searcher.search_slice(&data).map(move |offset| FoundMatch { offset: offset as u64, len }))
+            }
+            Matcher::Regex(regex) => Box::new(
+                regex
+                    .find_iter(&data)
+                    .filter(|m| (m.start() as u64).is_multiple_of(align))
+                    .filter(|m| {
+                        !word || !(m.start().checked_sub(1).is_some_and(is_word_byte) || is_word_byte(m.end()))
+                    })
+                    .map(|m| FoundMatch { offset: m.start() as u64, len: m.len() }),
+            ),
+        };
+        for found in found_matches {
+            if !on_match(i, found) {
+                return Ok(());
             }
         }
-        println!("{}", if n != results.len() - 1 {"\n"} else {""});
     }
-    
+
     Ok(())
 }
 
-/// Find all occurrences of `pattern` in `filenames`. Return a Vec of
-/// matched offsets.
-pub fn grep<T: AsRef<Path>>(
-    pattern: &[u8],
-    filenames: &[T],
-) -> Result<Vec<(PathBuf, Vec<u64>)>, io::Error> {
-    let mut ret = Vec::new();
-    for mut file in util::open_files(filenames) {
-        ret.push((PathBuf::from(file.path), Vec::new()));
-        let found_matches: Vec<_> = util::find_matches(&mut file, pattern).collect();
-        for &offset in found_matches.iter() {
-            ret.last_mut().unwrap().1.push(offset);
-        }
+/// Prepare every pattern for searching with the options of
+/// `grep_config`.
+fn build_matchers<P: AsRef<[u8]>>(
+    patterns: &[P],
+    grep_config: &GrepConfig,
+) -> Result<Vec<Matcher>, io::Error> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            let pattern = pattern.as_ref();
+            if grep_config.regex {
+                let regex = util::compile_regex(pattern, grep_config.case_insensitive)?;
+                Ok(Matcher::Regex(regex))
+            } else {
+                let searcher = Searcher::new(&grep_config.encoding.encode(pattern)?)
+                    .ignore_case(grep_config.case_insensitive)
+                    .fold(grep_config.fold)
+                    .align(grep_config.align.unwrap_or(1))
+                    .word(grep_config.word);
+                Ok(Matcher::Literal(searcher))
+            }
+        })
+        .collect()
+}
+
+/// Lazy flavour of `grep_patterns_with_progress()`. Return an iterator
+/// yielding the results file by file, so they can be used before the
+/// whole search is done.
+pub fn grep_iter<'a, T: AsRef<Path>, P: AsRef<[u8]>>(
+    patterns: &'a [P],
+    filenames: &'a [T],
+    grep_config: &GrepConfig,
+    progress: &'a mut dyn FnMut(u64, u64),
+) -> Result<GrepIter<'a, T>, io::Error> {
+    let matchers = build_matchers(patterns, grep_config)?;
+    if grep_config.checkpoint.is_some() && (patterns.len() != 1 || grep_config.regex) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Only a single literal pattern can be checkpointed",
+        ));
     }
-    
-    Ok(ret)
+    let resume = match &grep_config.checkpoint {
+        Some(checkpoint_filename) if grep_config.resume => {
+            Some(Checkpoint::read(checkpoint_filename)?)
+        }
+        _ => None,
+    };
+    Ok(GrepIter {
+        matchers,
+        files: util::open_files(filenames),
+        file_type: grep_config.file_type,
+        align: grep_config.align.unwrap_or(1).max(1),
+        word: grep_config.word,
+        pattern_counts: vec![0; patterns.len()],
+        checkpoint: grep_config.checkpoint.clone(),
+        resume,
+        decompress: grep_config.decompress,
+        limit_total: grep_config.limit_total,
+        found_total: 0,
+        adjacent: AdjacentFilter {
+            not_after: grep_config.not_after.clone(),
+            not_before: grep_config.not_before.clone(),
+        },
+        progress: util::Progress::new(0, progress),
+    })
 }
 
 #[cfg(test)]
@@ -83,6 +2285,418 @@ mod tests {
         ]);
     }
 
+    #[test]
+    fn contains_test() {
+        assert!(contains(b"nya", Path::new("test_files/file_one")).unwrap());
+        assert!(!contains(b"meow", Path::new("test_files/file_one")).unwrap());
+        assert!(contains(b"\xfe", Path::new("test_files/file_three")).unwrap());
+
+        let files = ["test_files/file_three", "test_files/file_four"];
+        let cfg = GrepConfig { case_insensitive: true, ..Default::default() };
+        assert!(contains_command(&[b"nyan"], &files, &cfg).unwrap());
+        let cfg = GrepConfig { file_type: FileType::Text, ..Default::default() };
+        assert!(!contains_command(&[b"NYA"], &files, &cfg).unwrap());
+        let cfg = GrepConfig::default();
+        assert!(contains_command(&[&b"meow"[..], b"\xfe"], &files, &cfg).unwrap());
+    }
+
+    #[test]
+    fn grep_list_file_test() {
+        let files = read_list_file(Path::new("test_files/file_list")).unwrap();
+        assert_eq!(files, vec!["test_files/file_one", "test_files/file_two"]);
+        let patterns = read_list_file(Path::new("test_files/pattern_list")).unwrap();
+        assert_eq!(patterns, vec!["nya", "meow"]);
+
+        let res = grep_patterns(&patterns, &files, &GrepConfig::default()).unwrap();
+        let direct = grep_patterns(
+            &["nya", "meow"],
+            &["test_files/file_one", "test_files/file_two"],
+            &GrepConfig::default(),
+        ).unwrap();
+        assert_eq!(res, direct);
+    }
+
+    #[test]
+    fn grep_opens_files_once_test() {
+        let files = ["test_files/file_one", "test_files/file_two", "test_files/file_three"];
+        for patterns in [&["nya"][..], &["nya", "meow", "\x01"]] {
+            util::OPEN_FILE_CALLS.with(|calls| calls.set(0));
+            grep_patterns(patterns, &files, &GrepConfig::default()).unwrap();
+            assert_eq!(util::OPEN_FILE_CALLS.with(|calls| calls.get()), files.len());
+
+            util::OPEN_FILE_CALLS.with(|calls| calls.set(0));
+            contains_command(patterns, &files, &GrepConfig::default()).unwrap();
+            assert!(util::OPEN_FILE_CALLS.with(|calls| calls.get()) <= files.len());
+
+            // Filtering reuses the searched file
+            util::OPEN_FILE_CALLS.with(|calls| calls.set(0));
+            let cfg = GrepConfig::builder().not_after(b"x".to_vec()).not_before(b"x".to_vec()).build().unwrap();
+            let res = grep_patterns(patterns, &files, &cfg).unwrap();
+            assert_eq!(util::OPEN_FILE_CALLS.with(|calls| calls.get()), files.len());
+
+            // Printing the bytes of the matches reads them through the
+            // searched files
+            assert!(res.iter().any(|(_, found)| !found.is_empty()));
+            for cfg in [
+                GrepConfig::builder().context(2).build().unwrap(),
+                GrepConfig::builder().line(true).build().unwrap(),
+                GrepConfig::builder().only_matching(true).build().unwrap(),
+                GrepConfig::builder().format(GrepFormat::parse("{match}").unwrap()).build().unwrap(),
+                GrepConfig::builder().format(GrepFormat::parse("{offset}").unwrap()).build().unwrap(),
+            ] {
+                util::OPEN_FILE_CALLS.with(|calls| calls.set(0));
+                let mut progress = |_, _| {};
+                let mut results = grep_iter(patterns, &files, &cfg, &mut progress).unwrap();
+                write_results_iter(&mut Vec::new(), results.searched_files(), &cfg).unwrap();
+                assert_eq!(util::OPEN_FILE_CALLS.with(|calls| calls.get()), files.len());
+            }
+        }
+    }
+
+    #[test]
+    fn count_matches_test() {
+        for (pattern, filename) in [
+            (&b"nya"[..], "test_files/file_one"),
+            (b"20%", "test_files/file_two"),
+            (b"abc", "test_files/file_many_matches"),
+            (b"meow", "test_files/file_one"),
+        ] {
+            let count = count_matches(pattern, fs::File::open(filename).unwrap()).unwrap();
+            let res = grep(pattern, &[filename]).unwrap();
+            assert_eq!(count, res[0].1.len());
+        }
+    }
+
+    #[test]
+    fn grep_concat_test() {
+        let parts = ["test_files/file_part_a", "test_files/file_part_b"];
+        let config = GrepConfig::default();
+        let res = grep_concat(&[&b"MAGIC"[..], b"part"], &parts, &config).unwrap();
+        let a = PathBuf::from(parts[0]);
+        let b = PathBuf::from(parts[1]);
+        assert_eq!(res, vec![
+            ConcatMatch { found: FoundMatch { offset: 0, len: 4 }, files: vec![a.clone()] },
+            ConcatMatch { found: FoundMatch { offset: 9, len: 5 }, files: vec![a, b.clone()] },
+            ConcatMatch { found: FoundMatch { offset: 15, len: 4 }, files: vec![b.clone()] },
+            ConcatMatch { found: FoundMatch { offset: 24, len: 5 }, files: vec![b] },
+        ]);
+
+        // Searched separately the straddling match is missed
+        let res = grep(b"MAGIC", &parts).unwrap();
+        assert_eq!(res.iter().map(|(_, found)| found.len()).sum::<usize>(), 1);
+
+        let config = GrepConfig::builder().regex(true).build().unwrap();
+        let res = grep_concat(&[b"MAGIC"], &parts, &config);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let config = GrepConfig::builder().file_type(FileType::Binary).build().unwrap();
+        let res = grep_concat(&[b"MAGIC"], &parts, &config);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn count_file_bytes_test() {
+        let file = Path::new("test_files/file_one");
+        let cfg = GrepConfig::default();
+        let (count, bytes) = count_file(&[b"nya"], file, &cfg).unwrap();
+        assert_eq!(count, grep(b"nya", &[file]).unwrap()[0].1.len());
+        assert_eq!(bytes, count as u64 * 3);
+
+        let cfg = GrepConfig::builder().regex(true).build().unwrap();
+        let (count, bytes) = count_file(&[b"ny+a"], file, &cfg).unwrap();
+        let found = grep_matches(b"ny+a", &[file], &cfg).unwrap();
+        assert_eq!(count, found[0].1.len());
+        assert_eq!(bytes, found[0].1.iter().map(|found| found.len as u64).sum::<u64>());
+    }
+
+    #[test]
+    fn count_options_test() {
+        // The decompressed data is counted, not the compressed file
+        let file = Path::new("test_files/file_gzip.gz");
+        let cfg = GrepConfig::builder().count(true).decompress(true).build().unwrap();
+        let res = count_file(&[b"\x00\x01\x02"], file, &cfg);
+        if cfg!(feature = "decompress") {
+            assert_eq!(res.unwrap(), (64, 192));
+        } else {
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        }
+
+        let file = Path::new("test_files/file_one");
+        let cfg = GrepConfig::builder().count(true).limit_total(5).build().unwrap();
+        assert_eq!(count_file(&[b"nya"], file, &cfg).unwrap(), (5, 15));
+        let cfg = GrepConfig::builder().count(true).not_after(b" ".to_vec()).build().unwrap();
+        let found = grep_patterns(&[b"nya"], &[file], &cfg).unwrap();
+        assert_eq!(count_file(&[b"nya"], file, &cfg).unwrap().0, found[0].1.len());
+        assert!(found[0].1.len() < 18);
+
+        for builder in [
+            GrepConfig::builder().invert_file(true).within(0..6),
+            GrepConfig::builder().base(0x1000),
+            GrepConfig::builder().format(GrepFormat::parse("{offset}").unwrap()),
+            GrepConfig::builder().offsets_file(PathBuf::from("test_files/count_offsets")),
+            GrepConfig::builder().only_matching(true),
+        ] {
+            let res = builder.count(true).build();
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn grep_pattern_longer_than_file_test() {
+        let file = Path::new("test_files/file_tiny");
+        assert_eq!(grep(b"nyanyanyan", &[file]).unwrap()[0].1, Vec::<u64>::new());
+        let cfg = GrepConfig::default();
+        assert_eq!(count_file(&[b"nyanyanyan"], file, &cfg).unwrap(), (0, 0));
+        // Another pattern still matches
+        let found = grep_patterns(&[&b"nyanyanyan"[..], b"ya"], &[file], &cfg).unwrap();
+        assert_eq!(found[0].1, vec![FoundMatch { offset: 1, len: 2 }]);
+    }
+
+    #[test]
+    fn count_matches_fast_test() {
+        for (pattern, filename) in [
+            (&b"nya"[..], "test_files/file_one"),
+            (b"be", "test_files/file_two"),
+            (b"aa", "test_files/file_overlap_even"),
+            (b"a", "test_files/file_overlap_odd"),
+            (b"meow", "test_files/file_one"),
+            (b"nya", "test_files/file_empty"),
+        ] {
+            let file = Path::new(filename);
+            let expected = grep(pattern, &[file]).unwrap()[0].1.len();
+            assert_eq!(count_matches_fast(pattern, file).unwrap(), expected, "{}", filename);
+        }
+        assert_eq!(util::count_horspool(b"abcabcab", b"cab"), 2);
+        assert_eq!(util::count_horspool(b"ab", b"abc"), 0);
+
+        // Big enough for the count command to take the fast path
+        let big = Path::new("test_files/file_fast_count");
+        let mut data = vec![0u8; FAST_COUNT_MIN_LEN as usize + 100];
+        for chunk in data.chunks_mut(4096) {
+            chunk[..4].copy_from_slice(b"\x7fELF");
+        }
+        fs::write(big, &data).unwrap();
+        let (count, _) = count_file(&[b"\x7fELF"], big, &GrepConfig::default()).unwrap();
+        assert_eq!(count, data.len().div_ceil(4096));
+        assert_eq!(count, grep(b"\x7fELF", &[big]).unwrap()[0].1.len());
+
+        // Can't be mapped, so it's read instead
+        if Path::new("/proc/self/status").exists() {
+            let count = count_matches_fast(b"Name:", Path::new("/proc/self/status")).unwrap();
+            assert_eq!(count, 1);
+        }
+    }
+
+    #[test]
+    fn grep_offsets_file_test() {
+        let files = [Path::new("test_files/file_one"), Path::new("test_files/file_two")];
+        let expected = grep(b"nya", &files).unwrap();
+        let offsets_file = Path::new("test_files/grep_offsets_file");
+        let all: Vec<u64> = expected.iter().flat_map(|(_, offsets)| offsets.clone()).collect();
+        assert!(!all.is_empty());
+
+        let cfg = GrepConfig::builder().offsets_file(offsets_file.to_path_buf()).build().unwrap();
+        let summary = grep_command(&[b"nya"], &files, &cfg, &mut |_, _| {}).unwrap();
+        assert_eq!(summary.matches, all.len());
+        let lines: Vec<u64> = std::fs::read_to_string(offsets_file)
+            .unwrap()
+            .lines()
+            .map(|line| line.parse().unwrap())
+            .collect();
+        assert_eq!(lines, all);
+
+        let cfg = GrepConfig::builder()
+            .offsets_file(offsets_file.to_path_buf())
+            .offsets_format(OffsetsFormat::Binary)
+            .build()
+            .unwrap();
+        grep_command(&[b"nya"], &files, &cfg, &mut |_, _| {}).unwrap();
+        let binary: Vec<u64> = std::fs::read(offsets_file)
+            .unwrap()
+            .chunks(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(binary, all);
+
+        let cfg = GrepConfig::builder()
+            .offsets_file(offsets_file.to_path_buf())
+            .offsets_format(OffsetsFormat::Csv)
+            .build()
+            .unwrap();
+        grep_command(&[b"nya"], &files, &cfg, &mut |_, _| {}).unwrap();
+        let csv = std::fs::read_to_string(offsets_file).unwrap();
+        let mut rows = csv.lines();
+        assert_eq!(rows.next(), Some("file,offset,len"));
+        let expected_rows: Vec<String> = expected
+            .iter()
+            .flat_map(|(path, offsets)| {
+                offsets.iter().map(move |offset| format!("{},{},3", path.display(), offset))
+            })
+            .collect();
+        assert_eq!(rows.collect::<Vec<_>>(), expected_rows);
+
+        assert_eq!(csv_field("a,b\"c"), "\"a,b\"\"c\"");
+        let res = GrepConfig::builder().offsets_file(offsets_file.to_path_buf()).json(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_text_test() {
+        let file = Path::new("test_files/file_lines");
+        let found = grep_text(&[b"nya"], file, &GrepConfig::default()).unwrap();
+        let lines: Vec<_> = found.iter().map(|line_match| (line_match.line, line_match.offset)).collect();
+        assert_eq!(lines, vec![(2, 18), (4, 33), (5, 43)]);
+        assert_eq!(found[0].bytes, b"second nya line");
+        assert_eq!(found[2].bytes, b"\xff\xfenya");
+        // Every line reports its first match, which grep finds too
+        let offsets = &grep(b"nya", &[file]).unwrap()[0].1;
+        assert!(lines.iter().all(|(_, offset)| offsets.contains(offset)));
+
+        // The anchors match at the ends of the lines
+        let cfg = GrepConfig::builder().regex(true).build().unwrap();
+        let found = grep_text(&["a$", "^th"], file, &cfg).unwrap();
+        let lines: Vec<_> = found.iter().map(|line_match| (line_match.line, line_match.offset)).collect();
+        assert_eq!(lines, vec![(3, 27), (4, 39), (5, 45)]);
+
+        let cfg = GrepConfig::builder().case_insensitive(true).build().unwrap();
+        let found = grep_text(&[b"FIRST"], file, &cfg).unwrap();
+        assert_eq!(found, vec![LineMatch { line: 1, offset: 0, bytes: b"first line".to_vec() }]);
+
+        let res = GrepConfig::builder().text(true).word(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = GrepConfig::builder().text(true).align(4).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = GrepConfig::builder().text(true).file_type(FileType::Binary).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = GrepConfig::builder()
+            .text(true)
+            .case_insensitive(true)
+            .fold(CaseFold::Latin1)
+            .build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_deltas_test() {
+        let file = "test_files/file_many_matches";
+        let cfg = GrepConfig::builder().deltas(true).build().unwrap();
+        let res = grep_matches(b"abc", &[file], &cfg).unwrap();
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &cfg).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let mut deltas = out.trim_end().split(", ");
+        assert_eq!(deltas.next(), Some("10"));
+        assert_eq!(deltas.clone().count(), 4095);
+        assert!(deltas.all(|delta| delta == "+16"));
+
+        let res = GrepConfig::builder().deltas(true).line(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_decompress_test() {
+        let file = "test_files/file_gzip.gz";
+        let cfg = GrepConfig::builder().decompress(true).build().unwrap();
+        let res = grep_patterns(&[&b"nya"[..], b"\x00\x01\x02"], &[file], &cfg);
+        if cfg!(feature = "decompress") {
+            let found: Vec<_> = res.unwrap()[0].1.iter().map(|found| found.offset).collect();
+            let zeros: Vec<_> = (0..64).map(|n| 15 + n * 256).collect();
+            assert_eq!(found[0], 11);
+            assert_eq!(found[1..65], zeros);
+            assert_eq!(found[65..], [16400]);
+        } else {
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        }
+
+        let res = GrepConfig::builder().decompress(true).regex(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn absent_test() {
+        let file = Path::new("test_files/file_one");
+        assert!(!absent(b"nya", file, None).unwrap());
+        assert!(!absent(b"nya", file, Some(0..6)).unwrap());
+        // Matches at 12 and 19 only overlap the range
+        assert!(absent(b"nya", file, Some(13..21)).unwrap());
+        assert!(absent(b"meow", file, None).unwrap());
+
+        let res = GrepConfig::builder().within(0..6).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    /// Open a pipe which yields the contents of `filename`, so it can't
+    /// be seeked like the file itself.
+    #[cfg(unix)]
+    fn pipe_of(filename: &str) -> fs::File {
+        let (reader, mut writer) = io::pipe().unwrap();
+        let data = fs::read(filename).unwrap();
+        thread::spawn(move || writer.write_all(&data).unwrap());
+        fs::File::from(std::os::fd::OwnedFd::from(reader))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn grep_pipe_test() {
+        let path = Path::new("test_files/file_one");
+        let mut no_progress = |_, _| {};
+        for cfg in [GrepConfig::default(), GrepConfig { regex: true, ..Default::default() }] {
+            let matchers = build_matchers(&[&b"nya"[..], b"yan"], &cfg).unwrap();
+            let mut counts = vec![0; 2];
+            let mut progress = util::Progress::new(0, &mut no_progress);
+            let file = util::open_file(path).unwrap();
+            let from_file = search_file(file, &matchers, 1, false, usize::MAX, &mut counts, &mut progress).unwrap();
+
+            let file = util::OpenedFile { file: io::BufReader::new(pipe_of("test_files/file_one")), path };
+            let from_pipe = search_file(file, &matchers, 1, false, usize::MAX, &mut counts, &mut progress).unwrap();
+            assert_eq!(from_pipe, from_file);
+            assert!(from_pipe.1.len() > 18);
+
+            let mut file = util::OpenedFile { file: io::BufReader::new(pipe_of("test_files/file_one")), path };
+            assert!(matches_within(&mut file, &matchers, &cfg, Some(0..6)).unwrap());
+        }
+    }
+
+    #[test]
+    fn grep_parallel_test() {
+        let files = [
+            "test_files/file_one",
+            "test_files/file_two",
+            "test_files/file_three",
+            "test_files/file_four",
+            "test_files/file_five",
+            "test_files/file_words",
+        ];
+        let patterns = ["nya", "20%"];
+        let expected = grep_patterns(&patterns, &files, &GrepConfig::default()).unwrap();
+        for jobs in [1, 2, 4] {
+            let cfg = GrepConfig { jobs, ..Default::default() };
+            assert_eq!(grep_parallel(&patterns, &files, &cfg).unwrap(), expected);
+            let max_open_files = MAX_OPEN_FILES.with(|max| max.get());
+            assert!((1..=jobs).contains(&max_open_files));
+        }
+
+        let cfg = GrepConfig { jobs: 2, file_type: FileType::Text, ..Default::default() };
+        let expected = grep_patterns(&patterns, &files, &cfg).unwrap();
+        assert!(expected.len() < files.len());
+        assert_eq!(grep_parallel(&patterns, &files, &cfg).unwrap(), expected);
+    }
+
+    #[test]
+    fn grep_test_utf16() {
+        assert_eq!(Encoding::Utf16Le.encode(b"nya").unwrap(), b"n\x00y\x00a\x00");
+        assert_eq!(Encoding::Utf16Be.encode(b"nya").unwrap(), b"\x00n\x00y\x00a");
+
+        let files = ["test_files/file_five"];
+        let cfg = GrepConfig { encoding: Encoding::Utf16Le, ..Default::default() };
+        let res = grep_matches(b"nya", &files, &cfg).unwrap();
+        assert_eq!(res[0].1, vec![FoundMatch { offset: 6, len: 6 }]);
+        let cfg = GrepConfig { encoding: Encoding::Utf16Be, ..Default::default() };
+        let res = grep_matches(b"nya", &files, &cfg).unwrap();
+        assert_eq!(res[0].1, vec![FoundMatch { offset: 19, len: 6 }]);
+        let res = grep(b"nya", &files).unwrap();
+        assert_eq!(res[0].1, vec![33]);
+    }
+
     #[test]
     fn simple_grep_test_two() {
         let files = vec!["test_files/file_three"];
@@ -98,6 +2712,593 @@ mod tests {
         assert_eq!(res.len(), 3);
         assert_eq!(res[0].1, vec![99]);
         assert_eq!(res[1].1, vec![12, 44, 76, 108]);
-        assert_eq!(res[2].1, vec![]);
+        assert_eq!(res[2].1, Vec::<u64>::new());
+    }
+
+    #[test]
+    fn grep_matches_length_test() {
+        let files = vec!["test_files/file_one"];
+        let res = grep_matches(b"nya", &files, &GrepConfig::default())
+            .expect("Probably file not found");
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].1.len(), 18);
+        assert!(res[0].1.iter().all(|m| m.len == 3));
+        assert_eq!(res[0].1[0], FoundMatch { offset: 3, len: 3 });
+    }
+
+    #[test]
+    fn grep_case_insensitive_test() {
+        let files = vec!["test_files/file_four"];
+        let res = grep(b"nya", &files).expect("Probably file not found");
+        assert_eq!(res[0].1, vec![4]);
+
+        let cfg = GrepConfig { case_insensitive: true, ..Default::default() };
+        let res = grep_matches(b"nya", &files, &cfg).expect("Probably file not found");
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![0, 4, 8, 12, 17]);
+
+        // Non-letter bytes still have to match exactly
+        let res = grep_matches(b"\x00\xdf", &files, &cfg).expect("Probably file not found");
+        assert!(res[0].1.is_empty());
+    }
+
+    #[test]
+    fn grep_fold_latin1_test() {
+        let files = vec!["test_files/file_latin1"];
+        // ASCII folding leaves the accented bytes alone
+        let cfg = GrepConfig::builder().case_insensitive(true).build().unwrap();
+        let res = grep_matches(b"caf\xc9", &files, &cfg).unwrap();
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![5]);
+
+        let cfg = GrepConfig::builder()
+            .case_insensitive(true)
+            .fold(CaseFold::Latin1)
+            .build()
+            .unwrap();
+        let res = grep_matches(b"caf\xc9", &files, &cfg).unwrap();
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![0, 5]);
+        let res = grep_matches(b"NA\xcfVE", &files, &cfg).unwrap();
+        assert_eq!(res[0].1.len(), 1);
+        let res = grep_matches(b"\xf7\xf7", &files, &cfg).unwrap();
+        assert!(res[0].1.is_empty());
+
+        let res = GrepConfig::builder().fold(CaseFold::Latin1).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_regex_test() {
+        let files = vec!["test_files/file_one"];
+        let cfg = GrepConfig { regex: true, ..Default::default() };
+        let res = grep_matches(b"nya[a-z]u", &files, &cfg).expect("Probably file not found");
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].1, vec![
+            FoundMatch { offset: 43, len: 5 },
+            FoundMatch { offset: 48, len: 5 },
+            FoundMatch { offset: 58, len: 5 },
+        ]);
+
+        let res = grep_matches(b"nya+[a-z]+ ", &files, &cfg).expect("Probably file not found");
+        let lens: Vec<_> = res[0].1.iter().map(|m| m.len).collect();
+        assert_eq!(lens, vec![6, 8, 11, 9, 10, 10, 10, 10]);
+    }
+
+    #[test]
+    fn grep_json_test() {
+        let files = vec!["test_files/file_two", "test_files/file_three"];
+        let res = grep_matches(b"20%", &files, &GrepConfig::default())
+            .expect("Probably file not found");
+        let json: serde_json::Value = serde_json::from_str(&grep_results_to_json(&res)).unwrap();
+        assert_eq!(json, serde_json::json!([
+            { "file": "test_files/file_two", "offsets": [21, 53, 85, 117] },
+            { "file": "test_files/file_three", "offsets": [] },
+        ]));
+    }
+
+    #[test]
+    fn grep_empty_file_test() {
+        let files = vec!["test_files/file_empty"];
+        let res = grep(b"nya", &files).unwrap();
+        assert_eq!(res, vec![(PathBuf::from("test_files/file_empty"), vec![])]);
+
+        let cfg = GrepConfig { regex: true, ..Default::default() };
+        let res = grep_matches(b"ny.", &files, &cfg).unwrap();
+        assert!(res[0].1.is_empty());
+    }
+
+    #[test]
+    fn grep_context_test() {
+        let files = vec!["test_files/file_one"];
+        let res = grep_matches(b"nyaku", &files, &GrepConfig::default())
+            .expect("Probably file not found");
+        let found = res[0].1[0];
+        assert_eq!(found.offset, 43);
+        let file = &mut fs::File::open(&res[0].0).unwrap();
+        let (start, bytes) = util::read_context(file, found.offset, found.len, 4).unwrap();
+        assert_eq!(start, 39);
+        assert_eq!(bytes, b" de nyakunyak");
+    }
+
+    #[test]
+    fn grep_before_after_context_test() {
+        let files = vec!["test_files/file_one"];
+        let res = grep_matches(b"nyaku", &files, &GrepConfig::default()).unwrap();
+        let cfg = GrepConfig::builder().before_context(3).after_context(6).build().unwrap();
+        let mut out = Vec::new();
+        write_results(&mut out, &res[..], &cfg).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // The window spans [43 - 3, 43 + 5 + 6)
+        assert!(out.starts_with("43:\n00000028: 64 65 20 6e 79 61 6b 75 6e 79 61 6b 75 20 "));
+        assert!(out.contains("de nyakunyaku \n"));
+
+        // Clamped at the start and the end of the file
+        let cfg = GrepConfig::builder().before_context(100).after_context(100).build().unwrap();
+        let mut out = Vec::new();
+        write_results(&mut out, &res[..], &cfg).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("43:\n00000000: "));
+        assert!(out.contains("\n00000070: "));
+        assert!(!out.contains("\n00000080: "));
+    }
+
+    #[test]
+    fn grep_patterns_sorted_unique_test() {
+        let files = vec!["test_files/file_one"];
+        let patterns: Vec<&[u8]> = vec![b"nyaku", b"nya", b"ku"];
+        let res = grep_patterns(&patterns, &files, &GrepConfig::default())
+            .expect("Probably file not found");
+        assert_eq!(res.len(), 1);
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![
+            3, 9, 12, 19, 22, 32, 43, 46, 48, 51, 55, 58, 61, 64, 67, 74, 77,
+            84, 91, 94, 104, 109
+        ]);
+        // The longer pattern wins at the same offset
+        assert_eq!(res[0].1[6], FoundMatch { offset: 43, len: 5 });
+    }
+
+    #[test]
+    fn grep_pattern_file_test() {
+        let pattern = read_pattern_file(Path::new("test_files/pattern_two"))
+            .expect("Probably file not found");
+        let files = vec!["test_files/file_two"];
+        let res = grep(&pattern, &files).expect("Probably file not found");
+        assert_eq!(res[0].1, vec![12, 44, 76, 108]);
+    }
+
+    #[test]
+    fn grep_count_bytes_test() {
+        let files = vec!["test_files/file_one", "test_files/file_two", "test_files/file_three"];
+        let cfg = GrepConfig { count_bytes: true, ..Default::default() };
+        let res = grep_matches(b"be", &files, &cfg).expect("Probably file not found");
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &cfg).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().last(), Some("Total: 5 matches covering 10 bytes"));
+
+        let cfg = GrepConfig { count_bytes: true, quiet: true, ..Default::default() };
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &cfg).unwrap();
+        assert!(!String::from_utf8(out).unwrap().contains("Total"));
+
+        // Quiet only leaves out the messages, the summary is still filled in
+        let res = grep_matches(b"meow", &files, &cfg).unwrap();
+        let mut out = Vec::new();
+        let summary = write_results(&mut out, &res, &cfg).unwrap();
+        assert!(out.is_empty());
+        assert_eq!(summary, GrepSummary { files_scanned: 3, files_matched: 0, matches: 0 });
+    }
+
+    #[test]
+    fn grep_only_matching_test() {
+        let files = vec!["test_files/file_two"];
+        let res = grep_matches(b"20%", &files, &GrepConfig::default()).unwrap();
+        let cfg = GrepConfig { only_matching: true, ..Default::default() };
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &cfg).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "21: 32 30 25\n53: 32 30 25\n85: 32 30 25\n117: 32 30 25\n",
+        );
+    }
+
+    #[test]
+    fn grep_with_filename_test() {
+        let files = vec!["test_files/file_two"];
+        let res = grep_matches(b"20%", &files, &GrepConfig::default()).unwrap();
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &GrepConfig::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "21, 53, 85, 117\n");
+        let cfg = GrepConfig::builder().with_filename(true).build().unwrap();
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &cfg).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "test_files/file_two:\n21, 53, 85, 117\n");
+
+        let files = vec!["test_files/file_two", "test_files/file_three", "test_files/file_two"];
+        let res = grep_matches(b"20%", &files, &GrepConfig::default()).unwrap();
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &GrepConfig::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "test_files/file_two:\n21, 53, 85, 117\n\ntest_files/file_three:\n\n\n\
+             test_files/file_two:\n21, 53, 85, 117\n",
+        );
+        let cfg = GrepConfig::builder().with_filename(false).build().unwrap();
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &cfg).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "21, 53, 85, 117\n21, 53, 85, 117\n");
+    }
+
+    #[test]
+    fn grep_line_test() {
+        let files = vec!["test_files/file_records"];
+        let res = grep_matches(b"secret", &files, &GrepConfig::default()).unwrap();
+        let cfg = GrepConfig::builder().line(true).build().unwrap();
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &cfg).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "17: key=\\x00\\x01secret\\x02\n40: note=secretive\n",
+        );
+
+        // Without newlines around the match the line is cut at the window
+        let file = &mut fs::File::open("test_files/file_zeros").unwrap();
+        let (start, line) = util::read_line(file, 30, 2, 8).unwrap();
+        assert_eq!((start, line.len()), (22, 18));
+        let file = &mut fs::File::open("test_files/file_overlap_odd").unwrap();
+        let (start, line) = util::read_line(file, 1, 2, 8).unwrap();
+        assert_eq!((start, &line[..]), (0, &b"aaaaa"[..]));
+
+        let res = GrepConfig::builder().line(true).json(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_color_test() {
+        let files = vec!["test_files/file_two"];
+        let res = grep_matches(b"20%", &files, &GrepConfig::default()).unwrap();
+        for context in [None, Some(GrepContext { before: 2, after: 2 })] {
+            let cfg = GrepConfig { context, ..Default::default() };
+            let mut out = Vec::new();
+            write_results(&mut out, &res, &cfg).unwrap();
+            assert!(!out.contains(&0x1b));
+        }
+
+        let cfg = GrepConfig { only_matching: true, color: true, ..Default::default() };
+        let mut out = Vec::new();
+        write_results(&mut out, &res[..], &cfg).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\x1b[36m21\x1b[0m: \x1b[31m32 30 25\x1b[0m\n"));
+
+        // Escape codes don't push the ASCII column around
+        let cfg = GrepConfig {
+            context: Some(GrepContext { before: 2, after: 2 }),
+            color: true,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        write_results(&mut out, &res[..1], &cfg).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let line = out.lines().find(|line| line.starts_with("00000013")).unwrap();
+        assert!(line.ends_with(&format!("\x1b[0m 20 63{:27}  t 20% c", "")));
+    }
+
+    #[test]
+    fn grep_align_test() {
+        let files = vec!["test_files/file_two"];
+        let cfg = GrepConfig { align: Some(4), ..Default::default() };
+        let res = grep_matches(b"be", &files, &cfg).unwrap();
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![12, 44, 76, 108]);
+        let res = grep_matches(b"20%", &files, &cfg).unwrap();
+        assert!(res[0].1.is_empty());
+
+        let cfg = GrepConfig { align: Some(4), regex: true, ..Default::default() };
+        let res = grep_matches(b"be|20%", &files, &cfg).unwrap();
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![12, 44, 76, 108]);
+    }
+
+    #[test]
+    fn grep_word_test() {
+        // The last "NYA" is embedded in "NYAN"
+        let files = vec!["test_files/file_four"];
+        let cfg = GrepConfig { word: true, case_insensitive: true, ..Default::default() };
+        let res = grep_matches(b"nya", &files, &cfg).unwrap();
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![0, 4, 8, 12]);
+
+        let res = grep_matches(b"nyan", &files, &cfg).unwrap();
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![17]);
+
+        let files = vec!["test_files/file_one"];
+        let cfg = GrepConfig { word: true, ..Default::default() };
+        let res = grep_matches(b"nya", &files, &cfg).unwrap();
+        assert!(res[0].1.is_empty());
+        let cfg = GrepConfig { word: true, regex: true, ..Default::default() };
+        let res = grep_matches(b"nya+", &files, &cfg).unwrap();
+        assert!(res[0].1.is_empty());
+    }
+
+    #[test]
+    fn grep_base_test() {
+        let files = vec!["test_files/file_two"];
+        let res = grep_matches(b"20%", &files, &GrepConfig::default()).unwrap();
+        let cfg = GrepConfig { base: 0x400000, ..Default::default() };
+        let mut out = Vec::new();
+        write_results(&mut out, &res, &cfg).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "4194325, 4194357, 4194389, 4194421\n",
+        );
+        // The library results themselves aren't shifted
+        assert_eq!(res[0].1[0].offset, 21);
+    }
+
+    #[test]
+    fn grep_count_files_test() {
+        let cfg = GrepConfig {
+            recursive: true,
+            count_files: true,
+            group_by_dir: true,
+            ..Default::default()
+        };
+        let paths = search_paths(&["test_files/hidden_dir", "test_files/file_three"], &cfg).unwrap();
+        let mut progress = |_, _| {};
+        let results = grep_iter(&[b"nya"], &paths, &cfg, &mut progress).unwrap();
+        let mut out = Vec::new();
+        let summary = write_results_iter(&mut out, results, &cfg).unwrap();
+        assert_eq!(summary, GrepSummary { files_scanned: 4, files_matched: 3, matches: 3 });
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("== test_files ==\ntest_files/file_three:\n\n\n"));
+        assert!(out.contains("== test_files/hidden_dir/.config ==\ntest_files/hidden_dir/.config/file:\n0\n"));
+        assert!(out.ends_with("\nScanned 4 files, 3 with matches\n"));
+    }
+
+    #[test]
+    fn grep_include_exclude_test() {
+        let cfg = GrepConfig::builder()
+            .recursive(true)
+            .include(vec!["*.so".to_string(), "*.d".to_string()])
+            .exclude(vec!["skip*".to_string(), "a.d".to_string()])
+            .build()
+            .unwrap();
+        let paths = search_paths(&["test_files/filter_dir"], &cfg).unwrap();
+        assert_eq!(paths, vec![
+            PathBuf::from("test_files/filter_dir/a.so"),
+            PathBuf::from("test_files/filter_dir/sub/c.so"),
+            PathBuf::from("test_files/filter_dir/sub/c.so.d"),
+        ]);
+        let mut progress = |_, _| {};
+        let results = grep_iter(&[b"nya"], &paths, &cfg, &mut progress).unwrap();
+        assert_eq!(results.count(), 3);
+
+        let res = GrepConfig::builder().include(vec!["*.so".to_string()]).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = GrepConfig::builder().recursive(true).exclude(vec!["[".to_string()]).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = GrepConfig::builder().max_depth(1).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_count_patterns_test() {
+        let files = ["test_files/file_one", "test_files/file_two"];
+        let patterns = [&b"nya"[..], b"meow"];
+        let cfg = GrepConfig::builder().count_patterns(true).build().unwrap();
+        let mut progress = |_, _| {};
+        let mut results = grep_iter(&patterns, &files, &cfg, &mut progress).unwrap();
+        let mut out = Vec::new();
+        write_results_iter(&mut out, &mut results, &cfg).unwrap();
+        assert_eq!(results.pattern_counts(), &[18, 0]);
+
+        let mut out = Vec::new();
+        write_pattern_counts(&mut out, &patterns, results.pattern_counts()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "nya: 18\nmeow: 0\n");
+
+        let res = GrepConfig::builder().count_patterns(true).json(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_checkpoint_test() {
+        let checkpoint = PathBuf::from("test_files/grep_checkpoint");
+        let files = ["test_files/file_two", "test_files/file_one"];
+        let saved = Checkpoint { path: PathBuf::from(files[1]), offset: 40, matches: vec![] };
+        saved.write(&checkpoint).unwrap();
+        assert_eq!(Checkpoint::read(&checkpoint).unwrap(), saved);
+
+        // Files before the checkpointed one are skipped, matches before
+        // the checkpointed offset aren't found again
+        let cfg = GrepConfig::builder().checkpoint(checkpoint.clone()).resume(true).build().unwrap();
+        let res = grep_patterns(&["nya"], &files, &cfg).unwrap();
+        assert_eq!(res.len(), 1);
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![43, 48, 55, 58, 64, 67, 74, 77, 84, 94, 104, 109]);
+        // A finished search doesn't need its checkpoint anymore
+        assert!(!checkpoint.exists());
+
+        // Matches saved in the checkpoint are kept
+        let found = FoundMatch { offset: 3, len: 3 };
+        let saved = Checkpoint { path: PathBuf::from(files[1]), offset: 100, matches: vec![found] };
+        saved.write(&checkpoint).unwrap();
+        let res = grep_patterns(&["nya"], &files, &cfg).unwrap();
+        let offsets: Vec<_> = res[0].1.iter().map(|m| m.offset).collect();
+        assert_eq!(offsets, vec![3, 104, 109]);
+
+        // Saving again only appends the new matches, and matches
+        // appended after the last save are left out
+        let path = PathBuf::from(files[1]);
+        let mut writer = CheckpointWriter::open(&checkpoint, &path, 0).unwrap();
+        writer.save(10, &[found]).unwrap();
+        let second = FoundMatch { offset: 9, len: 3 };
+        writer.save(20, &[second]).unwrap();
+        let matches_path = Checkpoint::matches_path(&checkpoint);
+        assert_eq!(fs::metadata(&matches_path).unwrap().len(), 2 * MATCH_RECORD_LEN);
+        fs::OpenOptions::new().append(true).open(&matches_path).unwrap().write_all(&[0; 16]).unwrap();
+        let saved = Checkpoint { path, offset: 20, matches: vec![found, second] };
+        assert_eq!(Checkpoint::read(&checkpoint).unwrap(), saved);
+
+        let cfg = GrepConfig::builder().checkpoint(checkpoint.clone()).build().unwrap();
+        let res = grep_patterns(&["nya", "meow"], &files, &cfg);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = GrepConfig::builder().resume(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_format_output_test() {
+        let files = ["test_files/file_two", "test_files/file_three"];
+        let format = GrepFormat::parse("{file}\\t{offset_hex}\\n").unwrap();
+        let cfg = GrepConfig::builder().format(format).build().unwrap();
+        let res = grep_matches(b"20%", &files, &cfg).unwrap();
+        let mut out = Vec::new();
+        let summary = write_results(&mut out, &res, &cfg).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "test_files/file_two\t0x15\ntest_files/file_two\t0x35\n\
+             test_files/file_two\t0x55\ntest_files/file_two\t0x75\n",
+        );
+        assert_eq!(summary, GrepSummary { files_scanned: 2, files_matched: 1, matches: 4 });
+
+        let format = GrepFormat::parse("{offset}").unwrap();
+        let res = GrepConfig::builder().format(format).json(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_progress_test() {
+        let files = vec!["test_files/file_one", "test_files/file_two"];
+        let mut reported = Vec::new();
+        grep_patterns_with_progress(&[b"nya"], &files, &GrepConfig::default(), &mut |done, total| {
+            reported.push((done, total));
+        }).expect("Probably file not found");
+        // The total grows as the files get opened
+        assert_eq!(reported, vec![(116, 116), (243, 243)]);
+    }
+
+    #[test]
+    fn grep_config_builder_test() {
+        let cfg = GrepConfig::builder()
+            .recursive(true)
+            .skip_hidden(true)
+            .context(4)
+            .build()
+            .unwrap();
+        assert!(cfg.recursive && cfg.skip_hidden);
+        assert_eq!(cfg.context, Some(GrepContext { before: 4, after: 4 }));
+        let cfg = GrepConfig::builder().context(4).after_context(1).build().unwrap();
+        assert_eq!(cfg.context, Some(GrepContext { before: 4, after: 1 }));
+
+        let res = GrepConfig::builder().json(true).context(4).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_streaming_output_test() {
+        let files = vec!["test_files/file_one", "test_files/file_two", "test_files/file_three"];
+        let cfg = GrepConfig::default();
+        let mut progress = |_, _| {};
+        let mut out = Vec::new();
+        let results = grep_iter(&[b"be"], &files, &cfg, &mut progress).unwrap();
+        write_results_iter(&mut out, results, &cfg).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "test_files/file_one:\n99\n\n\
+             test_files/file_two:\n12, 44, 76, 108\n\n\
+             test_files/file_three:\n\n",
+        );
+
+        let res = grep_matches(b"be", &files, &cfg).unwrap();
+        let mut batch_out = Vec::new();
+        write_results(&mut batch_out, &res, &cfg).unwrap();
+        let mut out = Vec::new();
+        let results = grep_iter(&[b"be"], &files, &cfg, &mut progress).unwrap();
+        write_results_iter(&mut out, results, &cfg).unwrap();
+        assert_eq!(out, batch_out);
+
+        let files = vec!["test_files/file_three"];
+        let mut out = Vec::new();
+        let results = grep_iter(&[b"be"], &files, &cfg, &mut progress).unwrap();
+        write_results_iter(&mut out, results, &cfg).unwrap();
+        assert_eq!(out, b"Nothing found\n");
+    }
+
+    #[test]
+    fn grep_limit_total_test() {
+        // The missing file is never opened once the limit is reached
+        let files = vec!["test_files/file_one", "test_files/file_two", "test_files/file_missing"];
+        let cfg = GrepConfig::builder().limit_total(3).build().unwrap();
+        let mut progress = |_, _| {};
+        let results: Vec<_> = grep_iter(&[b"be"], &files, &cfg, &mut progress)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let offsets: Vec<Vec<u64>> = results
+            .iter()
+            .map(|(_, found)| found.iter().map(|found| found.offset).collect())
+            .collect();
+        assert_eq!(offsets, vec![vec![99], vec![12, 44]]);
+
+        let cfg = GrepConfig::builder().limit_total(5).build().unwrap();
+        let results = grep_iter(&[b"be"], &files, &cfg, &mut progress).unwrap();
+        assert_eq!(results.count(), 2);
+        let results = grep_iter(&[b"be"], &files, &GrepConfig::default(), &mut progress).unwrap();
+        assert!(results.last().unwrap().is_err());
+
+        let files = vec!["test_files/file_one"];
+        let cfg = GrepConfig::builder().limit_total(2).build().unwrap();
+        let patterns = [&b"be"[..], b"nya"];
+        let mut results = grep_iter(&patterns, &files, &cfg, &mut progress).unwrap();
+        let (_, found) = results.next().unwrap().unwrap();
+        assert_eq!(found, vec![FoundMatch { offset: 3, len: 3 }, FoundMatch { offset: 9, len: 3 }]);
+
+        let res = GrepConfig::builder().limit_total(2).jobs(2).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_not_adjacent_test() {
+        let files = vec!["test_files/file_percent"];
+        let mut progress = |_, _| {};
+        let mut offsets = |cfg: &GrepConfig| -> Vec<u64> {
+            let mut results = grep_iter(&[b"20%"], &files, cfg, &mut progress).unwrap();
+            let (_, found) = results.next().unwrap().unwrap();
+            found.iter().map(|found| found.offset).collect()
+        };
+        assert_eq!(offsets(&GrepConfig::default()), vec![0, 5, 10, 16]);
+
+        let cfg = GrepConfig::builder().not_before(b"a".to_vec()).build().unwrap();
+        assert_eq!(offsets(&cfg), vec![5, 16]);
+        let cfg = GrepConfig::builder().not_after(b" x".to_vec()).build().unwrap();
+        assert_eq!(offsets(&cfg), vec![0, 5, 10]);
+        let cfg = GrepConfig::builder()
+            .not_after(b"x".to_vec())
+            .not_before(b"a".to_vec())
+            .limit_total(1)
+            .build()
+            .unwrap();
+        assert_eq!(offsets(&cfg), vec![5]);
+
+        let res = GrepConfig::builder().not_before(b"a".to_vec()).jobs(2).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn grep_file_type_test() {
+        let files = vec!["test_files/file_one", "test_files/file_three", "test_files/file_four"];
+        let cfg = GrepConfig { file_type: FileType::Binary, ..Default::default() };
+        let res = grep_matches(b"\x00", &files, &cfg).expect("Probably file not found");
+        let paths: Vec<_> = res.iter().map(|(path, _)| path.as_path()).collect();
+        assert_eq!(paths, vec![Path::new("test_files/file_three"), Path::new("test_files/file_four")]);
+
+        let cfg = GrepConfig { file_type: FileType::Text, ..Default::default() };
+        let res = grep_matches(b"nya", &files, &cfg).expect("Probably file not found");
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].0, Path::new("test_files/file_one"));
     }
 }
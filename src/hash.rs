@@ -0,0 +1,167 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::util;
+
+/// Digest computed by the hash command.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Default, Debug)]
+pub struct HashConfig {
+    pub algo: HashAlgo,
+    pub recursive: bool,
+    pub skip_hidden: bool,
+    pub jobs: usize,
+}
+
+/// Function for executing the command line hash command. You probably
+/// want to use `hash_file()` or `hash_tree()` instead. Print
+/// `digest  path` for every file like sha256sum does, sorted by path
+/// in the recursive mode. Return the printed digests.
+pub fn hash_command<T: AsRef<Path>>(
+    filenames: &[T],
+    hash_config: &HashConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<Vec<(PathBuf, String)>, io::Error> {
+    let digests = if hash_config.recursive {
+        hash_tree(filenames, hash_config)?
+    } else {
+        util::expand_globs(filenames)?
+            .into_iter()
+            .map(|filename| {
+                let digest = hash_file_with_progress(&filename, hash_config.algo, progress)?;
+                Ok((filename, digest))
+            })
+            .collect::<Result<_, io::Error>>()?
+    };
+    let mut out = io::stdout().lock();
+    for (path, digest) in digests.iter() {
+        writeln!(out, "{}  {}", digest, path.display())?;
+    }
+
+    Ok(digests)
+}
+
+/// Digest of the contents of `filename` as lowercase hex digits. The
+/// file is read in chunks, so it's never held whole in memory.
+pub fn hash_file(filename: &Path, algo: HashAlgo) -> Result<String, io::Error> {
+    hash_file_with_progress(filename, algo, &mut |_, _| {})
+}
+
+/// Same as `hash_file()`, except `progress` is periodically called
+/// with the number of bytes hashed so far and the file size.
+pub fn hash_file_with_progress(
+    filename: &Path,
+    algo: HashAlgo,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<String, io::Error> {
+    let mut input_file = util::open_file(filename)?;
+    let file_len = util::file_len(input_file.file.get_ref())?;
+    let mut progress = util::Progress::new(file_len, progress);
+    let digest = match algo {
+        HashAlgo::Sha256 => digest_reader(Sha256::new(), &mut input_file.file, &mut progress)?,
+        HashAlgo::Sha512 => digest_reader(Sha512::new(), &mut input_file.file, &mut progress)?,
+    };
+
+    Ok(digest)
+}
+
+fn digest_reader<D: Digest + Write, R: io::Read>(
+    mut hasher: D,
+    reader: &mut R,
+    progress: &mut util::Progress,
+) -> Result<String, io::Error> {
+    util::copy_with_progress(reader, &mut hasher, progress)?;
+
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Hash every file in `paths` and in the directories under them, with
+/// `hash_config.jobs` threads at once. Every thread has at most one
+/// file open at a time. The digests are sorted by path, whatever order
+/// the threads finish in. A `jobs` of 0 is treated as 1.
+pub fn hash_tree<T: AsRef<Path>>(
+    paths: &[T],
+    hash_config: &HashConfig,
+) -> Result<Vec<(PathBuf, String)>, io::Error> {
+    let paths = util::expand_globs(paths)?;
+    let filter = util::NameFilter::default();
+    let mut filenames = util::open_all_directories(&paths, hash_config.skip_hidden, &filter, None)?;
+    filenames.sort();
+    let next = AtomicUsize::new(0);
+
+    let mut results: Vec<_> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..hash_config.jobs.max(1))
+            .map(|_| scope.spawn(|| {
+                let mut ret = Vec::new();
+                loop {
+                    let n = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(filename) = filenames.get(n) else {
+                        break;
+                    };
+                    ret.push((n, hash_file(filename, hash_config.algo)));
+                }
+                ret
+            }))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("Hash worker panicked"))
+            .collect()
+    });
+
+    // Workers finish in any order
+    results.sort_by_key(|&(n, _)| n);
+    results
+        .into_iter()
+        .map(|(n, res)| res.map(|digest| (filenames[n].clone(), digest)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_hash_test() {
+        let digest = hash_file(Path::new("test_files/hash_dir/b_first"), HashAlgo::Sha256).unwrap();
+        assert_eq!(digest, "841a7c28d2425b3fc7aa0370ce41df3ae155e16c9e1e7ad0d0daace3340d5b18");
+        let digest = hash_file(Path::new("test_files/hash_dir/b_first"), HashAlgo::Sha512).unwrap();
+        assert_eq!(
+            digest,
+            "a62614886b2fd210101643f7b684bc1a27d2acf312290d81cafb42d49c0c13d8\
+             ccf7128006cd26f3e2eda633ac95780afc65a17cf45cdde1d297f41444896153",
+        );
+    }
+
+    #[test]
+    fn hash_tree_test() {
+        let expected = vec![
+            (
+                PathBuf::from("test_files/hash_dir/b_first"),
+                "841a7c28d2425b3fc7aa0370ce41df3ae155e16c9e1e7ad0d0daace3340d5b18".to_string(),
+            ),
+            (
+                PathBuf::from("test_files/hash_dir/sub/deep"),
+                "c4e93bc7b96008f285a9e1ee72124d0874f40e9a70fa5a0ddf642e0021016d23".to_string(),
+            ),
+            (
+                PathBuf::from("test_files/hash_dir/sub/empty"),
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855".to_string(),
+            ),
+        ];
+        for jobs in [1, 2, 8] {
+            let cfg = HashConfig { recursive: true, jobs, ..Default::default() };
+            assert_eq!(hash_tree(&["test_files/hash_dir"], &cfg).unwrap(), expected);
+        }
+    }
+}
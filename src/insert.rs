@@ -1,52 +1,371 @@
 use std::io;
 use std::path::Path;
-use std::io::{Write, Read, BufWriter};
+use std::io::{Write, BufWriter};
 use std::fs::File;
-use crate::util;
+use crate::searcher::Searcher;
+use crate::template::Template;
+use crate::util::{self, Offset};
 
 #[derive(Default, Debug)]
 pub struct InsertConfig {
     pub quiet: bool,
+    pub in_place: bool,
+    pub json: bool,
+    pub dry_run: bool,
+    pub template: bool,
+    pub reject_empty: bool,
+    pub anchor: Option<InsertAnchor>,
+    pub nth: usize,
+    pub no_clobber: bool,
+}
+
+/// A match of a pattern the offsets of an insert are counted from,
+/// instead of the start and the end of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InsertAnchor {
+    /// Count from right after the match.
+    After(Vec<u8>),
+    /// Count from the start of the match.
+    Before(Vec<u8>),
+}
+
+/// Summary of a finished insert, returned even when nothing is printed.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct InsertReport {
+    /// Offsets the bytes were inserted at, counted from the start of
+    /// the input, in the order they were given.
+    pub offsets: Vec<usize>,
+    /// Number of inserted bytes at all the offsets together.
+    pub inserted: u64,
+    /// Size of the output, or the size it would have in dry-run mode.
+    pub bytes_written: u64,
 }
 
 /// Function for executing the command line insert command. You
-/// probably want to use `insert()` instead.
+/// probably want to use `insert()` instead. Offsets counted from the
+/// end are resolved against the size of the input file. With `-` as
+/// the output filename the result is written to stdout and the
+/// messages to stderr. When there's nothing to insert the input is
+/// just copied, or left alone in place, with a note about it. Return
+/// an error instead if `reject_empty` is set. With an anchor the
+/// offsets are counted from the `nth` match of its pattern instead,
+/// negative ones going back from it. With `no_clobber` an existing
+/// output file is an error. The report is returned in quiet mode too,
+/// only the messages are left out.
 pub fn insert_command(
     to_insert: &[u8],
-    offset: usize,
+    offsets: &[Offset],
     input_filename: &Path,
     output_filename: &Path,
-    insert_config: &InsertConfig
-) -> Result<(), io::Error> {
-    insert(to_insert, offset, input_filename, output_filename)?;
-    if !insert_config.quiet {
-        println!("Inserting was successful");
+    insert_config: &InsertConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<InsertReport, io::Error> {
+    if insert_config.no_clobber {
+        if insert_config.in_place {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no_clobber can't be used together with in_place",
+            ));
+        }
+        util::ensure_no_clobber(output_filename)?;
+    }
+    let file_len = util::file_len(&File::open(input_filename)?)?;
+    let offsets = match &insert_config.anchor {
+        Some(anchor) => {
+            let base = anchor_offset(input_filename, anchor, insert_config.nth)?;
+            offsets
+                .iter()
+                .map(|offset| match *offset {
+                    Offset::FromStart(offset) => Ok(base + offset),
+                    Offset::FromEnd(back) => base.checked_sub(back).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            format!("Offset -{} is before the start of the file", back),
+                        )
+                    }),
+                })
+                .collect::<Result<Vec<_>, io::Error>>()?
+        }
+        None => offsets
+            .iter()
+            .map(|offset| offset.resolve(file_len))
+            .collect::<Result<Vec<_>, _>>()?,
+    };
+    // Every offset gets its own bytes, templates expand differently
+    let insertions: Vec<_> = if insert_config.template {
+        let template = Template::parse(to_insert)?;
+        template.fits(file_len)?;
+        offsets.iter().map(|&offset| (offset, template.expand(offset as u64, file_len))).collect()
+    } else {
+        offsets.iter().map(|&offset| (offset, to_insert.to_vec())).collect()
+    };
+    let nothing_to_insert = insertions.iter().all(|(_, bytes)| bytes.is_empty());
+    if nothing_to_insert && insert_config.reject_empty {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Nothing to insert, the payload is empty",
+        ));
+    }
+    let inserted: u64 = insertions.iter().map(|(_, bytes)| bytes.len() as u64).sum();
+    let report = InsertReport { offsets: offsets.clone(), inserted, bytes_written: file_len + inserted };
+    if insert_config.dry_run {
+        if !insert_config.in_place {
+            util::ensure_different_files(input_filename, output_filename)?;
+        }
+        check_offsets(&offsets, file_len)?;
+        if !insert_config.quiet {
+            let offsets: Vec<_> = offsets.iter().map(|offset| offset.to_string()).collect();
+            let plural = if offsets.len() == 1 { "" } else { "s" };
+            println!(
+                "Would insert {} bytes at offset{} {}, new size {}",
+                inserted,
+                plural,
+                offsets.join(", "),
+                report.bytes_written,
+            );
+        }
+        return Ok(report);
+    }
+    let insertions = &insertions;
+    let to_stdout = output_filename == Path::new("-");
+    if to_stdout {
+        if insert_config.in_place {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "In place mode needs an output file, it can't be stdout",
+            ));
+        }
+        let mut output = BufWriter::new(io::stdout().lock());
+        insert_each_to_writer(insertions, input_filename, &mut output, progress)?;
+        output.flush()?;
+    } else if insert_config.in_place && nothing_to_insert {
+        // Rewriting the file would give back the same bytes
+        check_offsets(&offsets, file_len)?;
+    } else {
+        let in_place = insert_config.in_place;
+        insert_each_with_progress(insertions, input_filename, output_filename, in_place, progress)?;
+    }
+    // Don't mix the messages with the data written to stdout
+    let message = if insert_config.json {
+        let inserted = insertions.iter().map(|(offset, bytes)| (*offset, bytes.len()));
+        inserted_to_json(inserted)
+    } else if !insert_config.quiet && nothing_to_insert {
+        "Nothing to insert, the output is the same as the input".to_string()
+    } else if !insert_config.quiet {
+        "Inserting was successful".to_string()
+    } else {
+        return Ok(report);
+    };
+    if to_stdout {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
     }
     
-    Ok(())
+    Ok(report)
+}
+
+/// Serialize the offsets given to `insert_many()` as a JSON array of
+/// `{ "inserted_at": ..., "bytes": ... }` objects, one per offset.
+pub fn insert_results_to_json(offsets: &[usize], len: usize) -> String {
+    inserted_to_json(offsets.iter().map(|&offset| (offset, len)))
+}
+
+/// Same as `insert_results_to_json()`, for `(offset, length)` pairs.
+fn inserted_to_json(inserted: impl Iterator<Item = (usize, usize)>) -> String {
+    let inserted: Vec<_> = inserted
+        .map(|(offset, len)| serde_json::json!({ "inserted_at": offset, "bytes": len }))
+        .collect();
+
+    serde_json::Value::Array(inserted).to_string()
 }
 
 /// Insert bytes from `to_insert` in offset specified in `offset`
-/// counting from 0. Results are saved in `output_filename`.
+/// counting from 0. Results are saved in `output_filename`. An empty
+/// `to_insert` makes the output a plain copy of the input.
 pub fn insert(
     to_insert: &[u8],
     offset: usize,
     input_filename: &Path,
     output_filename: &Path,
+) -> Result<(), io::Error> {
+    insert_many(to_insert, &[offset], input_filename, output_filename)
+}
+
+/// Same as `insert()`, except `to_insert` is inserted at every offset
+/// in `offsets`. The offsets are in the coordinates of the original
+/// input file, so they don't have to account for the earlier
+/// insertions growing the file. They don't have to be sorted and the
+/// same offset given twice inserts the bytes twice.
+pub fn insert_many(
+    to_insert: &[u8],
+    offsets: &[usize],
+    input_filename: &Path,
+    output_filename: &Path,
+) -> Result<(), io::Error> {
+    insert_many_with_progress(to_insert, offsets, input_filename, output_filename, &mut |_, _| {})
+}
+
+/// Same as `insert_many()`, except the result is written to `output`,
+/// e.g. stdout or an in-memory buffer, instead of a file.
+pub fn insert_many_to_writer<W: Write>(
+    to_insert: &[u8],
+    offsets: &[usize],
+    input_filename: &Path,
+    output: &mut W,
+) -> Result<(), io::Error> {
+    let insertions: Vec<_> = offsets.iter().map(|&offset| (offset, to_insert)).collect();
+    insert_each_to_writer(&insertions, input_filename, output, &mut |_, _| {})
+}
+
+/// Check `offsets` the same way `insert_many()` does, without writing
+/// anything. Return the size the output file would have.
+pub fn insert_dry_run(
+    to_insert: &[u8],
+    offsets: &[usize],
+    input_filename: &Path,
+) -> Result<u64, io::Error> {
+    let file_len = util::file_len(&File::open(input_filename)?)?;
+    check_offsets(offsets, file_len)?;
+
+    Ok(file_len + (to_insert.len() * offsets.len()) as u64)
+}
+
+/// Return an error if any of `offsets` is past the end of the file.
+fn check_offsets(offsets: &[usize], file_len: u64) -> Result<(), io::Error> {
+    if let Some(&offset) = offsets.iter().max().filter(|&&offset| offset as u64 > file_len) {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            format!("Offset {} is past the end of the file", offset),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Same as `insert_many()`, except `progress` is periodically called
+/// with the number of input bytes copied so far and the input size.
+pub fn insert_many_with_progress(
+    to_insert: &[u8],
+    offsets: &[usize],
+    input_filename: &Path,
+    output_filename: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let insertions: Vec<_> = offsets.iter().map(|&offset| (offset, to_insert)).collect();
+    insert_each_with_progress(&insertions, input_filename, output_filename, false, progress)
+}
+
+/// Same as `insert_many_with_progress()`, except every offset comes
+/// with its own bytes to insert. Bytes given for the same offset are
+/// inserted in the order they're given in. With `in_place` the output
+/// may be the input, it's only replaced once everything is written.
+fn insert_each_with_progress<B: AsRef<[u8]>>(
+    insertions: &[(usize, B)],
+    input_filename: &Path,
+    output_filename: &Path,
+    in_place: bool,
+    progress: &mut dyn FnMut(u64, u64),
 ) -> Result<(), io::Error> {
     let mut input_file = util::open_file(input_filename)?;
-    let mut output_file = BufWriter::new(File::create(output_filename)?);
-
-    // This will crash if there's not enough RAM but it's good enough for now.
-    // Why Rust doesn't have sendfile(2)?!
-    // A simple solution would be to read the file in chunks.
-    let mut buf = vec![0u8; offset];
-    input_file.file.read_exact(&mut buf)?;
-    output_file.write_all(&buf)?;
-    output_file.write_all(to_insert)?;
-    let mut buf = Vec::new();
-    input_file.file.read_to_end(&mut buf)?;
-    output_file.write_all(&buf)?;
+    if !in_place {
+        util::ensure_different_files(input_filename, output_filename)?;
+    }
+    let insertions = sorted_insertions(insertions, &input_file)?;
+    util::write_atomically(output_filename, |output_file| {
+        if insertions.iter().all(|(_, to_insert)| to_insert.as_ref().is_empty()) {
+            let copied = io::copy(&mut input_file.file, output_file)?;
+            progress(copied, copied);
+        } else {
+            write_insertions(&insertions, &mut input_file, output_file, progress)?;
+        }
+        output_file.flush()?;
+        util::copy_metadata(input_file.file.get_ref(), output_file.get_ref())
+    })?;
+
+    Ok(())
+}
+
+/// Offset of the `nth` match of the pattern of `anchor` in
+/// `input_filename`, counting from 0: where the match ends for
+/// `InsertAnchor::After` and where it starts for `Before`. Return an
+/// error if the pattern doesn't occur that many times.
+pub fn anchor_offset(
+    input_filename: &Path,
+    anchor: &InsertAnchor,
+    nth: usize,
+) -> Result<usize, io::Error> {
+    let (pattern, after) = match anchor {
+        InsertAnchor::After(pattern) => (pattern, true),
+        InsertAnchor::Before(pattern) => (pattern, false),
+    };
+    if pattern.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The pattern to insert next to is empty",
+        ));
+    }
+    let searcher = Searcher::new(pattern);
+    let found = searcher.search(File::open(input_filename)?).nth(nth).ok_or_else(|| {
+        let message = if nth == 0 {
+            format!("Pattern {} wasn't found", util::escape_bytes(pattern))
+        } else {
+            format!("Pattern {} occurs fewer than {} times", util::escape_bytes(pattern), nth + 1)
+        };
+        io::Error::new(io::ErrorKind::InvalidInput, message)
+    })?;
+    let offset = if after { found + pattern.len() as u64 } else { found };
+
+    Ok(offset as usize)
+}
+
+/// Same as `insert_each_with_progress()`, except the result is written
+/// to `output`.
+fn insert_each_to_writer<B: AsRef<[u8]>, W: Write>(
+    insertions: &[(usize, B)],
+    input_filename: &Path,
+    output: &mut W,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let mut input_file = util::open_file(input_filename)?;
+    let insertions = sorted_insertions(insertions, &input_file)?;
+    write_insertions(&insertions, &mut input_file, output, progress)
+}
+
+/// Sort `insertions` by their offsets. Return an error if any of them
+/// is past the end of `input_file`, before anything gets written.
+fn sorted_insertions<'a, B>(
+    insertions: &'a [(usize, B)],
+    input_file: &util::OpenedFile,
+) -> Result<Vec<&'a (usize, B)>, io::Error> {
+    let file_len = input_file.file.get_ref().metadata()?.len();
+    let mut insertions: Vec<_> = insertions.iter().collect();
+    insertions.sort_by_key(|(offset, _)| *offset);
+    let offsets: Vec<_> = insertions.iter().map(|(offset, _)| *offset).collect();
+    check_offsets(&offsets, file_len)?;
+
+    Ok(insertions)
+}
+
+/// Copy `input_file` to `output` with the sorted `insertions` in it.
+fn write_insertions<B: AsRef<[u8]>, W: Write>(
+    insertions: &[&(usize, B)],
+    input_file: &mut util::OpenedFile,
+    output: &mut W,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let file_len = input_file.file.get_ref().metadata()?.len();
+    let mut progress = util::Progress::new(file_len, progress);
+
+    // Copying works in chunks, so we never hold the whole file in memory
+    let mut last_offset = 0;
+    for (offset, to_insert) in insertions.iter() {
+        let to_copy = (offset - last_offset) as u64;
+        util::copy_exact_with_progress(&mut input_file.file, output, to_copy, &mut progress)?;
+        output.write_all(to_insert.as_ref())?;
+        last_offset = *offset;
+    }
+    util::copy_with_progress(&mut input_file.file, output, &mut progress)?;
 
     Ok(())
 }
@@ -75,4 +394,204 @@ mod tests {
         assert_eq!(res.len(), 1);
         assert_eq!(res[0].1, vec![4, 5]);
     }
+
+    #[test]
+    fn insert_test_same_file() {
+        let file = Path::new("test_files/file_three_insert_same_file");
+        std::fs::copy("test_files/file_three", file).unwrap();
+        let res = insert(b"meow", 0, file, file);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(std::fs::read(file).unwrap(), b"\x00\x00\x01\x01\xfe");
+
+        let cfg = InsertConfig { in_place: true, quiet: true, ..Default::default() };
+        let report = insert_command(b"meow", &[Offset::FromStart(2)], file, file, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(std::fs::read(file).unwrap(), b"\x00\x00meow\x01\x01\xfe");
+        // Quiet only leaves out the messages
+        assert_eq!(report, InsertReport { offsets: vec![2], inserted: 4, bytes_written: 9 });
+    }
+
+    #[test]
+    fn insert_test_no_clobber() {
+        let file = Path::new("test_files/file_three");
+        let output = Path::new("test_files/file_three_insert_no_clobber");
+        std::fs::write(output, b"meow").unwrap();
+        let cfg = InsertConfig { no_clobber: true, quiet: true, ..Default::default() };
+        let res = insert_command(b"nya", &[Offset::FromStart(2)], file, output, &cfg, &mut |_, _| {});
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(output).unwrap(), b"meow");
+
+        std::fs::remove_file(output).unwrap();
+        insert_command(b"nya", &[Offset::FromStart(2)], file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"\x00\x00nya\x01\x01\xfe");
+    }
+
+    #[test]
+    fn insert_test_many() {
+        insert_many(
+            b"meow",
+            &[4, 2],
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_insert_many")
+        ).expect("Probably a file related error");
+
+        // Offsets refer to the original file, not the growing one
+        let file = vec!["test_files/file_three_insert_many"];
+        let res = grep::grep(b"meow", &file).unwrap();
+        assert_eq!(res[0].1, vec![2, 8]);
+        let res = grep::grep(b"\xfe", &file).unwrap();
+        assert_eq!(res[0].1, vec![12]);
+    }
+
+    #[test]
+    fn insert_test_past_eof() {
+        let res = insert(
+            b"meow",
+            6,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_insert_past_eof")
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn insert_test_empty_file() {
+        let file = Path::new("test_files/file_empty");
+        let output = Path::new("test_files/file_empty_insert");
+        insert(b"meow", 0, file, output).expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"meow");
+
+        // The start and the end of an empty file are the same offset
+        let cfg = InsertConfig { quiet: true, ..Default::default() };
+        let offsets = [Offset::FromStart(0), Offset::FromEnd(0)];
+        insert_command(b"meow", &offsets, file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"meowmeow");
+
+        let res = insert(b"meow", 1, file, output);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn insert_test_empty_payload() {
+        let file = Path::new("test_files/file_three");
+        let output = Path::new("test_files/file_three_insert_empty");
+        insert_many(b"", &[4, 0], file, output).expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), std::fs::read(file).unwrap());
+        // Offsets are still checked
+        let res = insert(b"", 6, file, output);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+
+        let cfg = InsertConfig { reject_empty: true, quiet: true, ..Default::default() };
+        let res = insert_command(b"", &[Offset::FromStart(0)], file, output, &cfg, &mut |_, _| {});
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn insert_test_writer() {
+        let file = Path::new("test_files/file_three");
+        let output = Path::new("test_files/file_three_insert_writer");
+        insert_many(b"meow", &[4, 0], file, output).expect("Probably a file related error");
+
+        let mut buf = Vec::new();
+        insert_many_to_writer(b"meow", &[4, 0], file, &mut buf).unwrap();
+        assert_eq!(buf, std::fs::read(output).unwrap());
+
+        let res = insert_many_to_writer(b"meow", &[6], file, &mut Vec::new());
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn insert_test_from_end() {
+        let file = Path::new("test_files/file_three");
+        let output = Path::new("test_files/file_three_insert_from_end");
+        let cfg = InsertConfig { quiet: true, ..Default::default() };
+        let offsets = ["-0".parse().unwrap(), "-2".parse().unwrap()];
+        assert_eq!(offsets, [Offset::FromEnd(0), Offset::FromEnd(2)]);
+        insert_command(b"meow", &offsets, file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"\x00\x00\x01meow\x01\xfemeow");
+
+        let res = insert_command(b"meow", &[Offset::FromEnd(6)], file, output, &cfg, &mut |_, _| {});
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn insert_test_dry_run() {
+        let file = Path::new("test_files/file_three");
+        let output = Path::new("test_files/file_three_insert_dry_run");
+        let _ = std::fs::remove_file(output);
+        let cfg = InsertConfig { dry_run: true, quiet: true, ..Default::default() };
+        insert_command(b"meow", &[Offset::FromStart(2)], file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert!(!output.exists());
+
+        assert_eq!(insert_dry_run(b"meow", &[2, 5], file).unwrap(), 13);
+        let res = insert_dry_run(b"meow", &[6], file);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn insert_test_template() {
+        let file = Path::new("test_files/file_three");
+        let output = Path::new("test_files/file_three_insert_template");
+        let cfg = InsertConfig { template: true, quiet: true, ..Default::default() };
+        let offsets = [Offset::FromStart(0), Offset::FromEnd(0)];
+        insert_command(b"LEN=%size%@%offset%", &offsets, file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"LEN=5@0\x00\x00\x01\x01\xfeLEN=5@5");
+    }
+
+    #[test]
+    fn insert_json_test() {
+        let json: serde_json::Value =
+            serde_json::from_str(&insert_results_to_json(&[3, 0], 4)).unwrap();
+        assert_eq!(json, serde_json::json!([
+            { "inserted_at": 3, "bytes": 4 },
+            { "inserted_at": 0, "bytes": 4 },
+        ]));
+    }
+
+    #[test]
+    fn insert_test_progress() {
+        let mut reported = Vec::new();
+        insert_many_with_progress(
+            b"meow",
+            &[40, 80],
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_insert_progress"),
+            &mut |done, total| reported.push((done, total)),
+        ).expect("Probably a file related error");
+        assert_eq!(reported, vec![(40, 127), (80, 127), (127, 127)]);
+    }
+
+    #[test]
+    fn insert_test_anchor() {
+        let file = Path::new("test_files/file_one");
+        let output = Path::new("test_files/file_one_insert_anchor");
+        let original = std::fs::read(file).unwrap();
+        let after = InsertAnchor::After(b"nya".to_vec());
+        let cfg = InsertConfig { quiet: true, anchor: Some(after.clone()), ..Default::default() };
+        let report = insert_command(b"XX", &[Offset::FromStart(0)], file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(report.offsets, vec![6]);
+        let inserted = std::fs::read(output).unwrap();
+        assert_eq!(&inserted[..10], b"NyanyaXXme");
+        assert_eq!(inserted[8..], original[6..]);
+
+        let before = InsertAnchor::Before(b"nya".to_vec());
+        assert_eq!(anchor_offset(file, &before, 1).unwrap(), 9);
+        let cfg = InsertConfig { quiet: true, anchor: Some(before), nth: 1, ..Default::default() };
+        let report = insert_command(b"XX", &[Offset::FromEnd(1)], file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(report.offsets, vec![8]);
+
+        let missing = InsertAnchor::After(b"meow".to_vec());
+        let err = anchor_offset(file, &missing, 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), "Pattern meow wasn't found");
+        let err = anchor_offset(file, &after, 18).unwrap_err();
+        assert_eq!(err.to_string(), "Pattern nya occurs fewer than 19 times");
+    }
 }
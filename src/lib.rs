@@ -1,9 +1,40 @@
 pub mod grep;
 pub mod replace;
 pub mod insert;
+pub mod fill;
+pub mod patch;
+pub mod entropy;
+pub mod swap;
+pub mod endian;
+pub mod cut;
+pub mod size;
+pub mod hash;
+pub mod peek;
+pub mod searcher;
+pub mod replacer;
+pub mod template;
+pub mod format;
+pub mod concat;
+pub mod error;
 
 pub use grep::*;
 pub use replace::*;
 pub use insert::*;
+pub use fill::*;
+pub use patch::*;
+pub use entropy::*;
+pub use swap::*;
+pub use endian::*;
+pub use cut::*;
+pub use size::*;
+pub use hash::*;
+pub use peek::*;
+pub use searcher::*;
+pub use replacer::*;
+pub use template::Template;
+pub use format::GrepFormat;
+pub use concat::ConcatReader;
+pub use error::Error;
+pub use util::{ensure_no_clobber, find_in_slice, unescape, CaseFold, FoundMatch, Offset};
 
 mod util;
@@ -1,10 +1,18 @@
 mod command;
 
-use crate::command::Cli;
+use crate::command::{Cli, EXIT_ERROR};
+
+use std::process;
 
 use clap::Parser;
 
 fn main() {
     let cli = Cli::parse();
-    cli.exec();
+    match cli.exec() {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("{} encountered error: {}", cli.command.name(), e);
+            process::exit(EXIT_ERROR);
+        }
+    }
 }
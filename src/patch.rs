@@ -0,0 +1,255 @@
+use std::io;
+use std::io::Write;
+use std::path::Path;
+use std::fs;
+use crate::util;
+
+#[derive(Default, Debug)]
+pub struct PatchConfig {
+    pub quiet: bool,
+}
+
+/// A single overwrite: `bytes` are written over the input starting at
+/// `offset`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatchEdit {
+    pub offset: u64,
+    pub bytes: Vec<u8>,
+}
+
+/// Function for executing the command line patch command. You
+/// probably want to use `patch()` instead.
+pub fn patch_command(
+    spec_filename: &Path,
+    input_filename: &Path,
+    output_filename: &Path,
+    patch_config: &PatchConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let edits = parse_patch_spec(&fs::read_to_string(spec_filename)?)?;
+    patch_with_progress(&edits, input_filename, output_filename, progress)?;
+    if !patch_config.quiet {
+        println!("Applied {} patches successfully", edits.len());
+    }
+
+    Ok(())
+}
+
+/// Parse a patch spec. Every line has the form `offset: hexbytes`,
+/// where the offset is in decimal or with a 0x prefix and the bytes
+/// are hex digits, optionally separated with spaces. Empty lines and
+/// lines starting with `#` are skipped. Malformed lines and edits
+/// overlapping each other are reported with their line numbers,
+/// counting from 1.
+pub fn parse_patch_spec(spec: &str) -> Result<Vec<PatchEdit>, io::Error> {
+    let mut edits = Vec::new();
+    let mut line_numbers = Vec::new();
+    for (n, line) in spec.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let edit = parse_patch_line(line).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", n + 1, e))
+        })?;
+        edits.push(edit);
+        line_numbers.push(n + 1);
+    }
+    check_overlaps(&edits, &line_numbers)?;
+
+    Ok(edits)
+}
+
+/// Return an error naming the lines of the first two edits which
+/// write over the same bytes, since the result would depend on their
+/// order.
+fn check_overlaps(edits: &[PatchEdit], line_numbers: &[usize]) -> Result<(), io::Error> {
+    let mut order: Vec<usize> = (0..edits.len()).collect();
+    order.sort_by_key(|&n| edits[n].offset);
+    // The edit reaching the furthest so far, the only one a later
+    // edit has to be checked against
+    let mut furthest: Option<(usize, u64)> = None;
+    for n in order {
+        let end = edits[n].offset.saturating_add(edits[n].bytes.len() as u64);
+        if let Some((previous, previous_end)) = furthest {
+            if edits[n].offset < previous_end {
+                let (first, second) = if line_numbers[previous] < line_numbers[n] {
+                    (line_numbers[previous], line_numbers[n])
+                } else {
+                    (line_numbers[n], line_numbers[previous])
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Lines {} and {}: the edits overlap", first, second),
+                ));
+            }
+        }
+        if furthest.is_none_or(|(_, previous_end)| end > previous_end) {
+            furthest = Some((n, end));
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_patch_line(line: &str) -> Result<PatchEdit, String> {
+    let (offset, hex) = line.split_once(':').ok_or("expected `offset: hexbytes`")?;
+    let offset = offset.trim();
+    let offset = match offset.strip_prefix("0x").or_else(|| offset.strip_prefix("0X")) {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => offset.parse(),
+    }.map_err(|e| format!("invalid offset {}: {}", offset, e))?;
+
+    let hex: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if hex.is_empty() || !hex.len().is_multiple_of(2) {
+        return Err("expected an even, non-zero number of hex digits".to_string());
+    }
+    if let Some(&b) = hex.iter().find(|b| !b.is_ascii_hexdigit()) {
+        return Err(format!("invalid hex digit {}", b as char));
+    }
+    let bytes = hex
+        .chunks(2)
+        .map(|digits| {
+            let digits = std::str::from_utf8(digits).expect("Hex digits are ASCII");
+            u8::from_str_radix(digits, 16).expect("Hex digits were checked")
+        })
+        .collect();
+
+    Ok(PatchEdit { offset, bytes })
+}
+
+/// Overwrite the bytes of `input_filename` as described by `edits`,
+/// all in a single pass over the file. Results are saved in
+/// `output_filename`. The edits don't have to be sorted, but they
+/// can't overlap and can't write past the end of the file.
+pub fn patch(
+    edits: &[PatchEdit],
+    input_filename: &Path,
+    output_filename: &Path,
+) -> Result<(), io::Error> {
+    patch_with_progress(edits, input_filename, output_filename, &mut |_, _| {})
+}
+
+/// Same as `patch()`, except `progress` is periodically called with
+/// the number of input bytes processed so far and the input size.
+pub fn patch_with_progress(
+    edits: &[PatchEdit],
+    input_filename: &Path,
+    output_filename: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let mut input_file = util::open_file(input_filename)?;
+    util::ensure_different_files(input_filename, output_filename)?;
+    let file_len = input_file.file.get_ref().metadata()?.len();
+
+    let mut edits: Vec<&PatchEdit> = edits.iter().collect();
+    edits.sort_by_key(|edit| edit.offset);
+    let mut last_end = 0;
+    for edit in edits.iter() {
+        let end = edit.offset.saturating_add(edit.bytes.len() as u64);
+        if end > file_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Patch at offset {} exceeds the end of the file", edit.offset),
+            ));
+        }
+        if edit.offset < last_end {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Patch at offset {} overlaps the previous one", edit.offset),
+            ));
+        }
+        last_end = end;
+    }
+    let mut progress = util::Progress::new(file_len, progress);
+    util::write_atomically(output_filename, |output_file| {
+        let mut last_offset = 0;
+        for edit in edits.iter() {
+            let to_copy = edit.offset - last_offset;
+            util::copy_exact_with_progress(&mut input_file.file, output_file, to_copy, &mut progress)?;
+            output_file.write_all(&edit.bytes)?;
+            input_file.file.seek_relative(edit.bytes.len() as i64)?;
+            progress.advance(edit.bytes.len() as u64);
+            last_offset = edit.offset + edit.bytes.len() as u64;
+        }
+        util::copy_with_progress(&mut input_file.file, output_file, &mut progress)?;
+        output_file.flush()?;
+        util::copy_metadata(input_file.file.get_ref(), output_file.get_ref())
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn simple_patch_test() {
+        let edits = parse_patch_spec("# two edits\n0x4: fe ff\n\n0: 41\n").unwrap();
+        assert_eq!(edits, vec![
+            PatchEdit { offset: 4, bytes: vec![0xfe, 0xff] },
+            PatchEdit { offset: 0, bytes: vec![0x41] },
+        ]);
+        patch(
+            &edits,
+            Path::new("test_files/file_one"),
+            Path::new("test_files/file_one_patch"),
+        ).expect("Probably a file related error");
+
+        let patched = std::fs::read("test_files/file_one_patch").unwrap();
+        let original = std::fs::read("test_files/file_one").unwrap();
+        assert_eq!(&patched[..6], b"Ayan\xfe\xff");
+        assert_eq!(patched[6..], original[6..]);
+    }
+
+    #[test]
+    fn patch_test_malformed_line() {
+        let res = parse_patch_spec("0: 41\n1: 4\n");
+        let err = res.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("Line 2:"));
+    }
+
+    #[test]
+    fn patch_test_overlapping_lines() {
+        let res = parse_patch_spec("0: 41\n# overlaps the next one\n0x10: 41424344\n0x12: 45\n");
+        let err = res.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with("Lines 3 and 4:"));
+        let res = parse_patch_spec("0x12: 45\n0x10: 41424344\n");
+        assert!(res.unwrap_err().to_string().starts_with("Lines 1 and 2:"));
+
+        let edits = parse_patch_spec("2: 4242\n0: 4141\n4: 43\n").unwrap();
+        patch(
+            &edits,
+            Path::new("test_files/file_one"),
+            Path::new("test_files/file_one_patch_adjacent"),
+        ).expect("Probably a file related error");
+        let patched = std::fs::read("test_files/file_one_patch_adjacent").unwrap();
+        assert_eq!(&patched[..6], b"AABBCa");
+    }
+
+    #[test]
+    fn patch_test_invalid_edits() {
+        let overlapping = [
+            PatchEdit { offset: 0, bytes: vec![0x41, 0x41] },
+            PatchEdit { offset: 1, bytes: vec![0x42] },
+        ];
+        let res = patch(
+            &overlapping,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_patch_invalid"),
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let past_eof = parse_patch_spec("4: 4141").unwrap();
+        let res = patch(
+            &past_eof,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_patch_invalid"),
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
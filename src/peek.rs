@@ -0,0 +1,73 @@
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::fs::File;
+use crate::util::{self, Offset};
+
+#[derive(Default, Debug)]
+pub struct PeekConfig {
+    pub ascii: bool,
+}
+
+/// Function for executing the command line peek command. You probably
+/// want to use `peek()` instead. Print the bytes as space separated
+/// hex digits, or as a hexdump with an ASCII column with `ascii`, and
+/// return them.
+pub fn peek_command(
+    input_filename: &Path,
+    offset: Offset,
+    len: usize,
+    peek_config: &PeekConfig,
+) -> Result<Vec<u8>, io::Error> {
+    let (start, bytes) = peek_at(input_filename, offset, len)?;
+    if peek_config.ascii {
+        print!("{}", util::hexdump(start, &bytes));
+    } else {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("{}", hex.join(" "));
+    }
+
+    Ok(bytes)
+}
+
+/// Read `len` bytes of `input_filename` starting at `offset`, without
+/// reading the rest of the file. Offsets counted from the end are
+/// resolved against the size of the file. Return an error if the range
+/// doesn't fit in the file.
+pub fn peek(input_filename: &Path, offset: Offset, len: usize) -> Result<Vec<u8>, io::Error> {
+    peek_at(input_filename, offset, len).map(|(_, bytes)| bytes)
+}
+
+fn peek_at(input_filename: &Path, offset: Offset, len: usize) -> Result<(u64, Vec<u8>), io::Error> {
+    let mut file = File::open(input_filename)?;
+    let file_len = util::file_len(&file)?;
+    let start = offset.resolve(file_len)? as u64;
+    if start.checked_add(len as u64).is_none_or(|end| end > file_len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Range {}..{} doesn't fit in the file", start, start.saturating_add(len as u64)),
+        ));
+    }
+    file.seek(SeekFrom::Start(start))?;
+    let mut bytes = vec![0; len];
+    file.read_exact(&mut bytes)?;
+
+    Ok((start, bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn simple_peek_test() {
+        let file = Path::new("test_files/file_three");
+        assert_eq!(peek(file, Offset::FromStart(2), 2).unwrap(), b"\x01\x01");
+        assert_eq!(peek(file, Offset::FromEnd(1), 1).unwrap(), b"\xfe");
+        assert_eq!(peek(file, Offset::FromStart(5), 0).unwrap(), b"");
+
+        let res = peek(file, Offset::FromStart(3), 3);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = peek(file, Offset::FromStart(usize::MAX), 2);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -1,46 +1,727 @@
 use std::io;
-use std::io::{Write, Seek, Read, BufReader, BufWriter};
-use std::fs::File;
-use std::path::Path;
+use std::io::{Write, Read, BufRead, BufReader, BufWriter, IsTerminal, Seek, SeekFrom};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use regex::bytes::Captures;
+
+use crate::grep;
+use crate::searcher::Searcher;
+use crate::template::Template;
 use crate::util;
 
 #[derive(Default, Debug)]
 pub struct ReplaceConfig {
     pub quiet: bool,
     pub nth: usize,
+    pub from_end: bool,
     pub replace_all: bool,
+    pub limit: Option<usize>,
     pub fill_byte: u8,
     pub allow_length_change: bool,
+    pub regex: bool,
+    pub interactive: bool,
+    pub in_place: bool,
+    pub force: bool,
+    pub json: bool,
+    pub recursive: bool,
+    pub output_dir: Option<PathBuf>,
+    pub template: bool,
+    pub verify: bool,
+    pub swap: bool,
+    pub print_offsets: Option<OffsetStyle>,
+    pub print0: bool,
+    pub no_clobber: bool,
+}
+
+/// How the replaced offsets are printed with `print_offsets`.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetStyle {
+    #[default]
+    Decimal,
+    /// Lowercase hex digits with a 0x prefix.
+    Hex,
+}
+
+impl ReplaceConfig {
+    /// Start building a config, validated when `build()` is called.
+    pub fn builder() -> ReplaceConfigBuilder {
+        ReplaceConfigBuilder::default()
+    }
+}
+
+/// Builder returned by `ReplaceConfig::builder()`.
+#[derive(Default, Debug)]
+pub struct ReplaceConfigBuilder {
+    config: ReplaceConfig,
+    nth_set: bool,
+}
+
+impl ReplaceConfigBuilder {
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.config.quiet = quiet;
+        self
+    }
+
+    pub fn nth(mut self, nth: usize) -> Self {
+        self.config.nth = nth;
+        self.nth_set = true;
+        self
+    }
+
+    pub fn from_end(mut self, from_end: bool) -> Self {
+        self.config.from_end = from_end;
+        self
+    }
+
+    pub fn replace_all(mut self, replace_all: bool) -> Self {
+        self.config.replace_all = replace_all;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.config.limit = Some(limit);
+        self
+    }
+
+    pub fn fill_byte(mut self, fill_byte: u8) -> Self {
+        self.config.fill_byte = fill_byte;
+        self
+    }
+
+    pub fn allow_length_change(mut self, allow_length_change: bool) -> Self {
+        self.config.allow_length_change = allow_length_change;
+        self
+    }
+
+    pub fn regex(mut self, regex: bool) -> Self {
+        self.config.regex = regex;
+        self
+    }
+
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.config.interactive = interactive;
+        self
+    }
+
+    pub fn in_place(mut self, in_place: bool) -> Self {
+        self.config.in_place = in_place;
+        self
+    }
+
+    pub fn force(mut self, force: bool) -> Self {
+        self.config.force = force;
+        self
+    }
+
+    pub fn json(mut self, json: bool) -> Self {
+        self.config.json = json;
+        self
+    }
+
+    /// Fail instead of overwriting an output file that already exists.
+    pub fn no_clobber(mut self, no_clobber: bool) -> Self {
+        self.config.no_clobber = no_clobber;
+        self
+    }
+
+    pub fn recursive(mut self, recursive: bool) -> Self {
+        self.config.recursive = recursive;
+        self
+    }
+
+    /// When replacing recursively, write the results under this
+    /// directory, at the same paths relative to the searched
+    /// directories, instead of replacing in place.
+    pub fn output_dir(mut self, output_dir: PathBuf) -> Self {
+        self.config.output_dir = Some(output_dir);
+        self
+    }
+
+    pub fn template(mut self, template: bool) -> Self {
+        self.config.template = template;
+        self
+    }
+
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.config.verify = verify;
+        self
+    }
+
+    /// Replace every match of the pattern with the replacing bytes and
+    /// every match of the replacing bytes with the pattern, see
+    /// `swap_patterns()`.
+    pub fn swap(mut self, swap: bool) -> Self {
+        self.config.swap = swap;
+        self
+    }
+
+    /// Print the replaced offsets instead of the message, one per line,
+    /// e.g. to pipe them into other tools.
+    pub fn print_offsets(mut self, style: OffsetStyle) -> Self {
+        self.config.print_offsets = Some(style);
+        self
+    }
+
+    /// End every printed offset with a NUL byte instead of a newline.
+    pub fn print0(mut self, print0: bool) -> Self {
+        self.config.print0 = print0;
+        self
+    }
+
+    /// Return the config, or an error if mutually exclusive options
+    /// were combined.
+    pub fn build(self) -> Result<ReplaceConfig, io::Error> {
+        if self.nth_set && self.config.replace_all {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "nth can't be used together with replace_all",
+            ));
+        }
+        if self.config.limit.is_some()
+            && (self.nth_set || self.config.from_end || self.config.replace_all) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "limit can't be used together with nth, from_end or replace_all",
+            ));
+        }
+        if self.config.from_end && self.config.replace_all {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "from_end can't be used together with replace_all",
+            ));
+        }
+        if self.config.regex && self.config.interactive {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Interactive mode can't be used together with regex",
+            ));
+        }
+        if self.config.recursive && self.config.in_place == self.config.output_dir.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "recursive works together with either in_place or output_dir",
+            ));
+        }
+        if self.config.swap && (self.nth_set || self.config.from_end || self.config.replace_all
+            || self.config.limit.is_some() || self.config.regex || self.config.interactive
+            || self.config.template) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "swap always replaces every match, it can't be used together with nth, \
+                 from_end, replace_all, limit, regex, interactive or template",
+            ));
+        }
+        if self.config.print_offsets.is_some() && (self.config.json || self.config.recursive) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "print_offsets can't be used together with JSON output or recursive",
+            ));
+        }
+        if self.config.print0 && self.config.print_offsets.is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "print0 only makes sense together with print_offsets",
+            ));
+        }
+        if self.config.no_clobber && self.config.in_place {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no_clobber can't be used together with in_place",
+            ));
+        }
+        if self.config.output_dir.is_some() && !self.config.recursive {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "output_dir only makes sense together with recursive",
+            ));
+        }
+
+        Ok(self.config)
+    }
 }
 
+/// Summary of a finished replace, for callers that want to log the
+/// results instead of scraping the printed message.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct ReplaceReport {
+    /// Number of replaced matches.
+    pub count: usize,
+    /// Offsets of the replaced matches in the input file.
+    pub offsets: Vec<u64>,
+    /// Size of the written output file.
+    pub bytes_written: u64,
+}
+
+impl ReplaceReport {
+    /// Serialize the report as a `{ "replaced": ..., "offsets": [...] }`
+    /// JSON object.
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "replaced": self.count,
+            "offsets": self.offsets,
+        }).to_string()
+    }
+
+    /// Write the replaced offsets to `out` in `style`, every one
+    /// followed by a newline, or by a NUL byte with `nul`.
+    pub fn write_offsets<W: Write>(&self, out: &mut W, style: OffsetStyle, nul: bool) -> Result<(), io::Error> {
+        let end = if nul { '\0' } else { '\n' };
+        for offset in self.offsets.iter() {
+            match style {
+                OffsetStyle::Decimal => write!(out, "{}{}", offset, end)?,
+                OffsetStyle::Hex => write!(out, "{:#x}{}", offset, end)?,
+            }
+        }
+        out.flush()
+    }
+}
+
+/// How many bytes of context to show on each side of a match when
+/// asking for confirmation in interactive mode.
+const INTERACTIVE_CONTEXT: usize = 16;
+
 /// Function for executing the command line replace command. You
-/// probably want to use `replace()` instead.
+/// probably want to use `replace()` instead. The report is returned
+/// in quiet mode too, only the message is left out.
 pub fn replace_command(
     to_replace: &[u8],
     replace_with: &[u8],
     input_filename: &Path,
     output_filename: &Path,
     replace_config: &ReplaceConfig,
-) -> Result<(), io::Error> {
-    if !replace_config.allow_length_change && replace_with.len() > to_replace.len() {
-        eprintln!("Replacing string is too long");
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<ReplaceReport, io::Error> {
+    let pipe = Path::new("-");
+    if input_filename != pipe {
+        ensure_keeps_executable_size(to_replace, replace_with, input_filename, replace_config)?;
     }
-    
-    let n = replace(to_replace, replace_with, input_filename, output_filename, replace_config)?;
-    if !replace_config.quiet {
-        if n == 1 {
-            println!("Replaced 1 match successfully");
+    if replace_config.verify && output_filename == pipe {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Verifying needs an output file, it can't be stdout",
+        ));
+    }
+    if replace_config.no_clobber {
+        util::ensure_no_clobber(output_filename)?;
+    }
+
+    // Pipes can't be seeked, so they are replaced in a single pass
+    if input_filename == pipe || output_filename == pipe {
+        let mut input: Box<dyn Read> = if input_filename == pipe {
+            Box::new(io::stdin().lock())
+        } else {
+            Box::new(File::open(input_filename)?)
+        };
+        let report = if output_filename == pipe {
+            replace_stream(to_replace, replace_with, &mut input, &mut io::stdout().lock(), replace_config)?
         } else {
-            println!("Replaced {} matches successfully", n);
+            util::write_atomically(output_filename, |output| {
+                replace_stream(to_replace, replace_with, &mut input, output, replace_config)
+            })?
+        };
+        if replace_config.verify {
+            verify_output(to_replace, replace_with, output_filename, &report, replace_config)?;
         }
+        // Don't mix the message with the replaced data
+        if let Some(style) = replace_config.print_offsets {
+            report.write_offsets(&mut io::stderr().lock(), style, replace_config.print0)?;
+        } else if replace_config.json {
+            eprintln!("{}", report.to_json());
+        } else if !replace_config.quiet {
+            eprintln!("{}", replaced_message(report.count));
+        }
+        return Ok(report);
+    }
+
+    // In place the output is written next to the input and renamed
+    // over it once it's complete
+    let report = replace_report(
+        to_replace,
+        replace_with,
+        input_filename,
+        output_filename,
+        replace_config,
+        progress,
+    )?;
+    if replace_config.verify {
+        verify_output(to_replace, replace_with, output_filename, &report, replace_config)?;
+    }
+    if let Some(style) = replace_config.print_offsets {
+        report.write_offsets(&mut io::stdout().lock(), style, replace_config.print0)?;
+    } else if replace_config.json {
+        println!("{}", report.to_json());
+    } else if !replace_config.quiet {
+        println!("{}", replaced_message(report.count));
     }
     
+    Ok(report)
+}
+
+/// Check if `filename` has anything `replace_report()` would replace,
+/// that is a match of `to_replace` or, when swapping, of either pattern.
+fn has_match(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    filename: &Path,
+    replace_config: &ReplaceConfig,
+) -> Result<bool, io::Error> {
+    if replace_config.regex {
+        let regex = util::compile_regex(to_replace, false)?;
+        return Ok(regex.is_match(&fs::read(filename)?));
+    }
+    if replace_config.swap && grep::contains(replace_with, filename)? {
+        return Ok(true);
+    }
+
+    grep::contains(to_replace, filename)
+}
+
+/// Function for executing the command line replace command in the
+/// recursive mode. Every file in `paths` and in the directories under
+/// them is replaced in place. Files without a match aren't rewritten.
+/// With `replace_config.output_dir` every file is written under it
+/// instead, mirroring the directory tree, matched or not. Return the
+/// report of every file with a match.
+pub fn replace_recursive_command<T: AsRef<Path>>(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    paths: &[T],
+    replace_config: &ReplaceConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<Vec<(PathBuf, ReplaceReport)>, io::Error> {
+    let mut reports = Vec::new();
+    for (filename, output_filename) in recursive_outputs(paths, replace_config.output_dir.as_deref())? {
+        ensure_keeps_executable_size(to_replace, replace_with, &filename, replace_config)?;
+        let report = match &output_filename {
+            Some(output_filename) => {
+                if replace_config.no_clobber {
+                    util::ensure_no_clobber(output_filename)?;
+                }
+                if let Some(parent) = output_filename.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                replace_report(
+                    to_replace,
+                    replace_with,
+                    &filename,
+                    output_filename,
+                    replace_config,
+                    progress,
+                )?
+            }
+            // Leave the file alone, rewriting it would only break its
+            // hard links
+            None if !has_match(to_replace, replace_with, &filename, replace_config)? => continue,
+            None => replace_report(
+                to_replace,
+                replace_with,
+                &filename,
+                &filename,
+                replace_config,
+                progress,
+            )?,
+        };
+        if replace_config.verify {
+            let written = output_filename.as_deref().unwrap_or(&filename);
+            verify_output(to_replace, replace_with, written, &report, replace_config)?;
+        }
+        if report.count != 0 {
+            if !replace_config.quiet && !replace_config.json {
+                println!("{}: {}", filename.display(), replaced_message(report.count));
+            }
+            reports.push((filename, report));
+        }
+    }
+    if replace_config.json {
+        let files: Vec<_> = reports
+            .iter()
+            .map(|(filename, report)| {
+                serde_json::json!({
+                    "file": filename.to_string_lossy(),
+                    "replaced": report.count,
+                    "offsets": report.offsets,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(files));
+    }
+
+    Ok(reports)
+}
+
+/// Every file in `paths` and in the directories under them, together
+/// with the file to write its result to under `output_dir`, at its
+/// path relative to the directory it was found in. Files given
+/// directly go right into `output_dir`. Without an `output_dir` the
+/// files are replaced in place, so there's no output file.
+fn recursive_outputs<T: AsRef<Path>>(
+    paths: &[T],
+    output_dir: Option<&Path>,
+) -> Result<Vec<(PathBuf, Option<PathBuf>)>, io::Error> {
+    let mut ret = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        let root = if path.is_dir() { path } else { path.parent().unwrap_or(Path::new("")) };
+        for filename in util::open_all_directories(&[path], false, &util::NameFilter::default(), None)? {
+            let output_filename = output_dir.map(|output_dir| {
+                output_dir.join(filename.strip_prefix(root).expect("Found under the root"))
+            });
+            ret.push((filename, output_filename));
+        }
+    }
+
+    Ok(ret)
+}
+
+/// Re-read `output_filename` written by a replace that returned `report`
+/// and check that it has the reported size and the replacement at
+/// every replaced offset, e.g. to catch a silently truncated write.
+/// Regex and template replacements differ per match, so only the size
+/// is checked for them. Nothing is checked if nothing was replaced.
+fn verify_output(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    output_filename: &Path,
+    report: &ReplaceReport,
+    replace_config: &ReplaceConfig,
+) -> Result<(), io::Error> {
+    if report.count == 0 {
+        return Ok(());
+    }
+    let mut output_file = File::open(output_filename)?;
+    let output_len = util::file_len(&output_file)?;
+    if output_len != report.bytes_written {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "Verifying failed, {} has {} bytes instead of {}",
+                output_filename.display(),
+                output_len,
+                report.bytes_written,
+            ),
+        ));
+    }
+    if replace_config.regex || replace_config.template || replace_config.swap {
+        return Ok(());
+    }
+
+    let replacement = replacement_fn(to_replace, replace_with, output_filename, replace_config)?(0, to_replace);
+    // Every earlier replacement moves the later ones by the difference
+    let shift = replacement.len() as i64 - to_replace.len() as i64;
+    let mut buf = vec![0u8; replacement.len()];
+    for (n, &offset) in report.offsets.iter().enumerate() {
+        let offset = (offset as i64 + n as i64 * shift) as u64;
+        output_file.seek(SeekFrom::Start(offset))?;
+        output_file.read_exact(&mut buf)?;
+        if buf != replacement {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Verifying failed, the replacement isn't at offset {} of {}",
+                    offset,
+                    output_filename.display(),
+                ),
+            ));
+        }
+    }
+
     Ok(())
 }
 
+/// Offsets in executables are all over the place, changing the size of
+/// one most likely breaks it. Return an error if the replacement would
+/// do that to `input_filename`, unless `force` is set.
+fn ensure_keeps_executable_size(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    input_filename: &Path,
+    replace_config: &ReplaceConfig,
+) -> Result<(), io::Error> {
+    let changes_size = replace_config.regex
+        || replace_with.len() > to_replace.len()
+        || (replace_config.allow_length_change
+            && (replace_config.template || replace_with.len() != to_replace.len()));
+    if !changes_size || replace_config.force {
+        return Ok(());
+    }
+    if let Some(format) = util::sniff_executable(input_filename)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "{} looks like an {} executable, changing its size will probably break it. \
+                 Use force to do it anyway",
+                input_filename.display(),
+                format,
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Return an error if `replace_with` is longer than `to_replace`
+/// while `allow_length_change` isn't set, it can't be replaced without
+/// changing the size. Shorter replacements are padded instead.
+pub(crate) fn ensure_fits(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    replace_config: &ReplaceConfig,
+) -> Result<(), io::Error> {
+    if !replace_config.allow_length_change && replace_with.len() > to_replace.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Replacing string is longer than the pattern, \
+             allow length change to replace it anyway",
+        ));
+    }
+
+    Ok(())
+}
+
+fn replaced_message(count: usize) -> String {
+    if count == 1 {
+        "Replaced 1 match successfully".to_string()
+    } else {
+        format!("Replaced {} matches successfully", count)
+    }
+}
+
+/// Same as `replace()`, except the data is read from `reader` and the
+/// result is written to `writer` in a single pass, so they don't have
+/// to be files, e.g. stdin and stdout. Only the last
+/// `to_replace.len() - 1` bytes are held back, in case a match
+/// straddles two reads. Counting `nth` from the end, regex,
+/// interactive and template mode need the whole input, so they aren't
+/// supported.
+pub fn replace_stream<R: Read, W: Write>(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    reader: &mut R,
+    writer: &mut W,
+    replace_config: &ReplaceConfig,
+) -> Result<ReplaceReport, io::Error> {
+    replace_stream_chunked(
+        to_replace,
+        replace_with,
+        reader,
+        writer,
+        replace_config,
+        util::PROGRESS_INTERVAL as usize,
+    )
+}
+
+fn replace_stream_chunked<R: Read, W: Write>(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    reader: &mut R,
+    writer: &mut W,
+    replace_config: &ReplaceConfig,
+    chunk_len: usize,
+) -> Result<ReplaceReport, io::Error> {
+    if replace_config.from_end || replace_config.regex || replace_config.interactive
+        || replace_config.template || replace_config.swap {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Replacing a stream doesn't support from_end, regex, interactive, template \
+             or swap mode",
+        ));
+    }
+    ensure_fits(to_replace, replace_with, replace_config)?;
+    let to_fill = if replace_config.allow_length_change {
+        0
+    } else {
+        to_replace.len().saturating_sub(replace_with.len())
+    };
+    let mut writer = BufWriter::new(writer);
+    let mut report = ReplaceReport::default();
+    let mut chunk = vec![0u8; chunk_len];
+    // Bytes read but not written yet, starting at `buf_offset` in the input
+    let mut buf: Vec<u8> = Vec::new();
+    let mut buf_offset = 0;
+    let mut seen = 0;
+    let mut match_end = 0;
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        buf.extend_from_slice(&chunk[..n]);
+
+        let mut written = 0;
+        let mut i = 0;
+        while !to_replace.is_empty() && i + to_replace.len() <= buf.len() {
+            if &buf[i..i + to_replace.len()] == to_replace {
+                let offset = buf_offset + i as u64;
+                let replace = if let Some(limit) = replace_config.limit {
+                    offset >= match_end && report.offsets.len() < limit
+                } else if replace_config.replace_all {
+                    offset >= match_end
+                } else {
+                    seen == replace_config.nth
+                };
+                seen += 1;
+                if replace {
+                    writer.write_all(&buf[written..i])?;
+                    writer.write_all(replace_with)?;
+                    writer.write_all(&vec![replace_config.fill_byte; to_fill])?;
+                    report.offsets.push(offset);
+                    report.bytes_written += (i - written + replace_with.len() + to_fill) as u64;
+                    written = i + to_replace.len();
+                    match_end = offset + to_replace.len() as u64;
+                    // The replaced bytes are consumed, matches overlapping
+                    // them are skipped
+                    if replace_config.replace_all || replace_config.limit.is_some() {
+                        i = written;
+                        continue;
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        // Everything before `i` was checked, the rest may be the start
+        // of a match continuing in the next read
+        let keep_from = if n == 0 { buf.len() } else { i.max(written) };
+        writer.write_all(&buf[written..keep_from])?;
+        report.bytes_written += (keep_from - written) as u64;
+        buf.drain(..keep_from);
+        buf_offset += keep_from as u64;
+        if n == 0 {
+            break;
+        }
+    }
+    writer.flush()?;
+    report.count = report.offsets.len();
+
+    Ok(report)
+}
+
+/// Read the replacing bytes from `filename`, or from stdin if it's
+/// `-`. Useful for binary replacements that can't be passed as an
+/// argument.
+pub fn read_replacement(filename: &Path) -> Result<Vec<u8>, io::Error> {
+    if filename == Path::new("-") {
+        let mut buf = Vec::new();
+        io::stdin().lock().read_to_end(&mut buf)?;
+        return Ok(buf);
+    }
+
+    fs::read(filename)
+}
+
 /// Replace the `to_replace` pattern in the file `input_filename` with
 /// bytes specified by `replace_with`. The result in saved in
-/// `output_filename`. Return the number of replaced patterns
+/// `output_filename`, which is written even if nothing matches, so an
+/// empty input gives an empty output. Return the number of replaced
+/// patterns.
+///
+/// Matches are replaced from left to right and never overlap. A match
+/// starting inside an already replaced one is skipped, so replacing
+/// `aa` with `bb` in `aaaaa` gives `bbbba`. `nth` still counts every
+/// match, overlapping ones too. A pattern longer than the input can't
+/// match, so the input is copied without searching it.
 pub fn replace(
     to_replace: &[u8],
     replace_with: &[u8],
@@ -48,65 +729,758 @@ pub fn replace(
     output_filename: &Path,
     replace_config: &ReplaceConfig,
 ) -> Result<usize, io::Error> {
-    let mut input_file = util::open_file(input_filename)?;
-    
-    let mut matches_iter = util::find_matches(&mut input_file, to_replace);
-    let found_matches: Vec<_>;
-
-    // Make it so later replacing the matches is a generic case,
-    // no matter if we're replacing one instance or all instances
-    if !replace_config.replace_all {
-        if let Some(offset) = matches_iter.nth(replace_config.nth) {
-            found_matches = vec![offset];
+    replace_with_progress(
+        to_replace,
+        replace_with,
+        input_filename,
+        output_filename,
+        replace_config,
+        &mut |_, _| {},
+    )
+}
+
+/// Same as `replace()`, except `progress` is periodically called with
+/// the number of bytes processed so far and the total. With
+/// `replace_all` or `limit` set the matches are replaced while they're
+/// found and the total is the input file size. Otherwise the input is
+/// gone through twice, once to find the match and once to write the
+/// output, so the total is twice the input file size.
+pub fn replace_with_progress(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<usize, io::Error> {
+    replace_report_offsets(
+        to_replace,
+        replace_with,
+        input_filename,
+        output_filename,
+        replace_config,
+        false,
+        progress,
+    ).map(|report| report.count)
+}
+
+/// Same as `replace_with_progress()`, except a `ReplaceReport` with
+/// the replaced offsets and the output size is returned instead of
+/// just the number of replaced matches.
+pub fn replace_report(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<ReplaceReport, io::Error> {
+    replace_report_offsets(
+        to_replace,
+        replace_with,
+        input_filename,
+        output_filename,
+        replace_config,
+        true,
+        progress,
+    )
+}
+
+/// Same as `replace_report()`, except the replaced offsets are only
+/// collected with `keep_offsets` set.
+fn replace_report_offsets(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    keep_offsets: bool,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<ReplaceReport, io::Error> {
+    if replace_config.swap {
+        return swap_patterns_with_progress(
+            to_replace,
+            replace_with,
+            input_filename,
+            output_filename,
+            replace_config,
+            progress,
+        );
+    }
+    if replace_config.regex {
+        if replace_config.interactive {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Interactive mode can't be used together with regex",
+            ));
+        }
+        // Capture group references are only expanded in the literal
+        // parts of a template, the bytes of its tokens can't produce
+        // them by accident
+        let template = if replace_config.template {
+            let file_len = util::file_len(&File::open(input_filename)?)?;
+            let template = Template::parse(replace_with)?;
+            template.fits(file_len)?;
+            Some((template, file_len))
         } else {
-            return Ok(0);
+            None
+        };
+        return replace_regex(
+            to_replace,
+            input_filename,
+            output_filename,
+            replace_config,
+            &mut |offset, captures, expanded| match &template {
+                Some((template, file_len)) => {
+                    template.expand_into(offset, *file_len, expanded, |literal, expanded| {
+                        captures.expand(literal, expanded)
+                    })
+                }
+                None => captures.expand(replace_with, expanded),
+            },
+            progress,
+        );
+    }
+
+    if replace_config.interactive {
+        // Don't hang waiting for answers that will never come
+        let stdin = io::stdin();
+        if !stdin.is_terminal() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Interactive mode needs stdin to be a terminal",
+            ));
+        }
+        return replace_interactive_report(
+            to_replace,
+            replace_with,
+            input_filename,
+            output_filename,
+            replace_config,
+            &mut stdin.lock(),
+            &mut io::stderr(),
+        );
+    }
+
+    let mut replacement = replacement_fn(to_replace, replace_with, input_filename, replace_config)?;
+    let mut offsets = Vec::new();
+    let mut report = replace_confirmed(
+        to_replace,
+        input_filename,
+        output_filename,
+        replace_config,
+        &mut |_| Ok(true),
+        &mut |offset, matched| {
+            if keep_offsets {
+                offsets.push(offset);
+            }
+            replacement(offset, matched)
+        },
+        progress,
+    )?;
+    report.offsets = offsets;
+
+    Ok(report)
+}
+
+/// Replace every match of `a` in `input_filename` with `b` and every
+/// match of `b` with `a`, saving the result in `output_filename`. All
+/// the matches are found before anything is written, so a swapped match
+/// is never swapped back like with two replaces one after another.
+/// Where matches overlap the one starting first wins, the longer one
+/// when they start together. The patterns have to be equally long
+/// unless `allow_length_change` is set. Return the report with the
+/// offsets of both kinds of matches.
+pub fn swap_patterns(
+    a: &[u8],
+    b: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+) -> Result<ReplaceReport, io::Error> {
+    swap_patterns_with_progress(a, b, input_filename, output_filename, replace_config, &mut |_, _| {})
+}
+
+/// Same as `swap_patterns()`, except `progress` is periodically called
+/// with the number of bytes written so far and the input file size.
+pub fn swap_patterns_with_progress(
+    a: &[u8],
+    b: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<ReplaceReport, io::Error> {
+    if a.is_empty() || b.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Patterns to swap can't be empty",
+        ));
+    }
+    if a.len() != b.len() && !replace_config.allow_length_change {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Patterns to swap have different lengths, allow changing the length to swap them anyway",
+        ));
+    }
+    let input_file = util::open_file(input_filename)?;
+    if !replace_config.in_place {
+        util::ensure_different_files(input_filename, output_filename)?;
+    }
+    let file_len = known_len(input_file.file.get_ref())?;
+
+    // Every match remembers whether it's one of `a`
+    let mut matches = Vec::new();
+    for (pattern, is_a) in [(a, true), (b, false)] {
+        let searcher = Searcher::new(pattern);
+        matches.extend(searcher.search(File::open(input_filename)?).map(|offset| (offset, is_a)));
+    }
+    let match_len = |is_a: bool| if is_a { a.len() } else { b.len() };
+    matches.sort_by(|x, y| x.0.cmp(&y.0).then(match_len(y.1).cmp(&match_len(x.1))));
+
+    let mut input_file = input_file.file;
+    let mut progress = util::Progress::new(file_len.unwrap_or(0), progress);
+    util::write_atomically(output_filename, |output_file| {
+        let mut report = ReplaceReport::default();
+        let mut last_offset = 0;
+        for (offset, is_a) in matches {
+            if offset < last_offset {
+                continue;
+            }
+            let (found, replacement) = if is_a { (a, b) } else { (b, a) };
+            let to_copy = offset - last_offset;
+            copy_segment(&mut input_file, output_file, last_offset, to_copy, file_len, &mut progress)?;
+            input_file.seek_relative(found.len() as i64)?;
+            progress.advance(found.len() as u64);
+            output_file.write_all(replacement)?;
+            report.offsets.push(offset);
+            report.bytes_written += to_copy + replacement.len() as u64;
+            last_offset = offset + found.len() as u64;
         }
+        report.bytes_written += copy_tail(&mut input_file, output_file, last_offset, file_len, &mut progress)?;
+        output_file.flush()?;
+        util::copy_metadata(input_file.get_ref(), output_file.get_ref())?;
+        report.count = report.offsets.len();
+
+        Ok(report)
+    })
+}
+
+/// Same as `replace()`, except before every replacement the offset and
+/// a hexdump of the surrounding bytes are written to `prompt` and an
+/// answer is read from `answers`. Only matches answered with `y` are
+/// replaced, everything else (including EOF) counts as a no.
+pub fn replace_interactive<R: BufRead, W: Write>(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    answers: &mut R,
+    prompt: &mut W,
+) -> Result<usize, io::Error> {
+    replace_interactive_report(
+        to_replace,
+        replace_with,
+        input_filename,
+        output_filename,
+        replace_config,
+        answers,
+        prompt,
+    ).map(|report| report.count)
+}
+
+fn replace_interactive_report<R: BufRead, W: Write>(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    answers: &mut R,
+    prompt: &mut W,
+) -> Result<ReplaceReport, io::Error> {
+    let mut replacement = replacement_fn(to_replace, replace_with, input_filename, replace_config)?;
+    let mut offsets = Vec::new();
+    // The context of every match is read through one opened file
+    let mut input = util::open_file(input_filename)?.file.into_inner();
+    let mut report = replace_confirmed(
+        to_replace,
+        input_filename,
+        output_filename,
+        replace_config,
+        &mut |offset| {
+            let (start, context) = util::read_context(
+                &mut input,
+                offset,
+                to_replace.len(),
+                INTERACTIVE_CONTEXT,
+            )?;
+            writeln!(prompt, "Match at offset {}:", offset)?;
+            write!(prompt, "{}", util::hexdump(start, &context))?;
+            write!(prompt, "Replace? [y/N] ")?;
+            prompt.flush()?;
+
+            let mut answer = String::new();
+            answers.read_line(&mut answer)?;
+            Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+        },
+        &mut |offset, matched| {
+            offsets.push(offset);
+            replacement(offset, matched)
+        },
+        &mut |_, _| {},
+    )?;
+    report.offsets = offsets;
+
+    Ok(report)
+}
+
+/// Same as `replace()`, except every match is replaced with the bytes
+/// returned by `replace_fn`, called with the offset of the match and
+/// the matched bytes, e.g. to number the matches. The replacements can
+/// have any length, so `allow_length_change` is implied. With `regex`
+/// set `to_replace` is a regex, as usual. Return the number of
+/// replaced patterns.
+pub fn replace_with_fn<F: FnMut(u64, &[u8]) -> Vec<u8>>(
+    to_replace: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    mut replace_fn: F,
+) -> Result<usize, io::Error> {
+    if replace_config.interactive {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Interactive mode can't be used together with a replacement function",
+        ));
+    }
+    let report = if replace_config.regex {
+        replace_regex(
+            to_replace,
+            input_filename,
+            output_filename,
+            replace_config,
+            &mut |offset, captures, expanded| {
+                expanded.extend(replace_fn(offset, &captures[0]));
+            },
+            &mut |_, _| {},
+        )?
     } else {
-        found_matches = matches_iter.collect()
+        replace_confirmed(
+            to_replace,
+            input_filename,
+            output_filename,
+            replace_config,
+            &mut |_| Ok(true),
+            &mut replace_fn,
+            &mut |_, _| {},
+        )?
+    };
+
+    Ok(report.count)
+}
+
+/// Called with the offset and the bytes of a match, returns what the
+/// match is replaced with.
+type ReplacementFn = dyn FnMut(u64, &[u8]) -> Vec<u8>;
+
+/// Build what every match is replaced with: `replace_with` padded with
+/// `fill_byte` to the length of `to_replace` unless the length can
+/// change. With `template` set, `replace_with` is a `Template` first
+/// expanded with the offset of the match and the size of
+/// `input_filename`. Return an error if the expansion can be longer
+/// than `to_replace` while the length can't change.
+fn replacement_fn(
+    to_replace: &[u8],
+    replace_with: &[u8],
+    input_filename: &Path,
+    replace_config: &ReplaceConfig,
+) -> Result<Box<ReplacementFn>, io::Error> {
+    let pad_to = if replace_config.allow_length_change { 0 } else { to_replace.len() };
+    let fill_byte = replace_config.fill_byte;
+    if !replace_config.template {
+        ensure_fits(to_replace, replace_with, replace_config)?;
+        let mut replacement = replace_with.to_vec();
+        if replacement.len() < pad_to {
+            replacement.resize(pad_to, fill_byte);
+        }
+        return Ok(Box::new(move |_, _| replacement.clone()));
     }
 
-    // Initialize variables for the loop
-    let to_fill = if replace_config.allow_length_change {
-        0
+    let file_len = util::file_len(&File::open(input_filename)?)?;
+    let template = Template::parse(replace_with)?;
+    template.fits(file_len)?;
+    if !replace_config.allow_length_change && template.max_len(file_len) > to_replace.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The expanded template can be longer than the pattern, \
+             allow length change to replace it anyway",
+        ));
+    }
+    Ok(Box::new(move |offset, _| {
+        let mut replacement = template.expand(offset, file_len);
+        if replacement.len() < pad_to {
+            replacement.resize(pad_to, fill_byte);
+        }
+        replacement
+    }))
+}
+
+/// The actual implementation of `replace()`. `confirm` is called with
+/// the offset of every match that is about to be replaced and decides
+/// whether it really should be. `replacement` is called with the
+/// offset and the bytes of every replaced match and returns what they
+/// are replaced with. The offsets in the returned report are left
+/// empty, so replacing all matches doesn't need memory for every one
+/// of them. Callers wanting them can record them in `replacement`.
+fn replace_confirmed(
+    to_replace: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    confirm: &mut dyn FnMut(u64) -> Result<bool, io::Error>,
+    replacement: &mut dyn FnMut(u64, &[u8]) -> Vec<u8>,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<ReplaceReport, io::Error> {
+    let mut input_file = util::open_file(input_filename)?;
+    if !replace_config.in_place {
+        util::ensure_different_files(input_filename, output_filename)?;
+    }
+    let file_len = known_len(input_file.file.get_ref())?;
+    let single = !replace_config.replace_all && replace_config.limit.is_none();
+
+    // A single match has to be found before anything is written, so
+    // the input is gone through twice. All matches are replaced while
+    // they're found, in one pass
+    let total = file_len.unwrap_or(0);
+    let mut progress = util::Progress::new(if single { total * 2 } else { total }, progress);
+    let too_long = util::longer_than_file(to_replace.len(), input_file.file.get_ref())?;
+    let matches: Box<dyn Iterator<Item = u64>> = if too_long {
+        Box::new(std::iter::empty())
+    } else if single {
+        let mut on_progress = |searched| progress.set(searched);
+        let mut matches_iter = util::find_matches(&mut input_file, to_replace)
+            .on_progress(&mut on_progress);
+        let found = if replace_config.from_end {
+            let all_matches: Vec<_> = matches_iter.collect();
+            all_matches.into_iter().rev().nth(replace_config.nth)
+        } else {
+            matches_iter.nth(replace_config.nth)
+        };
+        // Without a match the output is still written, as a copy
+        Box::new(found.map(|found| found.offset).into_iter())
     } else {
-        to_replace.len() - replace_with.len()
+        Box::new(util::find_matches(&mut input_file, to_replace).map(|found| found.offset))
     };
+    progress.set(if single { total } else { 0 });
+
+    // Initialize variables for the loop
+    let mut report = ReplaceReport::default();
     let mut input_file = BufReader::new(File::open(input_filename)?);
-    let mut output_file = BufWriter::new(File::create(output_filename)?);
-    let mut last_offset = 0;
-    
-    // Handle replacing the file with copying in this kind of pattern:
-    // file[0:1st_off] + replace_with + file[1st_off+len(replace_with):2nd_off] + ...
-    // Hope you see it, otherwise I don't know how to explain it better with words
-    for &offset in found_matches.iter() {
-        if last_offset > offset as usize {
-            continue;
-        }
-        
-        let mut buf = vec![0u8; offset as usize-last_offset];
-        input_file.read_exact(&mut buf)?;
-        output_file.write_all(&buf)?;
-        
-        input_file.seek_relative(to_replace.len() as i64)?;
-        output_file.write_all(replace_with)?;
-        let fill_bytes = vec![replace_config.fill_byte; to_fill];
-        output_file.write_all(&fill_bytes)?;
-
-        last_offset += buf.len() + to_replace.len();
-    }
-    // Handle the last case which is from the last offset to the end of the file
+    util::write_atomically(output_filename, |output_file| {
+        let mut last_offset = 0;
+
+        // Handle replacing the file with copying in this kind of pattern:
+        // file[0:1st_off] + replacement + file[1st_off+len(to_replace):2nd_off] + ...
+        // Hope you see it, otherwise I don't know how to explain it better with words
+        for offset in matches {
+            if replace_config.limit.is_some_and(|limit| report.count >= limit) {
+                break;
+            }
+            // Matches overlapping an already replaced one can't be replaced anyway
+            if offset < last_offset || !confirm(offset)? {
+                continue;
+            }
+            let to_copy = offset - last_offset;
+            let needed = offset + to_replace.len() as u64;
+            if let Some(file_len) = file_len.filter(|&file_len| needed > file_len) {
+                return Err(truncated_error(needed, file_len));
+            }
+            copy_segment(&mut input_file, output_file, last_offset, to_copy, file_len, &mut progress)?;
+
+            input_file.seek_relative(to_replace.len() as i64)?;
+            progress.advance(to_replace.len() as u64);
+            let replaced = replacement(offset, to_replace);
+            output_file.write_all(&replaced)?;
+            report.bytes_written += to_copy + replaced.len() as u64;
+
+            report.count += 1;
+            last_offset += to_copy + to_replace.len() as u64;
+        }
+        // Handle the last case which is from the last offset to the end of the file
+        report.bytes_written += copy_tail(&mut input_file, output_file, last_offset, file_len, &mut progress)?;
+        output_file.flush()?;
+        util::copy_metadata(input_file.get_ref(), output_file.get_ref())
+    })?;
+
+    Ok(report)
+}
+
+/// Length of an input to replace, `None` if it isn't known. Pipes and
+/// files in `/proc` report a length of 0 while having data.
+fn known_len(file: &File) -> Result<Option<u64>, io::Error> {
+    Ok(Some(util::file_len(file)?).filter(|&len| len > 0))
+}
+
+/// Copy the `len` bytes at `position` of an input which was `input_len`
+/// bytes long when the replace started, if that's known, with `reader`
+/// already at `position`. Return a descriptive error if they don't fit
+/// in the input or it turns out shorter, e.g. because it changed
+/// meanwhile.
+fn copy_segment<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    position: u64,
+    len: u64,
+    input_len: Option<u64>,
+    progress: &mut util::Progress,
+) -> Result<(), io::Error> {
+    let end = position.saturating_add(len);
+    if let Some(input_len) = input_len.filter(|&input_len| end > input_len) {
+        return Err(truncated_error(end, input_len));
+    }
+    let copied = util::copy_with_progress(&mut reader.take(len), writer, progress)?;
+    if copied != len {
+        return Err(truncated_error(position + len, position + copied));
+    }
+
+    Ok(())
+}
+
+/// Copy the rest of the input from `position` on, like
+/// `copy_segment()`. With an unknown `input_len` everything up to the
+/// end of `reader` is copied. Return the number of copied bytes.
+fn copy_tail<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    position: u64,
+    input_len: Option<u64>,
+    progress: &mut util::Progress,
+) -> Result<u64, io::Error> {
+    match input_len {
+        Some(input_len) => {
+            let len = input_len.saturating_sub(position);
+            copy_segment(reader, writer, position, len, Some(input_len), progress)?;
+            Ok(len)
+        }
+        None => util::copy_with_progress(reader, writer, progress),
+    }
+}
+
+fn truncated_error(needed: u64, input_len: u64) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        format!(
+            "The input has to be at least {} bytes long, but only {} bytes could be read. \
+             Did it change while replacing?",
+            needed,
+            input_len,
+        ),
+    )
+}
+
+/// Regex flavour of `replace()`. `to_replace` is compiled as a byte
+/// regex and `replace_with` can reference capture groups like `$1` or
+/// `${name}`. The replacement length can differ from the match, so
+/// `allow_length_change` is implied. The whole input file is read
+/// into memory. `replacement` is called with the offset and the
+/// captures of every match and appends what it's replaced with.
+fn replace_regex(
+    to_replace: &[u8],
+    input_filename: &Path,
+    output_filename: &Path,
+    replace_config: &ReplaceConfig,
+    replacement: &mut dyn FnMut(u64, &Captures, &mut Vec<u8>),
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<ReplaceReport, io::Error> {
+    let regex = util::compile_regex(to_replace, false)?;
+    let mut input_file = util::open_file(input_filename)?;
+    if !replace_config.in_place {
+        util::ensure_different_files(input_filename, output_filename)?;
+    }
     let mut buf = Vec::new();
-    input_file.read_to_end(&mut buf)?;
-    output_file.write_all(&buf)?;
-    
-    Ok(found_matches.len())
+    input_file.file.read_to_end(&mut buf)?;
+
+    let mut captures_iter = regex.captures_iter(&buf);
+    let found_captures: Vec<_> = if let Some(limit) = replace_config.limit {
+        captures_iter.take(limit).collect()
+    } else if !replace_config.replace_all {
+        let captures = if replace_config.from_end {
+            let all_captures: Vec<_> = captures_iter.collect();
+            all_captures.into_iter().rev().nth(replace_config.nth)
+        } else {
+            captures_iter.nth(replace_config.nth)
+        };
+        captures.into_iter().collect()
+    } else {
+        captures_iter.collect()
+    };
+
+    let report = util::write_atomically(output_filename, |output_file| {
+        let mut last_offset = 0;
+        let mut expanded = Vec::new();
+        let mut report = ReplaceReport::default();
+        for captures in found_captures.iter() {
+            let found = captures.get(0).unwrap();
+            output_file.write_all(&buf[last_offset..found.start()])?;
+            expanded.clear();
+            replacement(found.start() as u64, captures, &mut expanded);
+            output_file.write_all(&expanded)?;
+            report.offsets.push(found.start() as u64);
+            report.bytes_written += (found.start() - last_offset + expanded.len()) as u64;
+            last_offset = found.end();
+        }
+        output_file.write_all(&buf[last_offset..])?;
+        output_file.flush()?;
+        util::copy_metadata(input_file.file.get_ref(), output_file.get_ref())?;
+        report.bytes_written += (buf.len() - last_offset) as u64;
+        report.count = report.offsets.len();
+        Ok(report)
+    })?;
+    progress(buf.len() as u64, buf.len() as u64);
+
+    Ok(report)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Cursor;
     use std::path::Path;
-    use crate::grep;
+
+    /// A reader which ends after `len` bytes, like a file truncated
+    /// while it's being replaced.
+    struct TruncatedReader {
+        data: Cursor<Vec<u8>>,
+        len: u64,
+    }
+
+    impl Read for TruncatedReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let left = self.len.saturating_sub(self.data.position());
+            (&mut self.data).take(left).read(buf)
+        }
+    }
+
+    #[test]
+    fn replace_pattern_longer_than_file_test() {
+        let file = Path::new("test_files/file_tiny");
+        let output = Path::new("test_files/file_tiny_replaced");
+        for cfg in [
+            ReplaceConfig::default(),
+            ReplaceConfig { replace_all: true, ..Default::default() },
+            ReplaceConfig { from_end: true, ..Default::default() },
+        ] {
+            let report = replace_report(b"nyanyanyan", b"meow", file, output, &cfg, &mut |_, _| {})
+                .expect("Probably a file related error");
+            assert_eq!(report, ReplaceReport { count: 0, offsets: vec![], bytes_written: 3 });
+            assert_eq!(std::fs::read(output).unwrap(), b"nya");
+        }
+    }
+
+    #[test]
+    fn replace_print_offsets_test() {
+        let file = Path::new("test_files/file_one");
+        let output = Path::new("test_files/file_one_print_offsets");
+        let cfg = ReplaceConfig::builder()
+            .replace_all(true)
+            .print_offsets(OffsetStyle::Hex)
+            .print0(true)
+            .build()
+            .unwrap();
+        let report = replace_report(b"nya", b"NYA", file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        let mut printed = Vec::new();
+        report.write_offsets(&mut printed, OffsetStyle::Hex, true).unwrap();
+        assert_eq!(printed.last(), Some(&0));
+        let offsets: Vec<u64> = printed
+            .split(|&b| b == 0)
+            .filter(|s| !s.is_empty())
+            .map(|s| u64::from_str_radix(std::str::from_utf8(&s[2..]).unwrap(), 16).unwrap())
+            .collect();
+        let res = grep::grep(b"NYA", &[output]).unwrap();
+        assert!(!offsets.is_empty());
+        assert_eq!(offsets, res[0].1);
+
+        let mut printed = Vec::new();
+        report.write_offsets(&mut printed, OffsetStyle::Decimal, false).unwrap();
+        let expected: String = offsets.iter().map(|o| format!("{}\n", o)).collect();
+        assert_eq!(printed, expected.as_bytes());
+
+        let res = ReplaceConfig::builder().print0(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = ReplaceConfig::builder().print_offsets(OffsetStyle::Decimal).json(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn swap_patterns_test() {
+        let file = Path::new("test_files/file_swap");
+        let output = Path::new("test_files/file_swap_swapped");
+        let cfg = ReplaceConfig::builder().swap(true).build().unwrap();
+        let report = replace_report(b"cat", b"dog", file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"dog cat dog catdog dogcat\n");
+        assert_eq!(report.offsets, vec![0, 4, 8, 12, 15, 19, 22]);
+        assert_eq!(report.bytes_written, 26);
+
+        // Swapping back gives the original
+        let twice = Path::new("test_files/file_swap_swapped_twice");
+        swap_patterns(b"dog", b"cat", output, twice, &cfg).expect("Probably a file related error");
+        assert_eq!(std::fs::read(twice).unwrap(), std::fs::read(file).unwrap());
+
+        let res = swap_patterns(b"cat", b"mouse", file, output, &cfg);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let cfg = ReplaceConfig { swap: true, allow_length_change: true, ..Default::default() };
+        swap_patterns(b"cat", b"do", file, output, &cfg).expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"do catg do catgdo docatg\n");
+
+        // The match starting first wins
+        swap_patterns(b"at", b"t d", file, output, &cfg).expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"ct d dog ct d dogct d ct ddog\n");
+
+        let res = ReplaceConfig::builder().swap(true).replace_all(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn replace_truncated_input_test() {
+        let mut no_progress = |_, _| {};
+        let mut progress = util::Progress::new(0, &mut no_progress);
+        let mut reader = TruncatedReader { data: Cursor::new(vec![0x41; 16]), len: 10 };
+        let mut out = Vec::new();
+        copy_segment(&mut reader, &mut out, 0, 8, Some(16), &mut progress).unwrap();
+        let err = copy_segment(&mut reader, &mut out, 8, 8, Some(16), &mut progress).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(err.to_string().starts_with("The input has to be at least 16 bytes long, but only 10"));
+
+        // A segment past the end is caught before reading anything
+        let err = copy_segment(&mut Cursor::new(vec![0; 16]), &mut out, 12, 8, Some(16), &mut progress)
+            .unwrap_err();
+        assert!(err.to_string().starts_with("The input has to be at least 20 bytes long, but only 16"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn replace_unknown_length_test() {
+        // Files in /proc report a length of 0, their tail is copied until EOF
+        let output = Path::new("test_files/proc_status_replace");
+        let cfg = ReplaceConfig { quiet: true, ..Default::default() };
+        let input = Path::new("/proc/self/status");
+        let report = replace_report(b"Name:", b"Nama:", input, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        let replaced = std::fs::read(output).unwrap();
+        assert_eq!(report.count, 1);
+        assert!(replaced.starts_with(b"Nama:"));
+        assert!(replaced.windows(4).any(|window| window == b"Pid:"));
+        assert_eq!(report.bytes_written, replaced.len() as u64);
+
+        // Without a match the whole input is still copied
+        let count = replace(b"zzzzqq", b"yyyyqq", input, output, &cfg)
+            .expect("Probably a file related error");
+        assert_eq!(count, 0);
+        assert!(std::fs::read(output).unwrap().starts_with(b"Name:"));
+    }
 
     #[test]
     fn simple_replace_test() {
@@ -163,6 +1537,235 @@ mod tests {
         assert_eq!(res[0].1, vec![53]);
     }
 
+    #[test]
+    fn replace_test_report() {
+        let cfg = ReplaceConfig { replace_all: true, quiet: true, ..Default::default() };
+        let report = replace_command(
+            b"20%",
+            b"1",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_report"),
+            &cfg,
+            &mut |_, _| {},
+        ).expect("Probably a file related error");
+        assert_eq!(report, ReplaceReport {
+            count: 4,
+            offsets: vec![21, 53, 85, 117],
+            bytes_written: 127,
+        });
+
+        let cfg = ReplaceConfig { regex: true, replace_all: true, ..Default::default() };
+        let report = replace_report(
+            b"20%",
+            b"1",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_report_regex"),
+            &cfg,
+            &mut |_, _| {},
+        ).expect("Probably a file related error");
+        assert_eq!(report.offsets, vec![21, 53, 85, 117]);
+        assert_eq!(report.bytes_written, 127 - 8);
+        let written = std::fs::metadata("test_files/file_two_replace_report_regex").unwrap().len();
+        assert_eq!(report.bytes_written, written);
+    }
+
+    #[test]
+    fn replace_test_overlapping() {
+        let cfg = ReplaceConfig { replace_all: true, ..Default::default() };
+        for (input, expected) in [("even", &b"bbbb"[..]), ("odd", b"bbbba")] {
+            let input = format!("test_files/file_overlap_{}", input);
+            let output = format!("{}_replace", input);
+            let count = replace(b"aa", b"bb", Path::new(&input), Path::new(&output), &cfg)
+                .expect("Probably a file related error");
+            assert_eq!(count, 2);
+            assert_eq!(std::fs::read(&output).unwrap(), expected);
+
+            let mut streamed = Vec::new();
+            let data = std::fs::read(&input).unwrap();
+            let report =
+                replace_stream_chunked(b"aa", b"bb", &mut Cursor::new(&data), &mut streamed, &cfg, 1)
+                    .unwrap();
+            assert_eq!(report.offsets, vec![0, 2]);
+            assert_eq!(streamed, expected);
+        }
+
+        // Every match counts for nth, also the overlapping ones
+        let cfg = ReplaceConfig { nth: 1, ..Default::default() };
+        let input = Path::new("test_files/file_overlap_odd");
+        let output = Path::new("test_files/file_overlap_odd_replace_nth");
+        replace(b"aa", b"bb", input, output, &cfg).expect("Probably a file related error");
+        assert_eq!(std::fs::read(output).unwrap(), b"abbaa");
+    }
+
+    #[test]
+    fn replace_test_stream() {
+        let input = std::fs::read("test_files/file_two").unwrap();
+        let cfg = ReplaceConfig { replace_all: true, ..Default::default() };
+        // Tiny reads, so matches straddle them
+        let mut output = Vec::new();
+        let report = replace_stream_chunked(
+            b"20%",
+            b"PI%",
+            &mut Cursor::new(&input),
+            &mut output,
+            &cfg,
+            2,
+        ).unwrap();
+        assert_eq!(report.offsets, vec![21, 53, 85, 117]);
+        assert_eq!(report.bytes_written, 127);
+
+        // Same result as replacing files
+        replace(
+            b"20%",
+            b"PI%",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_stream"),
+            &cfg,
+        ).unwrap();
+        assert_eq!(output, std::fs::read("test_files/file_two_replace_stream").unwrap());
+
+        let cfg = ReplaceConfig { nth: 2, fill_byte: b'!', ..Default::default() };
+        let mut output = Vec::new();
+        let report = replace_stream(b"20%", b"1", &mut Cursor::new(&input), &mut output, &cfg)
+            .unwrap();
+        assert_eq!(report.offsets, vec![85]);
+        assert_eq!(&output[85..88], b"1!!");
+        assert_eq!(output[..85], input[..85]);
+        assert_eq!(output[88..], input[88..]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_test_keeps_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, SystemTime};
+
+        let input = Path::new("test_files/file_two_executable");
+        std::fs::copy("test_files/file_two", input).unwrap();
+        std::fs::set_permissions(input, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        File::options().write(true).open(input).unwrap().set_modified(mtime).unwrap();
+
+        let output = Path::new("test_files/file_two_executable_replace");
+        replace(b"20%", b"PI%", input, output, &ReplaceConfig::default()).unwrap();
+        let metadata = std::fs::metadata(output).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o755);
+        assert_eq!(metadata.modified().unwrap(), mtime);
+    }
+
+    #[test]
+    fn replace_test_many_matches() {
+        let input = Path::new("test_files/file_many_matches");
+        let output = Path::new("test_files/file_many_matches_replace");
+        let cfg = ReplaceConfig { replace_all: true, ..Default::default() };
+        let mut last = (0, 0);
+        let n = replace_with_progress(b"abc", b"XYZ", input, output, &cfg, &mut |done, total| {
+            last = (done, total);
+        }).expect("Probably a file related error");
+        assert_eq!(n, 4096);
+        // Everything is replaced in a single pass over the input
+        assert_eq!(last, (65536, 65536));
+
+        let expected = b"0123456789XYZdef".repeat(4096);
+        assert_eq!(std::fs::read(output).unwrap(), expected);
+
+        let report = replace_report(b"abc", b"XYZ", input, output, &cfg, &mut |_, _| {}).unwrap();
+        assert_eq!(report.offsets.len(), 4096);
+        assert_eq!(report.offsets[4095], 4095 * 16 + 10);
+    }
+
+    #[test]
+    fn replace_test_empty_file() {
+        let input = Path::new("test_files/file_empty");
+        let output = Path::new("test_files/file_empty_replace");
+        for replace_all in [false, true] {
+            let cfg = ReplaceConfig { replace_all, ..Default::default() };
+            let _ = fs::remove_file(output);
+            let n = replace(b"nya", b"ab", input, output, &cfg).unwrap();
+            assert_eq!(n, 0);
+            assert_eq!(fs::read(output).unwrap(), b"");
+        }
+
+        // A file without a match is copied as it is
+        let output = Path::new("test_files/file_one_replace_nothing");
+        let n = replace(b"woof", b"meow", Path::new("test_files/file_one"), output, &Default::default());
+        assert_eq!(n.unwrap(), 0);
+        assert_eq!(fs::read(output).unwrap(), fs::read("test_files/file_one").unwrap());
+    }
+
+    #[test]
+    fn replace_test_limit() {
+        let cfg = ReplaceConfig::builder().limit(2).build().unwrap();
+        let n = replace(
+            b"20%",
+            b"PI%",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_limit"),
+            &cfg
+        ).expect("Probably a file related error");
+        assert_eq!(n, 2);
+
+        let file = vec!["test_files/file_two_replace_limit"];
+        let res = grep::grep(b"PI%", &file).unwrap();
+        assert_eq!(res[0].1, vec![21, 53]);
+        let res = grep::grep(b"20%", &file).unwrap();
+        assert_eq!(res[0].1, vec![85, 117]);
+
+        let res = ReplaceConfig::builder().limit(2).nth(1).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn replace_test_executable_needs_force() {
+        let input = Path::new("test_files/file_elf");
+        let output = Path::new("test_files/file_elf_replace");
+        let cfg = ReplaceConfig { allow_length_change: true, quiet: true, ..Default::default() };
+        let res = replace_command(b"nya", b"nyan", input, output, &cfg, &mut |_, _| {});
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        // Same length replacements don't move anything
+        replace_command(b"nya", b"meo", input, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+
+        // Longer replacements change the size without allowing it too
+        let cfg = ReplaceConfig { allow_length_change: false, ..cfg };
+        let res = replace_command(b"nya", b"nyanyanya", input, output, &cfg, &mut |_, _| {});
+        assert!(res.unwrap_err().to_string().contains("executable"));
+        let cfg = ReplaceConfig { force: true, ..cfg };
+        let res = replace_command(b"nya", b"nyanyanya", input, output, &cfg, &mut |_, _| {});
+        assert!(res.unwrap_err().to_string().starts_with("Replacing string is longer"));
+
+        let cfg = ReplaceConfig { allow_length_change: true, ..cfg };
+        let report = replace_command(b"nya", b"nyan", input, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(report.bytes_written, 29);
+    }
+
+    #[test]
+    fn replace_report_json_test() {
+        let report = ReplaceReport { count: 2, offsets: vec![3, 9], bytes_written: 127 };
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json, serde_json::json!({ "replaced": 2, "offsets": [3, 9] }));
+    }
+
+    #[test]
+    fn replace_test_from_end() {
+        let cfg = ReplaceConfig { from_end: true, ..Default::default() };
+        replace(
+            b"20%",
+            b"PI%",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_from_end"),
+            &cfg
+        ).expect("Probably a file related error");
+
+        let file = vec!["test_files/file_two_replace_from_end"];
+        let res = grep::grep(b"PI%", &file).unwrap();
+        assert_eq!(res[0].1, vec![117]);
+        let res = grep::grep(b"20%", &file).unwrap();
+        assert_eq!(res[0].1, vec![21, 53, 85]);
+    }
+
     #[test]
     fn replace_test_fill_byte() {
         let cfg = ReplaceConfig { fill_byte: b'%', ..Default::default() };
@@ -200,4 +1803,316 @@ mod tests {
         assert_eq!(res.len(), 1);
         assert_eq!(res[0].1, vec![54, 86, 118]);
     }
+
+    #[test]
+    fn replace_test_regex() {
+        let cfg = ReplaceConfig { regex: true, replace_all: true, ..Default::default() };
+        let n = replace(
+            br"(\d+)%",
+            b"${1}0%",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_regex_all"),
+            &cfg
+        ).expect("Probably a file related error");
+        assert_eq!(n, 4);
+
+        let file = vec!["test_files/file_two_replace_regex_all"];
+        let res = grep::grep(b"200%", &file).unwrap();
+        assert_eq!(res[0].1, vec![21, 54, 87, 120]);
+
+        let cfg = ReplaceConfig { regex: true, nth: 1, ..Default::default() };
+        let n = replace(
+            br"(\d+)%",
+            b"${1}0%",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_regex_nth"),
+            &cfg
+        ).expect("Probably a file related error");
+        assert_eq!(n, 1);
+
+        let file = vec!["test_files/file_two_replace_regex_nth"];
+        let res = grep::grep(b"200%", &file).unwrap();
+        assert_eq!(res[0].1, vec![53]);
+        let res = grep::grep(b"20%", &file).unwrap();
+        assert_eq!(res[0].1, vec![21, 86, 118]);
+    }
+
+    #[test]
+    fn replace_test_template() {
+        let input = Path::new("test_files/file_two");
+        let output = Path::new("test_files/file_two_replace_template");
+        let cfg = ReplaceConfig { template: true, replace_all: true, ..Default::default() };
+        replace(b"20%", b"%size%", input, output, &cfg).expect("Probably a file related error");
+        let res = grep::grep(b"127", &[output]).unwrap();
+        assert_eq!(res[0].1, vec![21, 53, 85, 117]);
+
+        // The expansion is padded like any other shorter replacement
+        let cfg = ReplaceConfig { template: true, fill_byte: b'!', ..Default::default() };
+        replace(b"20%", b"%offset:1%", input, output, &cfg).expect("Probably a file related error");
+        let res = grep::grep(b"\x15!!", &[output]).unwrap();
+        assert_eq!(res[0].1, vec![21]);
+
+        let res = replace(b"20%", b"%offset%!", input, output, &cfg);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let cfg = ReplaceConfig { template: true, allow_length_change: true, ..Default::default() };
+        replace(b"20%", b"%offset%!", input, output, &cfg).expect("Probably a file related error");
+        let res = grep::grep(b"21!", &[output]).unwrap();
+        assert_eq!(res[0].1, vec![21]);
+    }
+
+    #[test]
+    fn replace_test_regex_template() {
+        // The match is at 0x24, a $ when expanded as a single byte
+        let input = Path::new("test_files/file_regex_template");
+        let output = Path::new("test_files/file_regex_template_replaced");
+        let mut contents = vec![b'x'; 0x24];
+        contents.extend_from_slice(b"ab");
+        std::fs::write(input, &contents).unwrap();
+        let cfg = ReplaceConfig { regex: true, template: true, ..Default::default() };
+        replace(b"(a)b", b"%offset:1%1", input, output, &cfg).expect("Probably a file related error");
+        assert_eq!(&std::fs::read(output).unwrap()[0x24..], b"$1");
+
+        // References in the literal parts are still expanded
+        replace(b"(a)b", b"%offset:1%$1.", input, output, &cfg).expect("Probably a file related error");
+        assert_eq!(&std::fs::read(output).unwrap()[0x24..], b"$a.");
+    }
+
+    #[test]
+    fn replace_test_verify() {
+        let input = Path::new("test_files/file_two");
+        let output = Path::new("test_files/file_two_replace_verify");
+        let cfg = ReplaceConfig {
+            verify: true,
+            replace_all: true,
+            allow_length_change: true,
+            quiet: true,
+            ..Default::default()
+        };
+        let report = replace_command(b"20%", b"PI", input, output, &cfg, &mut |_, _| {})
+            .expect("Verifying should pass");
+        assert_eq!(report.count, 4);
+
+        // A report that doesn't match the output fails
+        let wrong = ReplaceReport { offsets: vec![21, 54, 85, 117], ..report };
+        let res = verify_output(b"20%", b"PI", output, &wrong, &cfg);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let wrong = ReplaceReport { bytes_written: 127, ..wrong };
+        let res = verify_output(b"20%", b"PI", output, &wrong, &cfg);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn replace_test_fn() {
+        let cfg = ReplaceConfig { replace_all: true, ..Default::default() };
+        let mut calls = 0;
+        let count = replace_with_fn(
+            b"20%",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_fn"),
+            &cfg,
+            |offset, matched| {
+                assert_eq!(matched, b"20%");
+                calls += 1;
+                offset.to_string().into_bytes()
+            },
+        ).expect("Probably a file related error");
+        assert_eq!((count, calls), (4, 4));
+        let data = std::fs::read("test_files/file_two_replace_fn").unwrap();
+        assert_eq!(data.len(), 124);
+        assert_eq!(&data[21..23], b"21");
+        assert_eq!(&data[52..54], b"53");
+        assert_eq!(&data[83..85], b"85");
+        assert_eq!(&data[114..117], b"117");
+
+        let cfg = ReplaceConfig { regex: true, limit: Some(2), ..Default::default() };
+        replace_with_fn(
+            b"[0-9]+%",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_fn_regex"),
+            &cfg,
+            |offset, matched| [matched, &(offset as u8).to_le_bytes()[..]].concat(),
+        ).expect("Probably a file related error");
+        let file = vec!["test_files/file_two_replace_fn_regex"];
+        let res = grep::grep(b"20%\x15", &file).unwrap();
+        assert_eq!(res[0].1, vec![21]);
+        let res = grep::grep(b"20%\x35", &file).unwrap();
+        assert_eq!(res[0].1, vec![54]);
+    }
+
+    #[test]
+    fn replace_test_interactive() {
+        let cfg = ReplaceConfig { replace_all: true, ..Default::default() };
+        let mut answers = io::Cursor::new(b"y\nn\nyes\n");
+        let mut prompt = Vec::new();
+        let n = replace_interactive(
+            b"20%",
+            b"PI%",
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_interactive"),
+            &cfg,
+            &mut answers,
+            &mut prompt,
+        ).expect("Probably a file related error");
+        // The fourth question hits EOF, which counts as a no
+        assert_eq!(n, 2);
+
+        let prompt = String::from_utf8(prompt).unwrap();
+        assert_eq!(prompt.matches("Replace? [y/N] ").count(), 4);
+        assert!(prompt.contains("Match at offset 53:"));
+
+        let file = vec!["test_files/file_two_replace_interactive"];
+        let res = grep::grep(b"PI%", &file).unwrap();
+        assert_eq!(res[0].1, vec![21, 85]);
+    }
+
+    #[test]
+    fn replace_test_same_file() {
+        let file = Path::new("test_files/file_two_replace_same_file");
+        std::fs::copy("test_files/file_two", file).unwrap();
+        let cfg = ReplaceConfig { ..Default::default() };
+        let res = replace(b"20%", b"PI%", file, file, &cfg);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        // The input must be left untouched
+        assert_eq!(std::fs::read(file).unwrap(), std::fs::read("test_files/file_two").unwrap());
+
+        let cfg = ReplaceConfig { in_place: true, quiet: true, ..Default::default() };
+        let report = replace_command(b"20%", b"PI%", file, file, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        // Quiet only leaves out the message
+        assert_eq!(report, ReplaceReport { count: 1, offsets: vec![21], bytes_written: 127 });
+        let res = grep::grep(b"PI%", &[file]).unwrap();
+        assert_eq!(res[0].1, vec![21]);
+    }
+
+    #[test]
+    fn replace_no_clobber_test() {
+        let file = Path::new("test_files/file_two");
+        let output = Path::new("test_files/file_two_no_clobber");
+        let _ = std::fs::remove_file(output);
+        let cfg = ReplaceConfig::builder().quiet(true).no_clobber(true).build().unwrap();
+        replace_command(b"20%", b"PI%", file, output, &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+
+        // The second run would overwrite the first output
+        std::fs::write(output, b"meow").unwrap();
+        let res = replace_command(b"20%", b"PI%", file, output, &cfg, &mut |_, _| {});
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(std::fs::read(output).unwrap(), b"meow");
+
+        let res = ReplaceConfig::builder().no_clobber(true).in_place(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn replace_recursive_test() {
+        use std::os::unix::fs::MetadataExt;
+
+        let dir = Path::new("test_files/replace_recursive_dir");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::copy("test_files/file_two", dir.join("with_pattern")).unwrap();
+        std::fs::copy("test_files/file_two", dir.join("sub/with_pattern")).unwrap();
+        std::fs::copy("test_files/file_three", dir.join("without_pattern")).unwrap();
+        let inode = std::fs::metadata(dir.join("without_pattern")).unwrap().ino();
+
+        let cfg = ReplaceConfig::builder()
+            .recursive(true)
+            .in_place(true)
+            .quiet(true)
+            .build()
+            .unwrap();
+        let mut reports = replace_recursive_command(b"20%", b"PI%", &[dir], &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        reports.sort_by(|a, b| a.0.cmp(&b.0));
+        let files: Vec<_> = reports.iter().map(|(file, report)| (file.clone(), report.count)).collect();
+        assert_eq!(files, vec![(dir.join("sub/with_pattern"), 1), (dir.join("with_pattern"), 1)]);
+        let res = grep::grep(b"PI%", &[dir.join("with_pattern"), dir.join("sub/with_pattern")]).unwrap();
+        assert_eq!(res[0].1, vec![21]);
+        assert_eq!(res[1].1, vec![21]);
+        // Files without a match aren't rewritten
+        assert_eq!(std::fs::read(dir.join("without_pattern")).unwrap(), std::fs::read("test_files/file_three").unwrap());
+        assert_eq!(std::fs::metadata(dir.join("without_pattern")).unwrap().ino(), inode);
+
+        let res = ReplaceConfig::builder().recursive(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn replace_recursive_output_dir_test() {
+        let dir = Path::new("test_files/replace_mirror_dir");
+        let output_dir = Path::new("test_files/replace_mirror_output");
+        let _ = std::fs::remove_dir_all(dir);
+        let _ = std::fs::remove_dir_all(output_dir);
+        std::fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+        std::fs::copy("test_files/file_two", dir.join("with_pattern")).unwrap();
+        std::fs::copy("test_files/file_two", dir.join("sub/deeper/with_pattern")).unwrap();
+        std::fs::copy("test_files/file_three", dir.join("sub/without_pattern")).unwrap();
+
+        let cfg = ReplaceConfig::builder()
+            .recursive(true)
+            .output_dir(output_dir.to_path_buf())
+            .quiet(true)
+            .build()
+            .unwrap();
+        let reports = replace_recursive_command(b"20%", b"PI%", &[dir], &cfg, &mut |_, _| {})
+            .expect("Probably a file related error");
+        assert_eq!(reports.len(), 2);
+        let mut mirrored = util::open_all_directories(&[output_dir], false, &util::NameFilter::default(), None)
+            .unwrap();
+        mirrored.sort();
+        assert_eq!(mirrored, vec![
+            output_dir.join("sub/deeper/with_pattern"),
+            output_dir.join("sub/without_pattern"),
+            output_dir.join("with_pattern"),
+        ]);
+        let res = grep::grep(b"PI%", &[output_dir.join("with_pattern"), output_dir.join("sub/deeper/with_pattern")])
+            .unwrap();
+        assert_eq!(res[0].1, vec![21]);
+        assert_eq!(res[1].1, vec![21]);
+        assert_eq!(std::fs::read(output_dir.join("sub/without_pattern")).unwrap(), std::fs::read("test_files/file_three").unwrap());
+        // The inputs are left untouched
+        assert_eq!(std::fs::read(dir.join("with_pattern")).unwrap(), std::fs::read("test_files/file_two").unwrap());
+
+        let res = ReplaceConfig::builder().output_dir(output_dir.to_path_buf()).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = ReplaceConfig::builder().recursive(true).in_place(true).output_dir(output_dir.to_path_buf()).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn replace_config_builder_test() {
+        let cfg = ReplaceConfig::builder()
+            .nth(2)
+            .replace_all(false)
+            .fill_byte(b' ')
+            .build()
+            .unwrap();
+        assert_eq!(cfg.nth, 2);
+        assert!(!cfg.replace_all);
+        assert_eq!(cfg.fill_byte, b' ');
+
+        let res = ReplaceConfig::builder().nth(2).replace_all(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = ReplaceConfig::builder().from_end(true).replace_all(true).build();
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn replace_test_replacement_file() {
+        let replace_with = read_replacement(Path::new("test_files/file_three"))
+            .expect("Probably file not found");
+        let cfg = ReplaceConfig { fill_byte: b'!', ..Default::default() };
+        replace(
+            b"cooler",
+            &replace_with,
+            Path::new("test_files/file_two"),
+            Path::new("test_files/file_two_replace_file"),
+            &cfg
+        ).expect("Probably a file related error");
+
+        let file = vec!["test_files/file_two_replace_file"];
+        let res = grep::grep(b"20% \x00\x00\x01\x01\xfe!\n", &file).unwrap();
+        assert_eq!(res[0].1, vec![21]);
+    }
 }
@@ -0,0 +1,155 @@
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+
+use crate::replace::{self, ReplaceConfig, ReplaceReport};
+use crate::searcher::Searcher;
+use crate::util;
+
+/// A pattern and its replacement prepared once, so they can be reused
+/// to replace in any number of readers without rebuilding anything per
+/// input. The pattern's skip table is built with the `Searcher` it
+/// holds. Unlike `replace()` it doesn't work with paths, so the input
+/// and the output can be anything, e.g. buffers in memory.
+#[derive(Debug, Clone)]
+pub struct Replacer {
+    searcher: Searcher,
+    replace_with: Vec<u8>,
+}
+
+impl Replacer {
+    pub fn new(to_replace: &[u8], replace_with: &[u8]) -> Self {
+        Replacer {
+            searcher: Searcher::new(to_replace),
+            replace_with: replace_with.to_vec(),
+        }
+    }
+
+    /// Compare bytes with ASCII case folding when searching, like
+    /// `Searcher::ignore_case()`.
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.searcher = self.searcher.ignore_case(ignore_case);
+        self
+    }
+
+    /// Only replace matches bounded by bytes that aren't ASCII
+    /// alphanumeric, like `Searcher::word()`.
+    pub fn word(mut self, word: bool) -> Self {
+        self.searcher = self.searcher.word(word);
+        self
+    }
+
+    /// Replace the matches in `reader`, from its current position to
+    /// its end, writing the result to `writer`. Which matches are
+    /// replaced and how the replacement is padded is decided by
+    /// `replace_config` like in `replace()`. The offsets in the report
+    /// are counted from the starting position. Regex, interactive and
+    /// template mode aren't supported. Unlike `replace_stream()` the
+    /// matches are found in a first pass and `reader` is then seeked
+    /// back to copy it with them replaced, so it has to be seekable.
+    pub fn replace_seekable<R: Read + Seek, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        replace_config: &ReplaceConfig,
+    ) -> Result<ReplaceReport, io::Error> {
+        if replace_config.regex || replace_config.interactive || replace_config.template
+            || replace_config.swap {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A replacer doesn't support regex, interactive, template or swap mode",
+            ));
+        }
+        replace::ensure_fits(self.searcher.pattern(), &self.replace_with, replace_config)?;
+        let len = self.searcher.pattern().len() as u64;
+        let mut replacement = self.replace_with.clone();
+        if !replace_config.allow_length_change && replacement.len() < len as usize {
+            replacement.resize(len as usize, replace_config.fill_byte);
+        }
+
+        // The matches are picked first, then the input is copied with
+        // them replaced in a second pass
+        let start = reader.stream_position()?;
+        let mut offsets = Vec::new();
+        {
+            let mut matches = self.searcher.search(&mut *reader);
+            if replace_config.replace_all || replace_config.limit.is_some() {
+                let limit = replace_config.limit.unwrap_or(usize::MAX);
+                for offset in matches {
+                    if offsets.len() >= limit {
+                        break;
+                    }
+                    // Matches overlapping an already replaced one are skipped
+                    if offsets.last().is_none_or(|&last| offset >= last + len) {
+                        offsets.push(offset);
+                    }
+                }
+            } else if replace_config.from_end {
+                let all_matches: Vec<_> = matches.collect();
+                offsets.extend(all_matches.into_iter().rev().nth(replace_config.nth));
+            } else {
+                offsets.extend(matches.nth(replace_config.nth));
+            }
+        }
+        reader.seek(SeekFrom::Start(start))?;
+
+        let mut writer = BufWriter::new(writer);
+        let mut no_progress = |_, _| {};
+        let mut progress = util::Progress::new(0, &mut no_progress);
+        let mut report = ReplaceReport::default();
+        let mut last_offset = 0;
+        for &offset in offsets.iter() {
+            util::copy_exact_with_progress(reader, &mut writer, offset - last_offset, &mut progress)?;
+            reader.seek(SeekFrom::Current(len as i64))?;
+            writer.write_all(&replacement)?;
+            report.bytes_written += offset - last_offset + replacement.len() as u64;
+            last_offset = offset + len;
+        }
+        report.bytes_written += util::copy_with_progress(reader, &mut writer, &mut progress)?;
+        writer.flush()?;
+        report.count = offsets.len();
+        report.offsets = offsets;
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn replacer_reuse_test() {
+        let replacer = Replacer::new(b"nya", b"NY");
+        let cfg = ReplaceConfig { replace_all: true, fill_byte: b'_', ..Default::default() };
+
+        let mut out = Vec::new();
+        let report = replacer.replace_seekable(&mut Cursor::new(b"nya nyanya"), &mut out, &cfg).unwrap();
+        assert_eq!(out, b"NY_ NY_NY_");
+        assert_eq!(report, ReplaceReport { count: 3, offsets: vec![0, 4, 7], bytes_written: 10 });
+
+        let mut out = Vec::new();
+        let report = replacer.replace_seekable(&mut Cursor::new(b"\x00nyanyan"), &mut out, &cfg).unwrap();
+        assert_eq!(out, b"\x00NY_NY_n");
+        assert_eq!(report.offsets, vec![1, 4]);
+
+        let cfg = ReplaceConfig { nth: 1, from_end: true, allow_length_change: true, ..Default::default() };
+        let mut out = Vec::new();
+        replacer.replace_seekable(&mut Cursor::new(b"nya nyanya"), &mut out, &cfg).unwrap();
+        assert_eq!(out, b"nya NYnya");
+
+        // Word boundaries are checked around the matches after the
+        // starting position too
+        let replacer = Replacer::new(b"nya", b"NYA").word(true);
+        let cfg = ReplaceConfig { replace_all: true, ..Default::default() };
+        let mut reader = Cursor::new(b"xxnya ab nya");
+        reader.seek(SeekFrom::Start(2)).unwrap();
+        let mut out = Vec::new();
+        let report = replacer.replace_seekable(&mut reader, &mut out, &cfg).unwrap();
+        assert_eq!(out, b"NYA ab NYA");
+        assert_eq!(report.offsets, vec![0, 7]);
+
+        let cfg = ReplaceConfig { regex: true, ..Default::default() };
+        let res = replacer.replace_seekable(&mut Cursor::new(b"nya"), &mut Vec::new(), &cfg);
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,366 @@
+use std::io::{self, BufReader, Read, Seek};
+
+use crate::util::{self, CaseFold, Finder, Match, OpenedFile};
+
+/// A search pattern prepared once, so it can be reused to search any
+/// number of files or readers without rebuilding anything per file.
+/// Its Boyer-Moore-Horspool skip table is built when the pattern and
+/// the case options are set, not per search.
+#[derive(Debug, Clone)]
+pub struct Searcher {
+    finder: Finder,
+    ignore_case: bool,
+    fold: CaseFold,
+    align: u64,
+    word: bool,
+}
+
+impl Searcher {
+    pub fn new(pattern: &[u8]) -> Self {
+        Searcher {
+            finder: Finder::new(pattern, None),
+            ignore_case: false,
+            fold: CaseFold::Ascii,
+            align: 1,
+            word: false,
+        }
+    }
+
+    /// Compare bytes with ASCII case folding, so only `A-Z` and `a-z`
+    /// are affected. Other bytes still have to match exactly.
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self.rebuild()
+    }
+
+    /// Fold case with `fold` instead of ASCII when ignoring case, e.g.
+    /// to also match accented Latin-1 letters.
+    pub fn fold(mut self, fold: CaseFold) -> Self {
+        self.fold = fold;
+        self.rebuild()
+    }
+
+    /// Build the skip table again for changed case options.
+    fn rebuild(mut self) -> Self {
+        self.finder = Finder::new(self.finder.pattern(), self.ignore_case.then_some(self.fold));
+        self
+    }
+
+    /// Only match at offsets that are a multiple of `align`, e.g. to
+    /// find aligned structures. An `align` of 0 is treated as 1.
+    pub fn align(mut self, align: u64) -> Self {
+        self.align = align.max(1);
+        self
+    }
+
+    /// Only match when the bytes around the match aren't ASCII
+    /// alphanumeric, like `grep -w`.
+    pub fn word(mut self, word: bool) -> Self {
+        self.word = word;
+        self
+    }
+
+    pub fn pattern(&self) -> &[u8] {
+        self.finder.pattern()
+    }
+
+    /// Whether the pattern is matched byte for byte anywhere, without
+    /// case folding, alignment or word boundaries.
+    pub(crate) fn is_plain(&self) -> bool {
+        !self.ignore_case && self.align == 1 && !self.word
+    }
+
+    /// Search `reader` from its current position. Return an iterator
+    /// of the offsets of the matches, counted from that position. For
+    /// word boundaries the data starts there too.
+    pub fn search<'a, R: Read + Seek + 'a>(&'a self, reader: R) -> impl Iterator<Item = u64> + 'a {
+        let mut reader = BufReader::new(reader);
+        let mut offset = 0;
+        let mut before = None;
+        std::iter::from_fn(move || loop {
+            let found = util::next_match(
+                &mut reader,
+                &self.finder,
+                self.word.then_some(&mut before),
+                &mut offset,
+                &mut None,
+            )?;
+            if !found.offset.is_multiple_of(self.align) {
+                continue;
+            }
+            return Some(found.offset);
+        })
+    }
+
+    /// Search `haystack`, data that's already in memory, without going
+    /// through a reader. Return an iterator of the offsets of the
+    /// matches, like `search()` does.
+    pub fn search_slice<'a>(&'a self, haystack: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let len = self.pattern().len();
+        let is_word_byte = |offset: usize| haystack.get(offset).is_some_and(u8::is_ascii_alphanumeric);
+        self.finder.find_iter(haystack)
+            .filter(move |&offset| (offset as u64).is_multiple_of(self.align))
+            .filter(move |&offset| {
+                !self.word
+                    || !(offset.checked_sub(1).is_some_and(is_word_byte) || is_word_byte(offset + len))
+            })
+    }
+
+    /// Same as `search()`, except `reader` doesn't have to be seekable,
+    /// e.g. a decompressor, and the offsets are collected while the
+    /// data streams by. Only the bytes a match can still start in are
+    /// held back between reads.
+    pub fn search_stream<R: Read>(&self, reader: R) -> Result<Vec<u64>, io::Error> {
+        let mut found = Vec::new();
+        search_stream_all(&[self], reader, 0, &mut |_, offset| {
+            found.push(offset);
+            true
+        }, &mut |_| Ok(()))?;
+
+        Ok(found)
+    }
+
+    /// Same as `util::find_matches()`, except with the options of
+    /// this searcher.
+    pub(crate) fn find_matches<'a, 'p>(
+        &'a self,
+        opened_file: &'a mut OpenedFile<'p>,
+    ) -> Match<'a, 'p> {
+        util::find_matches(opened_file, self.finder.pattern())
+            .ignore_case(self.ignore_case)
+            .fold(self.fold)
+            .align(self.align)
+            .word(self.word)
+            .finder(&self.finder)
+    }
+}
+
+/// Search `reader` for all of `searchers` in a single pass, like
+/// `Searcher::search_stream()` does for one of them. Only the matches
+/// from `start` on are searched for. Past the start of the data
+/// `reader` has to begin with the byte right before `start`, for word
+/// boundaries and so the offsets are counted from the start of the
+/// data. `on_match` gets the index of the searcher and the offset of
+/// every match, and the search stops as soon as it returns false.
+/// Matches of one searcher come in order. `on_progress` gets the offset
+/// before which every match was passed to `on_match` about every
+/// `PROGRESS_INTERVAL` bytes and at the end, and an error returned by
+/// it stops the search right away.
+pub(crate) fn search_stream_all<R: Read>(
+    searchers: &[&Searcher],
+    mut reader: R,
+    start: u64,
+    on_match: &mut dyn FnMut(usize, u64) -> bool,
+    on_progress: &mut dyn FnMut(u64) -> Result<(), io::Error>,
+) -> Result<(), io::Error> {
+    if searchers.iter().all(|searcher| searcher.pattern().is_empty()) {
+        return Ok(());
+    }
+    let is_word_byte = |byte: Option<&u8>| byte.is_some_and(u8::is_ascii_alphanumeric);
+    let mut chunk = vec![0u8; util::PROGRESS_INTERVAL as usize];
+    // Data not dropped yet, starting `buf_offset` bytes into the
+    // stream. Matches of every searcher starting before its `next` were
+    // already checked
+    let mut buf: Vec<u8> = Vec::new();
+    let mut buf_offset = start.saturating_sub(1);
+    let mut next = vec![usize::from(start > 0); searchers.len()];
+    let mut reported = start / util::PROGRESS_INTERVAL;
+    loop {
+        let n = match reader.read(&mut chunk) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        buf.extend_from_slice(&chunk[..n]);
+        // A match is only checked once the byte after it is known,
+        // for word boundaries
+        let end = if n == 0 { buf.len() } else { buf.len() - 1 };
+        for (i, searcher) in searchers.iter().enumerate() {
+            let len = searcher.pattern().len();
+            if len == 0 || end < next[i] + len {
+                continue;
+            }
+            for at in searcher.finder.find_iter(&buf[next[i]..end]) {
+                let at = next[i] + at;
+                let offset = buf_offset + at as u64;
+                if !offset.is_multiple_of(searcher.align) {
+                    continue;
+                }
+                let before = at.checked_sub(1).and_then(|before| buf.get(before));
+                if searcher.word && (is_word_byte(before) || is_word_byte(buf.get(at + len))) {
+                    continue;
+                }
+                if !on_match(i, offset) {
+                    return Ok(());
+                }
+            }
+            next[i] = end - len + 1;
+        }
+        if n == 0 {
+            return on_progress(buf_offset + buf.len() as u64);
+        }
+        let searched = searchers
+            .iter()
+            .zip(&next)
+            .filter(|(searcher, _)| !searcher.pattern().is_empty())
+            .map(|(_, &next)| next)
+            .min()
+            .unwrap_or(0);
+        let searched_offset = buf_offset + searched as u64;
+        if searched_offset / util::PROGRESS_INTERVAL != reported {
+            reported = searched_offset / util::PROGRESS_INTERVAL;
+            on_progress(searched_offset)?;
+        }
+        // Keep the byte before the next match, for word boundaries
+        let drop = searched.saturating_sub(1);
+        buf.drain(..drop);
+        buf_offset += drop as u64;
+        for next in next.iter_mut() {
+            *next = next.saturating_sub(drop);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::{self, Cursor, SeekFrom};
+
+    #[test]
+    fn searcher_reuse_test() {
+        let searcher = Searcher::new(b"NYA").ignore_case(true);
+
+        let offsets: Vec<_> = searcher.search(Cursor::new(b"nya nyanya")).collect();
+        assert_eq!(offsets, vec![0, 4, 7]);
+
+        let file = File::open("test_files/file_one").unwrap();
+        let offsets: Vec<_> = searcher.search(file).collect();
+        assert_eq!(offsets, vec![
+            0, 3, 9, 12, 19, 22, 32, 43, 48, 55, 58, 64, 67, 74, 77, 84, 94, 104, 109
+        ]);
+    }
+
+    /// A reader which, like a block device, claims to be empty when
+    /// asked for its size, but still yields data when read.
+    struct NoSizeReader(Cursor<Vec<u8>>);
+
+    impl Read for NoSizeReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl Seek for NoSizeReader {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            match pos {
+                SeekFrom::End(_) => Ok(0),
+                pos => self.0.seek(pos),
+            }
+        }
+    }
+
+    #[test]
+    fn searcher_slice_test() {
+        let haystack = b"NYA nyanya nya\x00";
+        for searcher in [
+            Searcher::new(b"nya"),
+            Searcher::new(b"NYA").ignore_case(true),
+            Searcher::new(b"nya").align(2).word(true),
+        ] {
+            let in_memory: Vec<_> = searcher.search_slice(haystack).map(|n| n as u64).collect();
+            let read: Vec<_> = searcher.search(Cursor::new(haystack)).collect();
+            assert_eq!(in_memory, read);
+        }
+        let offsets: Vec<_> = Searcher::new(b"nya").word(true).search_slice(haystack).collect();
+        assert_eq!(offsets, vec![11]);
+    }
+
+    #[test]
+    fn searcher_word_buffer_boundary_test() {
+        // Matches ending or starting right at the end of the 8 KiB read
+        // buffer have their boundaries in the next or the previous read
+        let searcher = Searcher::new(b"nya").word(true);
+        for padding in 8185..8195 {
+            for tail in [&b"nya x"[..], b"nyax ", b" nya", b"xnya nya"] {
+                let mut data = vec![b' '; padding];
+                data.extend_from_slice(tail);
+                let in_memory: Vec<_> = searcher.search_slice(&data).map(|n| n as u64).collect();
+                let read: Vec<_> = searcher.search(Cursor::new(&data)).collect();
+                assert_eq!(read, in_memory);
+            }
+        }
+    }
+
+    #[test]
+    fn searcher_stream_test() {
+        let data = std::fs::read("test_files/file_one").unwrap();
+        for searcher in [
+            Searcher::new(b"nya"),
+            Searcher::new(b"NYA").ignore_case(true),
+            Searcher::new(b"nya").align(3).word(true),
+        ] {
+            let streamed = searcher.search_stream(Cursor::new(&data)).unwrap();
+            let read: Vec<_> = searcher.search(Cursor::new(&data)).collect();
+            assert_eq!(streamed, read);
+        }
+        // One byte at a time, so every match straddles reads
+        let data = b"nya xnya nyanya nya";
+        let streamed = Searcher::new(b"nya").search_stream(OneByteReader(data.to_vec(), 0)).unwrap();
+        assert_eq!(streamed, vec![0, 5, 9, 12, 16]);
+        let streamed = Searcher::new(b"nya").word(true).search_stream(OneByteReader(data.to_vec(), 0)).unwrap();
+        assert_eq!(streamed, vec![0, 16]);
+    }
+
+    #[test]
+    fn search_stream_all_start_test() {
+        // Starting in the middle finds the rest of the matches, with the
+        // offsets and the word boundaries of the whole data
+        let data = std::fs::read("test_files/file_one").unwrap();
+        for searcher in [Searcher::new(b"nya"), Searcher::new(b"nya").align(3).word(true)] {
+            let all = searcher.search_stream(Cursor::new(&data)).unwrap();
+            for start in [1, 20, 44] {
+                let mut found = Vec::new();
+                let mut on_match = |_, offset| {
+                    found.push(offset);
+                    true
+                };
+                let reader = Cursor::new(&data[start - 1..]);
+                search_stream_all(&[&searcher], reader, start as u64, &mut on_match, &mut |_| Ok(())).unwrap();
+                let expected: Vec<_> = all.iter().copied().filter(|&offset| offset >= start as u64).collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    /// A reader returning a single byte per read.
+    struct OneByteReader(Vec<u8>, usize);
+
+    impl Read for OneByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.get(self.1), buf.first_mut()) {
+                (Some(&byte), Some(first)) => {
+                    *first = byte;
+                    self.1 += 1;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    #[test]
+    fn searcher_no_size_test() {
+        let reader = NoSizeReader(Cursor::new(b"\x00nya\x00\x00nya".to_vec()));
+        let offsets: Vec<_> = Searcher::new(b"nya").search(reader).collect();
+        assert_eq!(offsets, vec![1, 6]);
+    }
+
+    #[test]
+    fn searcher_align_test() {
+        let searcher = Searcher::new(b"NYA").ignore_case(true).align(4);
+        let file = File::open("test_files/file_one").unwrap();
+        let offsets: Vec<_> = searcher.search(file).collect();
+        assert_eq!(offsets, vec![0, 12, 32, 48, 64, 84, 104]);
+    }
+}
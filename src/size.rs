@@ -0,0 +1,80 @@
+use std::io;
+use std::path::Path;
+use std::fs::File;
+use crate::util::{self, Offset};
+
+/// Function for executing the command line size command. You probably
+/// want to use `size()` or `range_size()` instead. Print the length
+/// of the range and return it.
+pub fn size_command(
+    input_filename: &Path,
+    start: Offset,
+    end: Option<Offset>,
+) -> Result<u64, io::Error> {
+    let len = range_size(input_filename, start, end)?;
+    println!("{}", len);
+
+    Ok(len)
+}
+
+/// Size of `input_filename` in bytes. Regular files are measured with
+/// their metadata. Anything else, like a pipe or a file in `/proc`
+/// that claims to be empty, is read to the end instead, so it's
+/// consumed.
+pub fn size(input_filename: &Path) -> Result<u64, io::Error> {
+    let mut file = File::open(input_filename)?;
+    if file.metadata()?.is_file() {
+        return Ok(file.metadata()?.len());
+    }
+    match util::file_len(&file)? {
+        0 => io::copy(&mut file, &mut io::sink()),
+        len => Ok(len),
+    }
+}
+
+/// Length of the range `[start, end)` of `input_filename`. Offsets
+/// counted from the end are resolved against the size of the file, a
+/// missing `end` means the end of the file. Return an error if the
+/// range doesn't fit in the file.
+pub fn range_size(
+    input_filename: &Path,
+    start: Offset,
+    end: Option<Offset>,
+) -> Result<u64, io::Error> {
+    let file_len = size(input_filename)?;
+    let start = start.resolve(file_len)?;
+    let end = end.unwrap_or(Offset::FromEnd(0)).resolve(file_len)?;
+    if start > end || end as u64 > file_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Range {}..{} doesn't fit in the file", start, end),
+        ));
+    }
+
+    Ok((end - start) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn simple_size_test() {
+        assert_eq!(size(Path::new("test_files/file_two")).unwrap(), 127);
+        assert_eq!(size(Path::new("test_files/file_zeros")).unwrap(), 64);
+    }
+
+    #[test]
+    fn range_size_test() {
+        let file = Path::new("test_files/file_two");
+        assert_eq!(range_size(file, Offset::FromStart(0), None).unwrap(), 127);
+        assert_eq!(range_size(file, Offset::FromStart(20), Some(Offset::FromEnd(7))).unwrap(), 100);
+        assert_eq!(range_size(file, Offset::FromEnd(10), None).unwrap(), 10);
+
+        let res = range_size(file, Offset::FromStart(20), Some(Offset::FromStart(10)));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let res = range_size(file, Offset::FromStart(0), Some(Offset::FromStart(128)));
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
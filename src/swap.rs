@@ -0,0 +1,151 @@
+use std::io;
+use std::io::{Read, Write, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+use crate::util;
+
+#[derive(Default, Debug)]
+pub struct SwapConfig {
+    pub quiet: bool,
+}
+
+/// Function for executing the command line swap command. You probably
+/// want to use `swap()` instead.
+pub fn swap_command(
+    a: Range<usize>,
+    b: Range<usize>,
+    input_filename: &Path,
+    output_filename: &Path,
+    swap_config: &SwapConfig,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    swap_with_progress(a, b, input_filename, output_filename, progress)?;
+    if !swap_config.quiet {
+        println!("Swapped successfully");
+    }
+
+    Ok(())
+}
+
+/// Exchange the byte ranges `a` and `b` of `input_filename`, counting
+/// from 0. The ranges can have different lengths, the bytes between
+/// them are shifted accordingly. Results are saved in
+/// `output_filename`. Return an error if the ranges overlap or don't
+/// fit in the file.
+pub fn swap(
+    a: Range<usize>,
+    b: Range<usize>,
+    input_filename: &Path,
+    output_filename: &Path,
+) -> Result<(), io::Error> {
+    swap_with_progress(a, b, input_filename, output_filename, &mut |_, _| {})
+}
+
+/// Same as `swap()`, except `progress` is periodically called with the
+/// number of input bytes processed so far and the input size.
+pub fn swap_with_progress(
+    a: Range<usize>,
+    b: Range<usize>,
+    input_filename: &Path,
+    output_filename: &Path,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<(), io::Error> {
+    let mut input_file = util::open_file(input_filename)?;
+    util::ensure_different_files(input_filename, output_filename)?;
+    let file_len = input_file.file.get_ref().metadata()?.len();
+    let (first, second) = if a.start <= b.start { (a, b) } else { (b, a) };
+    for range in [&first, &second] {
+        if range.start > range.end || range.end as u64 > file_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Range {}..{} doesn't fit in the file", range.start, range.end),
+            ));
+        }
+    }
+    if first.end > second.start {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Ranges {}..{} and {}..{} overlap",
+                first.start, first.end, second.start, second.end,
+            ),
+        ));
+    }
+
+    // Only the swapped ranges are held in memory, the rest is copied
+    // in chunks
+    let mut first_bytes = vec![0u8; first.len()];
+    input_file.file.seek(SeekFrom::Start(first.start as u64))?;
+    input_file.file.read_exact(&mut first_bytes)?;
+    let mut second_bytes = vec![0u8; second.len()];
+    input_file.file.seek(SeekFrom::Start(second.start as u64))?;
+    input_file.file.read_exact(&mut second_bytes)?;
+    input_file.file.rewind()?;
+
+    let mut progress = util::Progress::new(file_len, progress);
+    util::write_atomically(output_filename, |output_file| {
+        let input = &mut input_file.file;
+        util::copy_exact_with_progress(input, output_file, first.start as u64, &mut progress)?;
+        output_file.write_all(&second_bytes)?;
+        input.seek_relative(first.len() as i64)?;
+        progress.advance(first.len() as u64);
+        let middle = (second.start - first.end) as u64;
+        util::copy_exact_with_progress(input, output_file, middle, &mut progress)?;
+        output_file.write_all(&first_bytes)?;
+        input.seek_relative(second.len() as i64)?;
+        progress.advance(second.len() as u64);
+        util::copy_with_progress(input, output_file, &mut progress)?;
+        output_file.flush()?;
+        util::copy_metadata(input_file.file.get_ref(), output_file.get_ref())
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn simple_swap_test() {
+        swap(
+            3..5,
+            0..2,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_swap"),
+        ).expect("Probably a file related error");
+        assert_eq!(std::fs::read("test_files/file_three_swap").unwrap(), b"\x01\xfe\x01\x00\x00");
+    }
+
+    #[test]
+    fn swap_test_different_lengths() {
+        swap(
+            0..1,
+            3..5,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_swap_lengths"),
+        ).expect("Probably a file related error");
+        let swapped = std::fs::read("test_files/file_three_swap_lengths").unwrap();
+        assert_eq!(swapped, b"\x01\xfe\x00\x01\x00");
+    }
+
+    #[test]
+    fn swap_test_invalid_ranges() {
+        let res = swap(
+            0..3,
+            2..4,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_swap_invalid"),
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let res = swap(
+            0..1,
+            4..6,
+            Path::new("test_files/file_three"),
+            Path::new("test_files/file_three_swap_invalid"),
+        );
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,184 @@
+use std::io;
+
+/// Bytes with `%offset%` and `%size%` tokens in them, parsed once and
+/// expanded for every offset they're written at. The tokens expand to
+/// the value in decimal ASCII. `%offset:N%` and `%size:N%` expand to
+/// the value as an N bytes long little endian integer instead, with a
+/// `be` suffix like `%size:4be%` for big endian. N can be 1, 2, 4 or
+/// 8. `%%` is a literal `%`, anything else is left as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(Vec<u8>),
+    Offset(Format),
+    Size(Format),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Ascii,
+    Le(usize),
+    Be(usize),
+}
+
+impl Format {
+    fn parse(spec: &[u8]) -> Result<Self, io::Error> {
+        let (width, big_endian) = match spec.strip_suffix(b"be") {
+            Some(width) => (width, true),
+            None => (spec, false),
+        };
+        let width = match width {
+            b"1" => 1,
+            b"2" => 2,
+            b"4" => 4,
+            b"8" => 8,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid template width {}", String::from_utf8_lossy(spec)),
+                ));
+            }
+        };
+
+        Ok(if big_endian { Format::Be(width) } else { Format::Le(width) })
+    }
+
+    fn write(self, value: u64, out: &mut Vec<u8>) {
+        match self {
+            Format::Ascii => out.extend_from_slice(value.to_string().as_bytes()),
+            Format::Le(width) => out.extend_from_slice(&value.to_le_bytes()[..width]),
+            Format::Be(width) => out.extend_from_slice(&value.to_be_bytes()[8 - width..]),
+        }
+    }
+}
+
+impl Template {
+    /// Parse the tokens in `template`. Return an error for a token with
+    /// an invalid width.
+    pub fn parse(template: &[u8]) -> Result<Self, io::Error> {
+        let mut parts = Vec::new();
+        let mut literal = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.iter().position(|&b| b == b'%') {
+            literal.extend_from_slice(&rest[..start]);
+            rest = &rest[start + 1..];
+            let Some(end) = rest.iter().position(|&b| b == b'%') else {
+                literal.push(b'%');
+                break;
+            };
+            let (name, spec) = match rest[..end].iter().position(|&b| b == b':') {
+                Some(colon) => (&rest[..colon], Some(&rest[colon + 1..end])),
+                None => (&rest[..end], None),
+            };
+            let format = match spec {
+                Some(spec) if matches!(name, b"offset" | b"size") => Format::parse(spec)?,
+                _ => Format::Ascii,
+            };
+            let part = match name {
+                b"" if spec.is_none() => {
+                    literal.push(b'%');
+                    rest = &rest[end + 1..];
+                    continue;
+                }
+                b"offset" => Part::Offset(format),
+                b"size" => Part::Size(format),
+                // Not a token, so keep the % and go on searching after it
+                _ => {
+                    literal.push(b'%');
+                    continue;
+                }
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+            rest = &rest[end + 1..];
+        }
+        literal.extend_from_slice(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template { parts })
+    }
+
+    /// Return an error if a value up to `max` doesn't fit in one of the
+    /// fixed width tokens. Values that don't fit are cut to their low
+    /// bytes by `expand()`.
+    pub fn fits(&self, max: u64) -> Result<(), io::Error> {
+        for part in self.parts.iter() {
+            if let Part::Offset(Format::Le(width) | Format::Be(width))
+                | Part::Size(Format::Le(width) | Format::Be(width)) = part
+            {
+                if *width < 8 && max >> (*width * 8) != 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} doesn't fit in a {} bytes wide template token", max, width),
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Expand the tokens with `offset` and `size`.
+    pub fn expand(&self, offset: u64, size: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.expand_into(offset, size, &mut out, |bytes, out| out.extend_from_slice(bytes));
+
+        out
+    }
+
+    /// Same as `expand()`, except the result is appended to `out` and
+    /// the literal parts are appended by `literal` instead, e.g. to
+    /// expand capture group references only in them and never in the
+    /// bytes of the tokens.
+    pub fn expand_into<F: FnMut(&[u8], &mut Vec<u8>)>(
+        &self,
+        offset: u64,
+        size: u64,
+        out: &mut Vec<u8>,
+        mut literal: F,
+    ) {
+        for part in self.parts.iter() {
+            match part {
+                Part::Literal(bytes) => literal(bytes, out),
+                Part::Offset(format) => format.write(offset, out),
+                Part::Size(format) => format.write(size, out),
+            }
+        }
+    }
+
+    /// The longest expansion for offsets and sizes up to `max`, since
+    /// a bigger value is never shorter.
+    pub fn max_len(&self, max: u64) -> usize {
+        self.expand(max, max).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_expand_test() {
+        let template = Template::parse(b"LEN=%size% at %offset:2%, %size:4be% 100%% %other%").unwrap();
+        assert_eq!(
+            template.expand(0x102, 127),
+            b"LEN=127 at \x02\x01, \x00\x00\x00\x7f 100% %other%",
+        );
+        assert_eq!(Template::parse(b"50%").unwrap().expand(0, 0), b"50%");
+        assert_eq!(Template::parse(b"%%size%").unwrap().expand(0, 3), b"%size%");
+
+        let res = Template::parse(b"%size:3%");
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let template = Template::parse(b"%offset:1%").unwrap();
+        assert!(template.fits(255).is_ok());
+        assert_eq!(template.fits(256).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
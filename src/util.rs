@@ -1,7 +1,14 @@
-use std::fs::File;
-use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::borrow::Cow;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::num::ParseIntError;
+use std::ops::Range;
+use std::str::FromStr;
 use std::path::{PathBuf, Path};
 
+use regex::bytes::{Regex, RegexBuilder};
+
 /// Custom struct to bundle an opened file and its path together
 #[derive(Debug)]
 pub struct OpenedFile<'a> {
@@ -9,18 +16,229 @@ pub struct OpenedFile<'a> {
     pub path: &'a Path,
 }
 
+/// A single match found in a file: where it starts and how many bytes
+/// it spans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoundMatch {
+    pub offset: u64,
+    pub len: usize,
+}
+
+/// An offset in a file, counted either from its start or backward from
+/// its end. `FromEnd(0)` is the end of the file itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offset {
+    FromStart(usize),
+    FromEnd(usize),
+}
+
+impl Offset {
+    /// Turn the offset into one counted from the start of a file of
+    /// `len` bytes. Return an error if it's before the start of the file.
+    pub fn resolve(self, len: u64) -> Result<usize, io::Error> {
+        match self {
+            Offset::FromStart(offset) => Ok(offset),
+            Offset::FromEnd(back) => (len as usize).checked_sub(back).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Offset -{} is before the start of the file", back),
+                )
+            }),
+        }
+    }
+}
+
+impl From<usize> for Offset {
+    fn from(offset: usize) -> Self {
+        Offset::FromStart(offset)
+    }
+}
+
+/// Parse `N` as an offset from the start and `-N` as an offset from
+/// the end, so `-0` is the end of the file.
+impl FromStr for Offset {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix('-') {
+            Some(back) => Ok(Offset::FromEnd(back.parse()?)),
+            None => Ok(Offset::FromStart(s.parse()?)),
+        }
+    }
+}
+
+/// How often, in bytes, long running operations report their progress.
+pub const PROGRESS_INTERVAL: u64 = 1 << 16;
+
+/// Which bytes are the same letter in another case when ignoring case.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseFold {
+    /// Only `A-Z` and `a-z`.
+    #[default]
+    Ascii,
+    /// ASCII and the accented letters of Latin-1, so e.g. `\xc9` (É)
+    /// matches `\xe9` (é). `\xd7` and `\xf7` aren't letters.
+    Latin1,
+}
+
+/// Lowercase of every byte as a Latin-1 character.
+static LATIN1_LOWER: [u8; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let byte = i as u8;
+        table[i] = match byte {
+            b'A'..=b'Z' | 0xc0..=0xde if byte != 0xd7 => byte + 0x20,
+            _ => byte,
+        };
+        i += 1;
+    }
+    table
+};
+
+impl CaseFold {
+    /// The lowercase of `byte`, the same for all bytes that are the
+    /// same letter.
+    fn lower(self, byte: u8) -> u8 {
+        match self {
+            CaseFold::Ascii => byte.to_ascii_lowercase(),
+            CaseFold::Latin1 => LATIN1_LOWER[byte as usize],
+        }
+    }
+
+    /// Check if `a` and `b` are the same ignoring case.
+    pub fn eq(self, a: &[u8], b: &[u8]) -> bool {
+        match self {
+            CaseFold::Ascii => a.eq_ignore_ascii_case(b),
+            CaseFold::Latin1 => {
+                a.len() == b.len() && a.iter().zip(b).all(|(&x, &y)| {
+                    LATIN1_LOWER[x as usize] == LATIN1_LOWER[y as usize]
+                })
+            }
+        }
+    }
+}
+
 /// Iterator returned by the `find_matches()` function. It helps us to
 /// get all the offsets of the matches of a pattern in an opened file.
-#[derive(Debug)]
-pub struct Match<'a> {
-    pub opened_file: &'a mut OpenedFile<'a>,
+pub struct Match<'a, 'p> {
+    pub opened_file: &'a mut OpenedFile<'p>,
     pub pattern: &'a [u8],
     pub offset: u64,
+    pub ignore_case: bool,
+    pub fold: CaseFold,
+    pub align: u64,
+    pub word: bool,
+    /// The byte right before `offset`, `None` at the start of the file.
+    pub before: Option<u8>,
+    pub on_progress: Option<&'a mut dyn FnMut(u64)>,
+    /// Built from the options on the first search, unless given.
+    finder: Option<Cow<'a, Finder>>,
 }
 
-/// Iterator returned by the `open_files()` function. Avoid using
-/// `.collect()`, otherwise we will hit the opened file descriptors
-/// limit.
+impl fmt::Debug for Match<'_, '_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Match")
+            .field("opened_file", &self.opened_file)
+            .field("pattern", &self.pattern)
+            .field("offset", &self.offset)
+            .field("ignore_case", &self.ignore_case)
+            .field("fold", &self.fold)
+            .field("align", &self.align)
+            .field("word", &self.word)
+            .field("before", &self.before)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Keeps track of how many bytes out of `total` were processed so far
+/// and reports it to a callback, so the caller can show a progress bar.
+pub struct Progress<'a> {
+    callback: &'a mut dyn FnMut(u64, u64),
+    done: u64,
+    total: u64,
+}
+
+impl fmt::Debug for Progress<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Progress")
+            .field("done", &self.done)
+            .field("total", &self.total)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> Progress<'a> {
+    pub fn new(total: u64, callback: &'a mut dyn FnMut(u64, u64)) -> Self {
+        Progress { callback, done: 0, total }
+    }
+
+    /// Grow the total by `n` bytes, for totals only known bit by bit.
+    pub fn add_total(&mut self, n: u64) {
+        self.total = self.total.saturating_add(n);
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done
+    }
+
+    /// Report that `done` bytes were processed. The reported value
+    /// never goes backwards and never exceeds the total.
+    pub fn set(&mut self, done: u64) {
+        let done = done.min(self.total);
+        if done > self.done {
+            self.done = done;
+            (self.callback)(self.done, self.total);
+        }
+    }
+
+    /// Report that `n` more bytes were processed.
+    pub fn advance(&mut self, n: u64) {
+        self.set(self.done + n);
+    }
+}
+
+/// Same as `io::copy()`, except progress is reported after every
+/// copied chunk.
+pub fn copy_with_progress<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    progress: &mut Progress,
+) -> Result<u64, io::Error> {
+    let mut buf = vec![0u8; PROGRESS_INTERVAL as usize];
+    let mut copied = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(copied),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+        progress.advance(n as u64);
+    }
+}
+
+/// Copy exactly `len` bytes from `reader` to `writer`, reporting
+/// progress. Return an error if the reader ends too early.
+pub fn copy_exact_with_progress<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    len: u64,
+    progress: &mut Progress,
+) -> Result<(), io::Error> {
+    if copy_with_progress(&mut reader.take(len), writer, progress)? != len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+
+    Ok(())
+}
+
+/// Iterator returned by the `open_files()` function. Files which
+/// can't be opened are yielded as errors instead of being skipped.
+/// Avoid using `.collect()`, otherwise we will hit the opened file
+/// descriptors limit.
 #[derive(Debug)]
 pub struct OpenFiles<'a, T: AsRef<Path> + 'a> {
     pub files: &'a [T],
@@ -28,46 +246,565 @@ pub struct OpenFiles<'a, T: AsRef<Path> + 'a> {
 }
 
 impl<'a, T: AsRef<Path> + 'a> Iterator for OpenFiles<'a, T> {
-    type Item = OpenedFile<'a>;
-    fn next(&mut self) -> Option<OpenedFile<'a>> {
-        match self.files.get(self.nth) {
-            Some(filename) => {
-                self.nth += 1;
-                open_file(filename.as_ref()).ok()
-            },
-            None => None,
-        }
+    type Item = Result<OpenedFile<'a>, io::Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        let filename = self.files.get(self.nth)?;
+        self.nth += 1;
+        Some(open_file(filename.as_ref()))
     }
 }
 
-impl<'a> Iterator for Match<'a> {
-    type Item = u64;
+impl Iterator for Match<'_, '_> {
+    type Item = FoundMatch;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let pattern_len = self.pattern.len();
-        // This is a slow O(n^2) way to do it. Obviously we can be smarter about it,
-        // using a proper string-searching algorithm
-        let mut buf = vec![0u8; pattern_len];
-        while let Ok(()) = self.opened_file.file.read_exact(&mut buf) {
-            self.offset += 1;
-            self.opened_file.file.seek_relative(-(pattern_len as i64)+1).unwrap();
-            if buf == self.pattern {
-                return Some(self.offset-1);
+        let finder = self.finder.get_or_insert_with(|| {
+            Cow::Owned(Finder::new(self.pattern, self.ignore_case.then_some(self.fold)))
+        });
+        loop {
+            let found = next_match(
+                &mut self.opened_file.file,
+                finder,
+                self.word.then_some(&mut self.before),
+                &mut self.offset,
+                &mut self.on_progress,
+            )?;
+            if !found.offset.is_multiple_of(self.align) {
+                continue;
+            }
+            return Some(found);
+        }
+    }
+}
+
+/// Find the next match of `pattern` in `reader`, starting from its
+/// current position, which has to be `offset` bytes into the data.
+/// `offset` is moved past the start of the returned match. This is
+/// the search loop shared by `Match` and `Searcher`. With `word` set only matches
+/// not surrounded by ASCII alphanumeric bytes are returned. It holds
+/// the byte right before the position of `reader`, `None` at the start
+/// of the data, and is kept up to date, so the boundaries are checked
+/// with the bytes that were read anyway.
+pub(crate) fn next_match<R: Read + Seek>(
+    reader: &mut BufReader<R>,
+    finder: &Finder,
+    mut word: Option<&mut Option<u8>>,
+    offset: &mut u64,
+    on_progress: &mut Option<&mut dyn FnMut(u64)>,
+) -> Option<FoundMatch> {
+    let pattern_len = finder.pattern().len();
+    // An empty pattern would match everywhere without ever moving forward
+    if pattern_len == 0 {
+        return None;
+    }
+    let mut advance = |offset: &mut u64, step: usize| {
+        let before = *offset / PROGRESS_INTERVAL;
+        *offset += step as u64;
+        if *offset / PROGRESS_INTERVAL != before {
+            if let Some(on_progress) = on_progress.as_mut() {
+                on_progress(*offset);
+            }
+        }
+    };
+    let is_word_byte = |byte: Option<u8>| byte.is_some_and(|byte| byte.is_ascii_alphanumeric());
+    loop {
+        // Search whatever is already buffered in memory first
+        let buffered = reader.fill_buf().ok()?;
+        if buffered.len() >= pattern_len {
+            let mut found = None;
+            // A match ending the buffer can't be checked for a word
+            // boundary without the byte after it
+            let mut straddling = None;
+            for i in finder.find_iter(buffered) {
+                let Some(before) = word.as_deref() else {
+                    found = Some(i);
+                    break;
+                };
+                let Some(&after) = buffered.get(i + pattern_len) else {
+                    straddling = Some(i);
+                    break;
+                };
+                let before = i.checked_sub(1).map_or(*before, |before| Some(buffered[before]));
+                if !is_word_byte(before) && !is_word_byte(Some(after)) {
+                    found = Some(i);
+                    break;
+                }
+            }
+            let step = match (found, straddling) {
+                (Some(i), _) => i + 1,
+                (None, Some(i)) => i,
+                (None, None) => buffered.len() - pattern_len + 1,
+            };
+            if step > 0 {
+                if let Some(before) = word.as_deref_mut() {
+                    *before = Some(buffered[step - 1]);
+                }
+                reader.consume(step);
+                advance(offset, step);
             }
+            if found.is_some() {
+                return Some(FoundMatch { offset: *offset-1, len: pattern_len });
+            }
+            if straddling.is_none() {
+                continue;
+            }
+        }
+
+        // The pattern doesn't fit in what's left of the buffer, so check
+        // this one offset by reading across the buffer boundary, together
+        // with the byte after it for word boundaries
+        let mut buf = Vec::with_capacity(pattern_len + 1);
+        reader.by_ref().take(pattern_len as u64 + 1).read_to_end(&mut buf).ok()?;
+        if buf.len() < pattern_len {
+            return None;
+        }
+        reader.seek_relative(-(buf.len() as i64)+1).ok()?;
+        advance(offset, 1);
+        let bounded = match word.as_deref_mut() {
+            Some(before) => {
+                let before = before.replace(buf[0]);
+                !is_word_byte(before) && !is_word_byte(buf.get(pattern_len).copied())
+            }
+            None => true,
+        };
+        if bounded && finder.is_match(&buf[..pattern_len]) {
+            return Some(FoundMatch { offset: *offset-1, len: pattern_len });
         }
-        
-        None
     }
 }
 
-pub fn find_matches<'a>(
-    opened_file: &'a mut OpenedFile<'a>,
+/// Find every occurrence of `needle` in `haystack`, including the
+/// overlapping ones. Yields the offsets of the matches in ascending
+/// order. An empty needle matches nothing. Doesn't touch any files,
+/// so it works on data that's already in memory.
+pub fn find_in_slice<'a>(haystack: &'a [u8], needle: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    slice_matches(haystack, needle, None)
+}
+
+/// Count the occurrences of `needle` in `haystack`, overlapping ones
+/// too, so the count is the same as that of `find_in_slice()`. Uses
+/// Boyer-Moore-Horspool, which skips ahead by up to the length of the
+/// needle after every mismatch.
+pub(crate) fn count_horspool(haystack: &[u8], needle: &[u8]) -> usize {
+    Finder::new(needle, None).find_iter(haystack).count()
+}
+
+/// A pattern together with its Boyer-Moore-Horspool skip table, built
+/// once and reused for every search. With `fold` set bytes are compared
+/// ignoring case and the table is keyed by their lowercase.
+#[derive(Clone)]
+pub(crate) struct Finder {
+    pattern: Vec<u8>,
+    fold: Option<CaseFold>,
+    /// How far the window can move when its last byte is this one
+    shift: Box<[usize; 256]>,
+}
+
+impl fmt::Debug for Finder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Finder")
+            .field("pattern", &self.pattern)
+            .field("fold", &self.fold)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Finder {
+    pub(crate) fn new(pattern: &[u8], fold: Option<CaseFold>) -> Self {
+        let len = pattern.len();
+        let mut finder = Finder { pattern: pattern.to_vec(), fold, shift: Box::new([len.max(1); 256]) };
+        for (i, &byte) in pattern[..len.saturating_sub(1)].iter().enumerate() {
+            let key = finder.key(byte);
+            finder.shift[key] = len - 1 - i;
+        }
+
+        finder
+    }
+
+    pub(crate) fn pattern(&self) -> &[u8] {
+        &self.pattern
+    }
+
+    fn key(&self, byte: u8) -> usize {
+        self.fold.map_or(byte, |fold| fold.lower(byte)) as usize
+    }
+
+    /// Check if `window` is the pattern, ignoring case with `fold`.
+    pub(crate) fn is_match(&self, window: &[u8]) -> bool {
+        match self.fold {
+            Some(fold) => fold.eq(window, &self.pattern),
+            None => window == self.pattern,
+        }
+    }
+
+    /// Find every occurrence of the pattern in `haystack`, including the
+    /// overlapping ones, in ascending order. An empty pattern matches
+    /// nothing.
+    pub(crate) fn find_iter<'a>(&'a self, haystack: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+        let len = self.pattern.len();
+        let mut position = 0;
+        std::iter::from_fn(move || {
+            if len == 0 {
+                return None;
+            }
+            while position + len <= haystack.len() {
+                let at = position;
+                position += self.shift[self.key(haystack[at + len - 1])];
+                if self.is_match(&haystack[at..at + len]) {
+                    return Some(at);
+                }
+            }
+            None
+        })
+    }
+}
+
+/// Same as `find_in_slice()`, optionally comparing with case folding.
+pub(crate) fn slice_matches<'a>(
+    haystack: &'a [u8],
+    needle: &'a [u8],
+    fold: Option<CaseFold>,
+) -> impl Iterator<Item = usize> + 'a {
+    // This is a slow O(n*m) way to do it. Obviously we can be smarter
+    // about it, using a proper string-searching algorithm
+    haystack
+        .windows(needle.len().max(1))
+        .enumerate()
+        .filter(move |(_, window)| {
+            !needle.is_empty() && match fold {
+                Some(fold) => fold.eq(window, needle),
+                None => *window == needle,
+            }
+        })
+        .map(|(i, _)| i)
+}
+
+impl<'a> Match<'a, '_> {
+    /// Compare bytes with ASCII case folding, so only `A-Z` and `a-z`
+    /// are affected. Other bytes still have to match exactly.
+    pub fn ignore_case(mut self, ignore_case: bool) -> Self {
+        self.ignore_case = ignore_case;
+        self.finder = None;
+        self
+    }
+
+    /// Fold case with `fold` instead of ASCII when ignoring case.
+    pub fn fold(mut self, fold: CaseFold) -> Self {
+        self.fold = fold;
+        self.finder = None;
+        self
+    }
+
+    /// Search with an already built `finder` instead of building one
+    /// from the pattern and the case options.
+    pub(crate) fn finder(mut self, finder: &'a Finder) -> Self {
+        self.finder = Some(Cow::Borrowed(finder));
+        self
+    }
+
+    /// Only yield matches at offsets that are a multiple of `align`.
+    /// An `align` of 0 is treated as 1.
+    pub fn align(mut self, align: u64) -> Self {
+        self.align = align.max(1);
+        self
+    }
+
+    /// Only yield matches that aren't surrounded by ASCII alphanumeric
+    /// bytes, like `grep -w`.
+    pub fn word(mut self, word: bool) -> Self {
+        self.word = word;
+        self
+    }
+
+    /// Call `on_progress` with the number of bytes searched so far,
+    /// every `PROGRESS_INTERVAL` bytes.
+    pub fn on_progress(mut self, on_progress: &'a mut dyn FnMut(u64)) -> Self {
+        self.on_progress = Some(on_progress);
+        self
+    }
+}
+
+pub fn find_matches<'a, 'p>(
+    opened_file: &'a mut OpenedFile<'p>,
     pattern: &'a [u8]
-) -> Match<'a> {
-    Match::<'a>{ opened_file, pattern, offset: 0 }
+) -> Match<'a, 'p> {
+    Match {
+        opened_file,
+        pattern,
+        offset: 0,
+        ignore_case: false,
+        fold: CaseFold::Ascii,
+        align: 1,
+        word: false,
+        before: None,
+        on_progress: None,
+        finder: None,
+    }
+}
+
+/// Compile `pattern` as a byte-oriented regex. Unicode mode is off, so
+/// `.` and escapes like `\xff` match single raw bytes.
+pub fn compile_regex(pattern: &[u8], ignore_case: bool) -> Result<Regex, io::Error> {
+    let pattern = std::str::from_utf8(pattern).map_err(|e| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Regex isn't valid UTF-8: {}", e))
+    })?;
+    RegexBuilder::new(pattern)
+        .unicode(false)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+}
+
+/// Length of an opened file. Block devices report a size of 0 in their
+/// metadata, so for anything that isn't a regular file the length is
+/// found by seeking to the end and back instead. Return 0 if that's
+/// not possible either, e.g. for pipes.
+pub fn file_len(file: &File) -> Result<u64, io::Error> {
+    let metadata = file.metadata()?;
+    if metadata.is_file() {
+        return Ok(metadata.len());
+    }
+    let mut file = file;
+    let Ok(position) = file.stream_position() else {
+        return Ok(0);
+    };
+    let len = file.seek(SeekFrom::End(0)).unwrap_or(0);
+    file.seek(SeekFrom::Start(position))?;
+
+    Ok(len)
+}
+
+/// Whether a pattern of `pattern_len` bytes is too long to ever match
+/// in `file`, so searching it can be skipped. A length of 0 isn't
+/// trusted, pipes and files in `/proc` report it while having data.
+pub fn longer_than_file(pattern_len: usize, file: &File) -> Result<bool, io::Error> {
+    let len = file_len(file)?;
+
+    Ok(len > 0 && pattern_len as u64 > len)
+}
+
+/// Read the bytes of a match in `file` at `offset` spanning `len`
+/// bytes, together with up to `context` bytes on each side. The range
+/// is clamped to the start and the end of the file. Return the offset
+/// of the first returned byte and the bytes themselves.
+pub fn read_context(
+    file: &mut File,
+    offset: u64,
+    len: usize,
+    context: usize,
+) -> Result<(u64, Vec<u8>), io::Error> {
+    read_window(file, offset, len, context, context)
+}
+
+/// Same as `read_context()`, except `before` bytes are read before the
+/// match and `after` bytes after it, so the window spans
+/// `[offset - before, offset + len + after)` clamped to the file.
+pub fn read_window(
+    file: &mut File,
+    offset: u64,
+    len: usize,
+    before: usize,
+    after: usize,
+) -> Result<(u64, Vec<u8>), io::Error> {
+    let start = offset.saturating_sub(before as u64);
+    let end = offset
+        .saturating_add((len + after) as u64)
+        .min(file_len(file)?);
+    file.seek(SeekFrom::Start(start))?;
+    let mut buf = Vec::new();
+    (&mut *file).take(end.saturating_sub(start)).read_to_end(&mut buf)?;
+
+    Ok((start, buf))
+}
+
+/// Read the `\n` delimited line containing the match at `offset`
+/// spanning `len` bytes, without the newlines themselves. At most
+/// `window` bytes are read on each side of the match, so the line is
+/// cut short if there's no newline within them. Return the offset of
+/// the first returned byte and the bytes themselves.
+pub fn read_line(
+    file: &mut File,
+    offset: u64,
+    len: usize,
+    window: usize,
+) -> Result<(u64, Vec<u8>), io::Error> {
+    let (start, mut bytes) = read_context(file, offset, len, window)?;
+    let match_start = (offset - start) as usize;
+    let match_end = (match_start + len).min(bytes.len());
+    let line_start = bytes[..match_start]
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |i| i + 1);
+    let line_end = bytes[match_end..]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(bytes.len(), |i| match_end + i);
+    bytes.truncate(line_end);
+    bytes.drain(..line_start);
+
+    Ok((start + line_start as u64, bytes))
+}
+
+/// Escape the bytes which aren't printable ASCII, like `\x00` or `\t`.
+pub fn escape_bytes(bytes: &[u8]) -> String {
+    bytes.escape_ascii().to_string()
+}
+
+/// Turn a pattern mixing literal text with `\xHH` escapes, like
+/// `\x7fELF`, into bytes. `\\`, `\n`, `\r`, `\t` and `\0` are
+/// understood too, a backslash before anything else is kept as it is.
+/// Return an error if `\x` isn't followed by two hex digits.
+pub fn unescape(pattern: &str) -> Result<Vec<u8>, io::Error> {
+    let bytes = pattern.as_bytes();
+    let mut ret = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            ret.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let escaped = match bytes[i + 1] {
+            b'x' => {
+                let byte = pattern
+                    .get(i + 2..i + 4)
+                    .filter(|digits| digits.bytes().all(|b| b.is_ascii_hexdigit()))
+                    .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                    .ok_or_else(|| io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("\\x at {} in {} isn't followed by two hex digits", i, pattern),
+                    ))?;
+                ret.push(byte);
+                i += 4;
+                continue;
+            }
+            b'\\' => b'\\',
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'0' => b'\0',
+            _ => {
+                ret.push(b'\\');
+                i += 1;
+                continue;
+            }
+        };
+        ret.push(escaped);
+        i += 2;
+    }
+
+    Ok(ret)
+}
+
+/// Format `bytes` as a hexdump, 16 bytes per line, with offsets
+/// counted from `start`.
+pub fn hexdump(start: u64, bytes: &[u8]) -> String {
+    hexdump_marked(start, bytes, 0..0)
+}
+
+/// Same as `hexdump()`, except the bytes in the `marked` range of
+/// offsets are pointed at with `^^` in a line under them.
+pub fn hexdump_marked(start: u64, bytes: &[u8], marked: Range<u64>) -> String {
+    hexdump_colored(start, bytes, marked, false)
+}
+
+/// Same as `hexdump_marked()`, except the marked bytes are also
+/// painted red when `color` is set.
+pub fn hexdump_colored(start: u64, bytes: &[u8], marked: Range<u64>, color: bool) -> String {
+    let mut ret = String::new();
+    for (n, chunk) in bytes.chunks(16).enumerate() {
+        let line_start = start + n as u64 * 16;
+        let hex: Vec<String> = chunk
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let marked = marked.contains(&(line_start + i as u64));
+                paint(format!("{:02x}", b), RED, color && marked)
+            })
+            .collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        // Escape codes take no room on the screen, so they can't count
+        // towards the padding
+        let padding = 47 - (chunk.len() * 3 - 1);
+        ret += &format!("{:08x}: {}{:padding$}  {}\n", line_start, hex.join(" "), "", ascii);
+
+        let marks: Vec<&str> = (line_start..line_start + chunk.len() as u64)
+            .map(|offset| if marked.contains(&offset) { "^^" } else { "  " })
+            .collect();
+        if marks.contains(&"^^") {
+            ret += &format!("{:10}{}\n", "", marks.join(" ").trim_end());
+        }
+    }
+
+    ret
+}
+
+/// ANSI escape code painting the following text red.
+pub const RED: &str = "\x1b[31m";
+/// ANSI escape code painting the following text cyan.
+pub const CYAN: &str = "\x1b[36m";
+/// ANSI escape code going back to the default color.
+pub const RESET: &str = "\x1b[0m";
+
+/// Wrap `text` in the `color` escape code when `enabled` is set.
+pub fn paint<T: fmt::Display>(text: T, color: &str, enabled: bool) -> String {
+    if enabled {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Check the magic number at the start of a file. Return the name of
+/// the format if it's an ELF or a PE executable.
+pub fn sniff_executable(filename: &Path) -> Result<Option<&'static str>, io::Error> {
+    let mut magic = Vec::new();
+    File::open(filename)?.take(4).read_to_end(&mut magic)?;
+    if magic.starts_with(b"\x7fELF") {
+        Ok(Some("ELF"))
+    } else if magic.starts_with(b"MZ") {
+        Ok(Some("MZ/PE"))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Wrap `reader` in a gzip decoder, so the decompressed data is read
+/// from it. Concatenated gzip members are all decompressed, like
+/// `gunzip` does. Return an error if binu was built without the
+/// `decompress` feature.
+#[cfg(feature = "decompress")]
+pub fn decompressed<'a, R: BufRead + 'a>(reader: R) -> Result<Box<dyn Read + 'a>, io::Error> {
+    Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader)))
+}
+
+#[cfg(not(feature = "decompress"))]
+pub fn decompressed<'a, R: BufRead + 'a>(_reader: R) -> Result<Box<dyn Read + 'a>, io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Decompressing needs binu built with the decompress feature",
+    ))
+}
+
+/// Guess if an opened file is binary by looking for a NUL byte in the
+/// first few KiB of it. Nothing is consumed from the file.
+pub fn looks_binary(opened_file: &mut OpenedFile) -> Result<bool, io::Error> {
+    Ok(opened_file.file.fill_buf()?.contains(&0))
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Number of `open_file()` calls made by the current thread, so
+    /// tests can check that files aren't reopened needlessly.
+    pub static OPEN_FILE_CALLS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
 }
 
 pub fn open_file(filename: &Path) -> Result<OpenedFile<'_>, io::Error> {
+    #[cfg(test)]
+    OPEN_FILE_CALLS.with(|calls| calls.set(calls.get() + 1));
     match File::open(filename) {
         Ok(f) => Ok(OpenedFile {file: BufReader::new(f), path: filename}),
         Err(e) => {
@@ -77,40 +814,482 @@ pub fn open_file(filename: &Path) -> Result<OpenedFile<'_>, io::Error> {
     }
 }
 
+/// Return an error if `input` and `output` resolve to the same file.
+/// Opening the output would truncate the input before we're done
+/// reading it. An output that doesn't exist yet is always fine.
+pub fn ensure_different_files(input: &Path, output: &Path) -> Result<(), io::Error> {
+    let output = match output.canonicalize() {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if input.canonicalize()? == output {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Input and output are the same file {}, use in-place mode instead",
+                output.display(),
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Return an error if `output` already exists, so it isn't silently
+/// overwritten. Stdout, given as `-`, never counts as existing.
+pub fn ensure_no_clobber(output: &Path) -> Result<(), io::Error> {
+    if output != Path::new("-") && output.try_exists()? {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Output file {} already exists", output.display()),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Give `output` the permissions and the modification time of `input`,
+/// so e.g. a patched executable stays executable.
+pub fn copy_metadata(input: &File, output: &File) -> Result<(), io::Error> {
+    let metadata = input.metadata()?;
+    output.set_permissions(metadata.permissions())?;
+    output.set_modified(metadata.modified()?)?;
+
+    Ok(())
+}
+
+/// Write `path` through a temporary file next to it, which is only
+/// renamed over `path` once `write` succeeded and the data was synced.
+/// On an error the temporary file is removed and `path` is left as it
+/// was, so a failed command never leaves a half written output behind.
+/// A symlink is resolved first, so the file it points to is replaced
+/// and the symlink itself is kept. Only a missing path or a regular
+/// file is replaced like that, anything else, e.g. a FIFO or a device,
+/// is opened and written through directly.
+pub fn write_atomically<T, F>(path: &Path, write: F) -> Result<T, io::Error>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<T, io::Error>,
+{
+    let resolved;
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            resolved = fs::canonicalize(path)?;
+            resolved.as_path()
+        }
+        _ => path,
+    };
+    let replaceable = match fs::metadata(path) {
+        Ok(metadata) => metadata.is_file(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+        Err(e) => return Err(e),
+    };
+    if !replaceable {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let ret = write(&mut writer)?;
+        writer.flush()?;
+        return Ok(ret);
+    }
+
+    let temp_path = temp_path(path);
+    let result = File::create(&temp_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        let ret = write(&mut writer)?;
+        writer.into_inner()?.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(ret)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+
+    result
+}
+
+/// The hidden temporary path used while writing `output`.
+fn temp_path(output: &Path) -> PathBuf {
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(output.file_name().unwrap_or_default());
+    temp_name.push(".binu-tmp");
+    output.with_file_name(temp_name)
+}
+
 pub fn open_files<T: AsRef<Path>>(filenames: &[T]) -> OpenFiles<'_, T> {
     OpenFiles { files: filenames, nth: 0 }
 }
 
+/// Check if a directory entry is hidden, that is if its filename
+/// begins with a dot.
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .map(|name| name.as_encoded_bytes().starts_with(b"."))
+        .unwrap_or(false)
+}
+
+/// Glob patterns deciding which files `open_all_directories()` keeps,
+/// matched against the file name. With any include patterns a file
+/// has to match one of them, a file matching an exclude pattern is
+/// always left out.
+#[derive(Default, Debug, Clone)]
+pub struct NameFilter {
+    include: Vec<glob::Pattern>,
+    exclude: Vec<glob::Pattern>,
+}
+
+impl NameFilter {
+    /// Compile the patterns, return an error if one is invalid.
+    pub fn new<S: AsRef<str>>(include: &[S], exclude: &[S]) -> Result<Self, io::Error> {
+        let compile = |patterns: &[S]| {
+            patterns.iter().map(|pattern| {
+                glob::Pattern::new(pattern.as_ref()).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Invalid glob pattern {}: {}", pattern.as_ref(), e),
+                    )
+                })
+            }).collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(NameFilter { include: compile(include)?, exclude: compile(exclude)? })
+    }
+
+    fn keeps(&self, path: &Path) -> bool {
+        let Some(name) = path.file_name() else {
+            return true;
+        };
+        let name = name.to_string_lossy();
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(&name)))
+            && !self.exclude.iter().any(|p| p.matches(&name))
+    }
+}
+
 /// Open a directory recursively, getting all the files in the
 /// directory and its subdirectories. Doesn't work with symlinks. We
-/// make an assumption that the dir argument is a directory.
-fn open_recursively(dir: &Path) -> Result<Vec<PathBuf>, io::Error> {
+/// make an assumption that the dir argument is a directory. When
+/// `skip_hidden` is set, hidden files are skipped and hidden
+/// directories are not descended into. Only files kept by `filter`
+/// are returned. With a `max_depth` subdirectories are only descended
+/// into that many levels deep, 0 meaning only the files in `dir`.
+fn open_recursively(
+    dir: &Path,
+    skip_hidden: bool,
+    filter: &NameFilter,
+    max_depth: Option<usize>,
+) -> Result<Vec<PathBuf>, io::Error> {
+    // The order of read_dir() depends on the filesystem, sorting keeps
+    // the output the same everywhere
+    let mut entries = dir.read_dir()?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
     let mut ret = Vec::new();
-    for entry in dir.read_dir()? {
-        let entry = entry?;
+    for entry in entries {
+        if skip_hidden && is_hidden(&entry.path()) {
+            continue;
+        }
         let metadata = entry.metadata()?;
         if metadata.is_file() {
-            ret.push(entry.path());
-        } else if metadata.is_dir() {
-            ret.append(&mut open_recursively(&entry.path())?);
+            if filter.keeps(&entry.path()) {
+                ret.push(entry.path());
+            }
+        } else if metadata.is_dir() && max_depth != Some(0) {
+            let max_depth = max_depth.map(|depth| depth - 1);
+            ret.append(&mut open_recursively(&entry.path(), skip_hidden, filter, max_depth)?);
         }
     }
     
     Ok(ret)
 }
 
+/// Check if a path contains any of the glob metacharacters.
+fn is_glob(path: &Path) -> bool {
+    path.as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .any(|c| matches!(c, b'*' | b'?' | b'['))
+}
+
+/// Expand every path in a slice that looks like a glob pattern, so
+/// it behaves the same no matter what shell is used. Paths without
+/// glob metacharacters and existing paths, e.g. a file named
+/// `dump[1].bin`, are passed through unchanged. Return an error if a
+/// glob doesn't match anything or can't be expanded.
+pub fn expand_globs<T: AsRef<Path>>(paths: &[T]) -> Result<Vec<PathBuf>, io::Error> {
+    let mut ret = Vec::new();
+    for path in paths {
+        let path = path.as_ref();
+        if !is_glob(path) || path.try_exists()? {
+            ret.push(path.to_path_buf());
+            continue;
+        }
+
+        let pattern = path.to_string_lossy();
+        let entries = glob::glob(&pattern).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Invalid glob pattern {}: {}", pattern, e),
+            )
+        })?;
+        let mut matched = entries.collect::<Result<Vec<_>, _>>().map_err(io::Error::from)?;
+        if matched.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No files match the pattern {}", pattern),
+            ));
+        }
+        ret.append(&mut matched);
+    }
+
+    Ok(ret)
+}
+
 /// Same as `open_recursively()`, except we do it for every path in a
 /// slice. A path doesn't need to be a directory, it can be a file -
-/// then it's just added to the returned Vec.
-pub fn open_all_directories<T: AsRef<Path>>(paths: &[T]) -> Result<Vec<PathBuf>, io::Error> {
+/// then it's just added to the returned Vec if `filter` keeps it.
+pub fn open_all_directories<T: AsRef<Path>>(
+    paths: &[T],
+    skip_hidden: bool,
+    filter: &NameFilter,
+    max_depth: Option<usize>,
+) -> Result<Vec<PathBuf>, io::Error> {
     let mut ret = Vec::new();
     for path in paths {
         if path.as_ref().is_dir() {
-            ret.append(&mut open_recursively(path.as_ref())?);
-        } else if path.as_ref().is_file() {
+            ret.append(&mut open_recursively(path.as_ref(), skip_hidden, filter, max_depth)?);
+        } else if path.as_ref().is_file() && filter.keeps(path.as_ref()) {
             ret.push(path.as_ref().to_path_buf());
         }
     }
     
     Ok(ret)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn open_all_directories_hidden() {
+        let dirs = vec!["test_files/hidden_dir"];
+        let mut res = open_all_directories(&dirs, false, &NameFilter::default(), None).unwrap();
+        res.sort();
+        assert_eq!(res, vec![
+            PathBuf::from("test_files/hidden_dir/.config/file"),
+            PathBuf::from("test_files/hidden_dir/.hidden"),
+            PathBuf::from("test_files/hidden_dir/visible"),
+        ]);
+
+        let res = open_all_directories(&dirs, true, &NameFilter::default(), None).unwrap();
+        assert_eq!(res, vec![PathBuf::from("test_files/hidden_dir/visible")]);
+    }
+
+    #[test]
+    fn open_all_directories_sorted() {
+        let res = open_all_directories(&["test_files/sorted_dir"], false, &NameFilter::default(), None).unwrap();
+        assert_eq!(res, vec![
+            PathBuf::from("test_files/sorted_dir/a"),
+            PathBuf::from("test_files/sorted_dir/b"),
+            PathBuf::from("test_files/sorted_dir/m/1"),
+            PathBuf::from("test_files/sorted_dir/m/2"),
+            PathBuf::from("test_files/sorted_dir/z"),
+        ]);
+    }
+
+    #[test]
+    fn open_all_directories_max_depth() {
+        let dirs = ["test_files/nested_dir"];
+        let filter = NameFilter::default();
+        let res = open_all_directories(&dirs, false, &filter, Some(0)).unwrap();
+        assert_eq!(res, vec![PathBuf::from("test_files/nested_dir/top")]);
+        let res = open_all_directories(&dirs, false, &filter, Some(1)).unwrap();
+        assert_eq!(res, vec![
+            PathBuf::from("test_files/nested_dir/one/mid"),
+            PathBuf::from("test_files/nested_dir/top"),
+        ]);
+        let res = open_all_directories(&dirs, false, &filter, None).unwrap();
+        assert_eq!(res, vec![
+            PathBuf::from("test_files/nested_dir/one/mid"),
+            PathBuf::from("test_files/nested_dir/one/two/deep"),
+            PathBuf::from("test_files/nested_dir/top"),
+        ]);
+    }
+
+    #[test]
+    fn case_fold_test() {
+        assert!(CaseFold::Ascii.eq(b"Caf\xe9", b"cAf\xe9"));
+        assert!(!CaseFold::Ascii.eq(b"caf\xc9", b"caf\xe9"));
+        assert!(CaseFold::Latin1.eq(b"CAF\xc9", b"caf\xe9"));
+        assert!(CaseFold::Latin1.eq(b"\xc0\xde", b"\xe0\xfe"));
+        // Multiplication and division signs aren't a letter pair
+        assert!(!CaseFold::Latin1.eq(b"\xd7", b"\xf7"));
+        assert!(!CaseFold::Latin1.eq(b"ab", b"abc"));
+    }
+
+    #[test]
+    fn unescape_test() {
+        assert_eq!(unescape("\\x7fELF").unwrap(), b"\x7fELF");
+        assert_eq!(unescape("a\\x00\\xFFb\\n\\\\x").unwrap(), b"a\x00\xffb\n\\x");
+        assert_eq!(unescape("20% \\d\\").unwrap(), b"20% \\d\\");
+        assert_eq!(unescape("\\x4").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(unescape("\\xzz").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let pattern = unescape("\\x7fELF").unwrap();
+        let mut file = open_file(Path::new("test_files/file_elf")).unwrap();
+        let offsets: Vec<_> = find_matches(&mut file, &pattern).map(|found| found.offset).collect();
+        assert_eq!(offsets, vec![0]);
+    }
+
+    #[test]
+    fn find_in_slice_test() {
+        for (filename, pattern) in [
+            ("test_files/file_one", &b"nya"[..]),
+            ("test_files/file_two", b"20%"),
+            ("test_files/file_zeros", b"\x00\x00\x00"),
+            ("test_files/file_overlap_odd", b"aa"),
+        ] {
+            let data = fs::read(filename).unwrap();
+            let mut file = open_file(Path::new(filename)).unwrap();
+            let expected: Vec<_> = find_matches(&mut file, pattern)
+                .map(|found| found.offset as usize)
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(find_in_slice(&data, pattern).collect::<Vec<_>>(), expected);
+        }
+        assert_eq!(find_in_slice(b"aaa", b"").count(), 0);
+        assert_eq!(find_in_slice(b"aa", b"aaa").count(), 0);
+    }
+
+    #[test]
+    fn finder_test() {
+        let haystack = b"Nyanyame NYANYA caf\xc9 caf\xe9 aaaa";
+        for (pattern, fold) in [
+            (&b"nya"[..], None),
+            (b"NYA", Some(CaseFold::Ascii)),
+            (b"caf\xe9", Some(CaseFold::Latin1)),
+            (b"aa", None),
+        ] {
+            let finder = Finder::new(pattern, fold);
+            let expected: Vec<_> = slice_matches(haystack, pattern, fold).collect();
+            assert!(!expected.is_empty());
+            assert_eq!(finder.find_iter(haystack).collect::<Vec<_>>(), expected);
+        }
+        assert_eq!(Finder::new(b"", None).find_iter(b"aaa").count(), 0);
+    }
+
+    #[test]
+    fn file_len_test() {
+        let file = File::open("test_files/file_two").unwrap();
+        assert_eq!(file_len(&file).unwrap(), 127);
+        let file = File::open("/dev/null").unwrap();
+        assert_eq!(file_len(&file).unwrap(), 0);
+    }
+
+    #[test]
+    fn open_files_error_test() {
+        let paths = ["test_files/file_one", "test_files/file_nonexistent", "test_files/file_two"];
+        let res: Vec<_> = open_files(&paths).collect();
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].as_ref().unwrap().path, Path::new("test_files/file_one"));
+        assert_eq!(res[1].as_ref().unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(res[2].as_ref().unwrap().path, Path::new("test_files/file_two"));
+    }
+
+    #[test]
+    fn expand_globs_test() {
+        let paths = vec!["test_files/file_[ot][nw][eo]", "test_files/file_three"];
+        let res = expand_globs(&paths).unwrap();
+        assert_eq!(res, vec![
+            PathBuf::from("test_files/file_one"),
+            PathBuf::from("test_files/file_two"),
+            PathBuf::from("test_files/file_three"),
+        ]);
+
+        let paths = vec!["test_files/*.nothing"];
+        assert_eq!(expand_globs(&paths).unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        // An existing file is taken literally even with brackets in its name
+        let paths = vec!["test_files/file_[1].bin"];
+        assert_eq!(expand_globs(&paths).unwrap(), vec![PathBuf::from("test_files/file_[1].bin")]);
+    }
+
+    #[test]
+    fn read_context_test() {
+        let file = &mut File::open("test_files/file_one").unwrap();
+        let (start, bytes) = read_context(file, 9, 3, 2).unwrap();
+        assert_eq!(start, 7);
+        assert_eq!(bytes, b"e nyany");
+
+        let (start, bytes) = read_context(file, 3, 3, 8).unwrap();
+        assert_eq!(start, 0);
+        assert_eq!(bytes, b"Nyanyame nyany");
+
+        let (start, bytes) = read_context(file, 109, 3, 8).unwrap();
+        assert_eq!(start, 101);
+        assert_eq!(bytes, b"te nyaganyagame");
+    }
+
+    #[test]
+    fn hexdump_test() {
+        assert_eq!(
+            hexdump(16, b"\x00nya\xff"),
+            "00000010: 00 6e 79 61 ff                                   .nya.\n",
+        );
+    }
+
+    #[test]
+    fn hexdump_marked_test() {
+        let dump = hexdump_marked(14, b"\x01\x02\x03\x04\x05", 15..17);
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("0000000e: 01 02 03 04 05  "));
+        assert_eq!(lines[1], "             ^^ ^^");
+    }
+
+    #[test]
+    fn looks_binary_test() {
+        let mut text = open_file(Path::new("test_files/file_one")).unwrap();
+        assert!(!looks_binary(&mut text).unwrap());
+        let mut binary = open_file(Path::new("test_files/file_three")).unwrap();
+        assert!(looks_binary(&mut binary).unwrap());
+        // Classifying doesn't consume anything
+        let mut buf = Vec::new();
+        binary.file.read_to_end(&mut buf).unwrap();
+        assert_eq!(buf.len(), 5);
+    }
+
+    #[test]
+    fn write_atomically_test() {
+        let output = Path::new("test_files/file_write_atomically");
+        fs::write(output, b"original").unwrap();
+
+        let res = write_atomically(output, |writer| {
+            writer.write_all(&[0x41; 1 << 16])?;
+            Err::<(), _>(io::Error::other("failed mid-write"))
+        });
+        assert_eq!(res.unwrap_err().to_string(), "failed mid-write");
+        assert_eq!(fs::read(output).unwrap(), b"original");
+        assert!(!temp_path(output).exists());
+
+        write_atomically(output, |writer| writer.write_all(b"replaced")).unwrap();
+        assert_eq!(fs::read(output).unwrap(), b"replaced");
+        assert!(!temp_path(output).exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomically_symlink_test() {
+        let target = Path::new("test_files/file_write_atomically_target");
+        let link = Path::new("test_files/file_write_atomically_link");
+        fs::write(target, b"original").unwrap();
+        let _ = fs::remove_file(link);
+        std::os::unix::fs::symlink("file_write_atomically_target", link).unwrap();
+
+        // Like in place mode, the input is only read while writing
+        write_atomically(link, |writer| {
+            let original = fs::read(link)?;
+            writer.write_all(&original.to_ascii_uppercase())
+        }).unwrap();
+        assert!(fs::symlink_metadata(link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(target).unwrap(), b"ORIGINAL");
+        assert!(!temp_path(link).exists());
+        assert!(!temp_path(target).exists());
+    }
+}